@@ -1,12 +1,15 @@
-use serde::{Deserialize, Serialize};
-use crate::blockchain::Blockchain;
+use serde::Serialize;
+use crate::blockchain::{Account, Blockchain};
 use crate::miner::Handle as MinerHandle;
 use crate::network::server::Handle as NetworkServerHandle;
 use crate::network::message::Message;
-use crate::types::hash::Hashable;
-use crate::types::transaction::SignedTransaction;
+use crate::types::hash::{Hashable, H256};
+use crate::types::transaction::{SignedTransaction, TxLock};
 use crate::types::address::Address;
-use crate::types::mempool::Mempool; // 引入 Mempool
+use crate::types::mempool::{Mempool, TxStatus}; // 引入 Mempool
+use crate::types::fee_estimator::FeeEstimator;
+use crate::types::proof::AccountProof;
+use crate::types::block::BlockHeader;
 
 use log::{info, error, warn};
 use std::collections::HashMap;
@@ -15,6 +18,10 @@ use std::thread;
 use tiny_http::{Header, Response, Server as HTTPServer, Request, Method};
 use url::Url;
 use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `/fee/estimate` 未指定 target_blocks 时的默认值
+pub const DEFAULT_FEE_TARGET_BLOCKS: u64 = 3;
 
 pub struct Server {
     handle: HTTPServer,
@@ -22,6 +29,7 @@ pub struct Server {
     network: NetworkServerHandle,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>, // Server 需要访问 Mempool 插入交易
+    fee_estimator: Arc<Mutex<FeeEstimator>>,
 }
 
 #[derive(Serialize)]
@@ -33,10 +41,83 @@ struct ApiResponse<T> {
 }
 
 #[derive(Serialize)]
-struct AccountInfo {
+pub(crate) struct ChainInfo {
+    pub(crate) chain_id: u64,
+    pub(crate) tip: String,
+    pub(crate) height: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct AccountInfo {
+    pub(crate) address: String,
+    pub(crate) nonce: u64,
+    pub(crate) balance: u64,
+}
+
+#[derive(Serialize)]
+struct AccountChangeInfo {
     address: String,
+    before: Option<Account>,
+    after: Option<Account>,
+}
+
+/// 区块 `from` 到 `to` 之间变化的账户，按地址排序，None 表示账户不存在
+#[derive(Serialize)]
+struct StateDiffInfo {
+    from: String,
+    from_height: u64,
+    to: String,
+    to_height: u64,
+    changes: Vec<AccountChangeInfo>,
+}
+
+/// 账户证明及其所在区块的区块头，状态根取自区块头
+#[derive(Serialize)]
+struct AccountProofInfo {
+    block_hash: String,
+    height: u64,
+    header: BlockHeader,
+    proof: AccountProof,
+}
+
+#[derive(Serialize)]
+struct PendingTxInfo {
+    hash: String,
+    sender: String,
+    to: String,
     nonce: u64,
-    balance: u64,
+    value: u64,
+    /// 批量交易的输出数，普通交易为 0
+    outputs: usize,
+    lock: Option<TxLock>,
+    gas_price: u64,
+    gas_limit: u64,
+    fee: u64,
+}
+
+impl From<&SignedTransaction> for PendingTxInfo {
+    fn from(tx: &SignedTransaction) -> Self {
+        Self {
+            hash: tx.hash().to_string(),
+            sender: tx.sender_address().to_string(),
+            to: tx.transaction.to.to_string(),
+            nonce: tx.transaction.nonce,
+            value: tx.transaction.total_value().unwrap_or(u64::MAX),
+            outputs: tx.transaction.outputs.len(),
+            lock: tx.transaction.lock,
+            gas_price: tx.transaction.gas_price,
+            gas_limit: tx.transaction.gas_limit,
+            fee: tx.transaction.fee(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PendingTxStatus {
+    #[serde(flatten)]
+    transaction: PendingTxInfo,
+    #[serde(flatten)]
+    status: TxStatus,
 }
 
 impl Server {
@@ -46,14 +127,16 @@ impl Server {
         network: &NetworkServerHandle,
         blockchain: &Arc<Mutex<Blockchain>>,
         mempool: &Arc<Mutex<Mempool>>, // 传入 Mempool
+        fee_estimator: &Arc<Mutex<FeeEstimator>>,
     ) {
-        let handle = HTTPServer::http(&addr).unwrap();
+        let handle = HTTPServer::http(addr).unwrap();
         let server = Self {
             handle,
             miner: miner.clone(),
             network: network.clone(),
             blockchain: Arc::clone(blockchain),
             mempool: Arc::clone(mempool),
+            fee_estimator: Arc::clone(fee_estimator),
         };
 
         info!("API Server started at http://{}", addr);
@@ -64,8 +147,9 @@ impl Server {
                 let network = server.network.clone();
                 let blockchain = server.blockchain.clone();
                 let mempool = server.mempool.clone();
+                let fee_estimator = server.fee_estimator.clone();
 
-                let response = handle_request(&mut req, &miner, &network, &blockchain, &mempool, addr);
+                let response = handle_request(&mut req, &miner, &network, &blockchain, &mempool, &fee_estimator, addr);
                 if let Err(e) = req.respond(response) {
                     error!("Failed to send response: {}", e);
                 }
@@ -80,6 +164,7 @@ fn handle_request(
     network: &NetworkServerHandle,
     blockchain: &Arc<Mutex<Blockchain>>,
     mempool: &Arc<Mutex<Mempool>>,
+    fee_estimator: &Arc<Mutex<FeeEstimator>>,
     addr: std::net::SocketAddr
 ) -> Response<std::io::Cursor<Vec<u8>>> {
     
@@ -117,6 +202,17 @@ fn handle_request(
         }
 
         // --- Blockchain ---
+        // 客户端签名交易前需要知道链 ID
+        (Method::Get, "/blockchain/info") => {
+            let chain = blockchain.lock().unwrap();
+            let tip = chain.tip();
+            let info = ChainInfo {
+                chain_id: chain.chain_id,
+                tip: tip.to_string(),
+                height: chain.get_height(&tip),
+            };
+            json_response(true, "Chain info", Some(info))
+        }
         (Method::Get, "/blockchain/longest-chain") => {
             let chain = blockchain.lock().unwrap();
             let v = chain.all_blocks_in_longest_chain();
@@ -129,17 +225,10 @@ fn handle_request(
                 Some(h) => h,
                 None => return json_response::<()>(false, "Missing hash parameter", None),
             };
-
-            let hash_vec = match hex::decode(hash_str) {
-                Ok(v) => v,
-                Err(_) => return json_response::<()>(false, "Invalid hex format", None),
+            let h256 = match parse_hash(hash_str) {
+                Ok(h) => h,
+                Err(e) => return json_response::<()>(false, e, None),
             };
-            
-            let hash_array: [u8; 32] = match hash_vec.try_into() {
-                Ok(a) => a,
-                Err(_) => return json_response::<()>(false, "Hash must be 32 bytes", None),
-            };
-            let h256 = crate::types::hash::H256::from(hash_array);
 
             let chain = blockchain.lock().unwrap();
             match chain.get_block(&h256) {
@@ -156,25 +245,68 @@ fn handle_request(
                 None => return json_response::<()>(false, "Missing address parameter", None),
             };
 
-            let recipient_bytes = match hex::decode(addr_str) {
-                Ok(b) => b,
-                Err(_) => return json_response::<()>(false, "Invalid hex address", None),
-            };
-            let byte_array: [u8; 20] = match recipient_bytes.try_into() {
-                Ok(arr) => arr,
-                Err(_) => return json_response::<()>(false, "Address must be 20 bytes", None),
+            let address = match parse_address(addr_str) {
+                Ok(a) => a,
+                Err(e) => return json_response::<()>(false, &e, None),
             };
-            let address = Address::from(byte_array);
 
+            // 可选的 block 参数 (哈希或主链高度) 查询历史状态
             let chain = blockchain.lock().unwrap();
-            let account = chain.get_account(&address);
-            
+            let block_hash = match params.get("block").map(|b| resolve_block(&chain, b)).transpose() {
+                Ok(h) => h.unwrap_or_else(|| chain.tip()),
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+            let height = chain.get_height(&block_hash);
+            let state = match chain.state_at(&block_hash) {
+                Ok(s) => s,
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+            drop(chain);
+            let account = match state.get(&address) {
+                Ok(account) => account.unwrap_or_default(),
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+
             let info = AccountInfo {
                 address: addr_str.to_string(),
                 nonce: account.nonce,
                 balance: account.balance,
             };
-            json_response(true, "Account info", Some(info))
+            let message = format!("Account info at block {} (height {})", block_hash, height);
+            json_response(true, &message, Some(info))
+        }
+
+        // 账户状态证明，默认使用 tip 的状态
+        (Method::Get, "/blockchain/account/proof") => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let address = match params.get("address").map(|a| parse_address(a)) {
+                Some(Ok(a)) => a,
+                Some(Err(e)) => return json_response::<()>(false, &e, None),
+                None => return json_response::<()>(false, "Missing address parameter", None),
+            };
+
+            // 可选的 block 参数: 哈希或主链高度
+            let chain = blockchain.lock().unwrap();
+            let block_hash = match params.get("block").map(|b| resolve_block(&chain, b)).transpose() {
+                Ok(h) => h.unwrap_or_else(|| chain.tip()),
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+            let block = match chain.get_block(&block_hash) {
+                Some(b) => b,
+                None => return json_response::<()>(false, "Block not found", None),
+            };
+            match chain.account_proof(&address, &block_hash) {
+                Some(proof) => {
+                    let info = AccountProofInfo {
+                        block_hash: block_hash.to_string(),
+                        height: chain.get_height(&block_hash),
+                        header: block.header(),
+                        proof,
+                    };
+                    json_response(true, "Account proof", Some(info))
+                }
+                None => json_response::<()>(false, "State of this block is not available", None),
+            }
         }
 
         // 提交已签名的交易
@@ -188,10 +320,14 @@ fn handle_request(
                 Err(e) => return json_response::<()>(false, &format!("Invalid Transaction JSON: {}", e), None),
             };
 
-            // 验证签名 (Server 端的安全防线)
-            if !tx.verify() {
-                warn!("Received transaction with invalid signature");
-                return json_response::<()>(false, "Invalid signature", None);
+            // 验证签名与 gas (Server 端的安全防线)
+            let (chain_id, height) = {
+                let chain = blockchain.lock().unwrap();
+                (chain.chain_id, chain.get_height(&chain.tip()))
+            };
+            if let Err(e) = tx.validate(chain_id) {
+                warn!("Received invalid transaction: {}", e);
+                return json_response::<()>(false, &e, None);
             }
 
             let hash = tx.hash();
@@ -201,6 +337,7 @@ fn handle_request(
                 let mut mp = mempool.lock().unwrap();
                 mp.insert(tx);
             }
+            fee_estimator.lock().unwrap().observe_transaction(hash, height);
 
             // 广播给 P2P 网络
             network.broadcast(Message::NewTransactionHashes(vec![hash]));
@@ -208,21 +345,188 @@ fn handle_request(
             json_response(true, "Transaction submitted", Some(hash.to_string()))
         }
 
-        _ => {
-            let payload = ApiResponse::<()> {
-                success: false,
-                message: "Endpoint not found".to_string(),
-                data: None,
+        (Method::Get, "/transaction/receipt") => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let hash = match params.get("hash").map(|h| parse_hash(h)) {
+                Some(Ok(h)) => h,
+                Some(Err(e)) => return json_response::<()>(false, e, None),
+                None => return json_response::<()>(false, "Missing hash parameter", None),
             };
-            let data = serde_json::to_string_pretty(&payload).unwrap();
-            Response::from_string(data)
-                .with_header("Content-Type: application/json".parse::<Header>().unwrap())
-                .with_status_code(404)
+
+            let chain = blockchain.lock().unwrap();
+            match chain.storage.get_receipt(&hash) {
+                Some(receipt) => json_response(true, "Receipt found", Some(receipt)),
+                None => json_response::<()>(false, "Receipt not found", None),
+            }
         }
+
+        (Method::Get, "/transaction/proof") => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let hash = match params.get("hash").map(|h| parse_hash(h)) {
+                Some(Ok(h)) => h,
+                Some(Err(e)) => return json_response::<()>(false, e, None),
+                None => return json_response::<()>(false, "Missing hash parameter", None),
+            };
+
+            let proof = blockchain.lock().unwrap().transaction_proof(&hash);
+            match proof {
+                Some(proof) => json_response(true, "Inclusion proof", Some(proof)),
+                None => json_response::<()>(false, "Transaction not found in any block", None),
+            }
+        }
+
+        // --- Fee ---
+        (Method::Get, "/fee/estimate") => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let target_blocks = match params.get("target_blocks").map(|v| v.parse::<u64>()).transpose() {
+                Ok(v) => v.unwrap_or(DEFAULT_FEE_TARGET_BLOCKS),
+                Err(_) => return json_response::<()>(false, "Invalid target_blocks", None),
+            };
+
+            let estimator = fee_estimator.lock().unwrap();
+            let estimate = estimator.estimate(target_blocks, &mempool.lock().unwrap());
+            json_response(true, "Fee estimate", Some(estimate))
+        }
+
+        // --- Mempool ---
+        (Method::Get, "/mempool/transactions") => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let sender = match params.get("sender").map(|a| parse_address(a)).transpose() {
+                Ok(a) => a,
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+            let recipient = match params.get("recipient").map(|a| parse_address(a)).transpose() {
+                Ok(a) => a,
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+            let min_fee = match params.get("min_fee").map(|v| v.parse::<u64>()).transpose() {
+                Ok(v) => v.unwrap_or(0),
+                Err(_) => return json_response::<()>(false, "Invalid min_fee", None),
+            };
+
+            let mp = mempool.lock().unwrap();
+            let txs: Vec<PendingTxInfo> = mp.filter(sender.as_ref(), recipient.as_ref(), min_fee)
+                .iter()
+                .map(PendingTxInfo::from)
+                .collect();
+            json_response(true, "Pending transactions", Some(txs))
+        }
+        (Method::Get, "/mempool/transaction") => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let hash = match params.get("hash").map(|h| parse_hash(h)) {
+                Some(Ok(h)) => h,
+                Some(Err(e)) => return json_response::<()>(false, e, None),
+                None => return json_response::<()>(false, "Missing hash parameter", None),
+            };
+
+            let tx = match mempool.lock().unwrap().get_transaction(&hash) {
+                Some(tx) => tx,
+                None => return json_response::<()>(false, "Transaction not in mempool", None),
+            };
+            let (account, next_height) = {
+                let chain = blockchain.lock().unwrap();
+                (chain.get_account(&tx.sender_address()), chain.get_height(&chain.tip()) + 1)
+            };
+            let account_nonce = match account {
+                Ok(account) => account.nonce,
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+
+            // 两次加锁之间交易可能已被打包或移除
+            match mempool.lock().unwrap().status(&hash, account_nonce, next_height, now) {
+                Some(status) => {
+                    let info = PendingTxStatus { transaction: PendingTxInfo::from(&tx), status };
+                    json_response(true, "Pending transaction", Some(info))
+                }
+                None => json_response::<()>(false, "Transaction not in mempool", None),
+            }
+        }
+        (Method::Get, "/mempool/stats") => {
+            let stats = mempool.lock().unwrap().stats();
+            json_response(true, "Mempool stats", Some(stats))
+        }
+        // 两个区块之间发生变化的账户，to 默认为 tip
+        (Method::Get, "/state/diff") => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let chain = blockchain.lock().unwrap();
+            let from = match params.get("from").map(|b| resolve_block(&chain, b)) {
+                Some(Ok(h)) => h,
+                Some(Err(e)) => return json_response::<()>(false, &e, None),
+                None => return json_response::<()>(false, "Missing from parameter", None),
+            };
+            let to = match params.get("to").map(|b| resolve_block(&chain, b)).transpose() {
+                Ok(h) => h.unwrap_or_else(|| chain.tip()),
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+            let states = chain.state_at(&from).and_then(|before| chain.state_at(&to).map(|after| (before, after)));
+            let (from_height, to_height) = (chain.get_height(&from), chain.get_height(&to));
+            drop(chain);
+
+            let changes = match states.and_then(|(before, after)| before.diff(&after)) {
+                Ok(changes) => changes,
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+            let info = StateDiffInfo {
+                from: from.to_string(),
+                from_height,
+                to: to.to_string(),
+                to_height,
+                changes: changes.into_iter()
+                    .map(|c| AccountChangeInfo { address: c.address.to_string(), before: c.before, after: c.after })
+                    .collect(),
+            };
+            let message = format!("{} accounts changed", info.changes.len());
+            json_response(true, &message, Some(info))
+        }
+        (Method::Get, "/state/cache") => {
+            let stats = blockchain.lock().unwrap().storage.state_cache_stats();
+            json_response(true, "State node cache stats", Some(stats))
+        }
+        (Method::Post, "/mempool/remove") => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let hash = match params.get("hash").map(|h| parse_hash(h)) {
+                Some(Ok(h)) => h,
+                Some(Err(e)) => return json_response::<()>(false, e, None),
+                None => return json_response::<()>(false, "Missing hash parameter", None),
+            };
+
+            match mempool.lock().unwrap().remove(&hash) {
+                Some(_) => {
+                    info!("Transaction {} removed from mempool via API", hash);
+                    json_response(true, "Transaction removed", Some(hash.to_string()))
+                }
+                None => json_response::<()>(false, "Transaction not in mempool", None),
+            }
+        }
+
+        _ => not_found(),
+    }
+}
+
+pub(crate) fn not_found() -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response::<()>(false, "Endpoint not found", None).with_status_code(404)
+}
+
+/// 区块参数: 64 位十六进制哈希，或最长链上的高度
+fn resolve_block(chain: &Blockchain, s: &str) -> Result<H256, String> {
+    if let Ok(height) = s.parse::<u64>() {
+        return chain.block_at_height(height).ok_or_else(|| format!("No block at height {}", height));
     }
+    parse_hash(s).map_err(|e| e.to_string())
+}
+
+pub(crate) fn parse_hash(s: &str) -> Result<H256, &'static str> {
+    let bytes = hex::decode(s).map_err(|_| "Invalid hex format")?;
+    let array: [u8; 32] = bytes.try_into().map_err(|_| "Hash must be 32 bytes")?;
+    Ok(H256::from(array))
+}
+
+pub(crate) fn parse_address(s: &str) -> Result<Address, String> {
+    s.parse()
 }
 
-fn json_response<T: Serialize>(success: bool, message: &str, data: Option<T>) -> Response<std::io::Cursor<Vec<u8>>> {
+pub(crate) fn json_response<T: Serialize>(success: bool, message: &str, data: Option<T>) -> Response<std::io::Cursor<Vec<u8>>> {
     let payload = ApiResponse {
         success,
         message: message.to_string(),
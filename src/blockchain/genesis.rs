@@ -0,0 +1,77 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::path::Path;
+use crate::types::address::Address;
+use crate::types::hash::H256;
+
+/// 默认网络的链 ID
+pub const DEFAULT_CHAIN_ID: u64 = 1;
+
+/// 预置资金的 God 账户 (见 README)
+const GOD_ADDRESS: &str = "67d39da22d106b686c4f301b6f357600d28fc104";
+const GOD_BALANCE: u64 = 100_000_000;
+
+/// 创世配置: 链 ID、初始难度与初始账户余额
+#[derive(Debug, Clone)]
+pub struct GenesisConfig {
+    /// 写入每笔交易的签名内容，防止交易在其他网络上被重放
+    pub chain_id: u64,
+    pub difficulty: H256,
+    pub allocations: Vec<(Address, u64)>,
+}
+
+/// 创世配置文件的 JSON 格式，地址和难度使用 hex 字符串
+#[derive(Deserialize)]
+struct GenesisFile {
+    chain_id: u64,
+    difficulty: Option<String>,
+    #[serde(default)]
+    alloc: BTreeMap<String, u64>,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        let mut difficulty_bytes = [255u8; 32];
+        for byte in difficulty_bytes.iter_mut().take(3) {
+            *byte = 0;
+        }
+        Self {
+            chain_id: DEFAULT_CHAIN_ID,
+            difficulty: H256::from(difficulty_bytes),
+            allocations: vec![(parse_address(GOD_ADDRESS).unwrap(), GOD_BALANCE)],
+        }
+    }
+}
+
+impl GenesisConfig {
+    /// 从 JSON 文件加载，例如
+    /// `{"chain_id": 2, "difficulty": "0000ffff...", "alloc": {"67d3...c104": 1000}}`
+    /// 未指定的难度使用默认值
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: GenesisFile = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+
+        let difficulty = match file.difficulty {
+            Some(hex_str) => {
+                let bytes = hex::decode(&hex_str).map_err(|_| "Invalid difficulty hex".to_string())?;
+                let array: [u8; 32] = bytes.try_into().map_err(|_| "Difficulty must be 32 bytes".to_string())?;
+                H256::from(array)
+            }
+            None => Self::default().difficulty,
+        };
+        let allocations = file.alloc.iter()
+            .map(|(addr, balance)| Ok((parse_address(addr)?, *balance)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            chain_id: file.chain_id,
+            difficulty,
+            allocations,
+        })
+    }
+}
+
+fn parse_address(s: &str) -> Result<Address, String> {
+    s.parse()
+}
@@ -1,24 +1,38 @@
-use crate::types::block::Block;
+pub mod genesis;
+pub mod pruner;
+pub mod snapshot;
+
+use crate::types::block::{Block, BlockHeader};
+use crate::types::proof::{AccountProof, TransactionProof};
+use crate::blockchain::genesis::GenesisConfig;
+use crate::blockchain::snapshot::SnapshotManifest;
 use std::collections::HashMap;
 use crate::types::hash::{H256, Hashable};
 use crate::types::address::Address;
-use crate::types::state_trie::{StateTrie, Node}; // 确保引入 Node
+use crate::types::state_trie::{compress_state_nodes, StateTrie, Node, STATE_NODE_FORMAT}; // 确保引入 Node
 use crate::database::Storage;
 use std::sync::Arc;
-use log::{info, error, warn, debug};
-use std::hash::Hash;
+use log::{info, warn};
 use ring::digest;
-use std::convert::TryInto;
-use crate::miner::BLOCK_REWARD;
+use crate::miner::{now_millis, BLOCK_REWARD, BLOCK_GAS_LIMIT, MAX_BLOCK_SIZE, MAX_BLOCK_TRANSACTIONS, MAX_FUTURE_BLOCK_TIME};
+use crate::types::receipt::Receipt;
+use crate::types::transaction::SignedTransaction;
 use crate::types::merkle::MerkleTree;
 
 // Account 定义保持不变
-#[derive(Clone, Debug, Default, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, Default, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Account {
     pub nonce: u64,
     pub balance: u64,
 }
 
+impl Account {
+    /// nonce 和余额都为 0 的账户与不存在的账户无法区分，不保存在状态树中
+    pub fn is_empty(&self) -> bool {
+        self.nonce == 0 && self.balance == 0
+    }
+}
+
 impl Hashable for Account {
     fn hash(&self) -> H256 {
         let mut bytes = Vec::new();
@@ -31,42 +45,180 @@ impl Hashable for Account {
     }
 }
 
-pub struct Blockchain {
-    pub tip: H256,
-    pub storage: Arc<Storage>,
+/// 执行区块得到的新状态。持有父状态的读取登记，
+/// 提交之前状态回收不会删除新状态引用的父状态节点
+#[derive(Clone)]
+pub struct ExecutedState {
+    pub state_root: H256,
+    /// 需要持久化的新节点
+    pub new_nodes: HashMap<H256, Node>,
+    _parent: StateTrie,
 }
 
-impl Blockchain {
-    pub fn new(path: &str) -> Self {
-        let storage = Arc::new(Storage::new(path));
+impl std::fmt::Debug for ExecutedState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ExecutedState")
+            .field("state_root", &self.state_root)
+            .field("new_nodes", &self.new_nodes.len())
+            .finish()
+    }
+}
+
+/// 在父状态之上依次执行一个区块中的交易。所有余额与手续费运算都做溢出检查，
+/// 任意一步失败时返回错误且不修改已累积的状态
+pub struct StateTransition<'a> {
+    state: &'a StateTrie,
+    updates: HashMap<Address, Account>,
+    total_fee: u64,
+}
 
-        if let Some(tip) = storage.get_item(&storage.meta, b"tip") {
-            info!("Restoring blockchain from DB: {}", path);
-            return Self { tip, storage };
+impl<'a> StateTransition<'a> {
+    pub fn new(state: &'a StateTrie) -> Self {
+        Self {
+            state,
+            updates: HashMap::new(),
+            total_fee: 0,
         }
+    }
 
-        info!("Initializing Genesis State at {}", path);
+    /// 读取账户: 优先使用本区块内已修改的值，否则从父状态读取
+    pub fn account(&self, addr: &Address) -> Result<Account, String> {
+        match self.updates.get(addr) {
+            Some(account) => Ok(*account),
+            None => Ok(self.state.get(addr)?.unwrap_or_default()),
+        }
+    }
 
-        let trie = StateTrie::new(storage.clone());
+    /// 执行一笔转账 (批量交易的所有输出)，返回实际收取的手续费
+    pub fn apply_transaction(&mut self, tx: &SignedTransaction) -> Result<u64, String> {
+        let t = &tx.transaction;
+        let sender_addr = tx.sender_address();
+
+        // 余额需覆盖 gas_limit 的全部费用，但只扣除实际使用的 gas
+        let max_cost = t.max_cost()
+            .ok_or_else(|| format!("Cost overflow in tx {:?}", tx.hash()))?;
+        let fee = tx.checked_fee_paid()
+            .ok_or_else(|| format!("Fee overflow in tx {:?}", tx.hash()))?;
+        let total_value = t.total_value()
+            .ok_or_else(|| format!("Value overflow in tx {:?}", tx.hash()))?;
+        let total_cost = total_value.checked_add(fee)
+            .ok_or_else(|| format!("Cost overflow in tx {:?}", tx.hash()))?;
+        let total_fee = self.total_fee.checked_add(fee)
+            .filter(|total| total.checked_add(BLOCK_REWARD).is_some())
+            .ok_or_else(|| format!("Total fee overflow at tx {:?}", tx.hash()))?;
+
+        let mut sender_acc = self.account(&sender_addr)?;
+
+        // 验证 Nonce
+        if t.nonce != sender_acc.nonce {
+            return Err(format!("Invalid nonce for tx {:?}, expected {}, got {}", tx.hash(), sender_acc.nonce, t.nonce));
+        }
+        // 验证余额
+        if sender_acc.balance < max_cost {
+            return Err(format!("Insufficient balance for tx {:?}", tx.hash()));
+        }
 
-        //god_address
-        let hex_str = "67d39da22d106b686c4f301b6f357600d28fc104";
-        let bytes: Vec<u8> = hex::decode(hex_str).expect("Invalid hex string");
-        let array: [u8; 20] = bytes.try_into().expect("Wrong length");
-        let god_address = Address::from(array);
+        sender_acc.balance = sender_acc.balance.checked_sub(total_cost)
+            .ok_or_else(|| format!("Insufficient balance for tx {:?}", tx.hash()))?;
+        sender_acc.nonce = sender_acc.nonce.checked_add(1)
+            .ok_or_else(|| format!("Nonce overflow for tx {:?}", tx.hash()))?;
+
+        // 先在局部累积，任一输出失败时整笔交易不生效
+        let mut staged: HashMap<Address, Account> = HashMap::new();
+        staged.insert(sender_addr, sender_acc);
+        for (receiver_addr, value) in t.payments() {
+            let mut receiver_acc = match staged.get(&receiver_addr) {
+                Some(acc) => *acc,
+                None => self.account(&receiver_addr)?,
+            };
+            receiver_acc.balance = receiver_acc.balance.checked_add(value)
+                .ok_or_else(|| format!("Receiver balance overflow in tx {:?}", tx.hash()))?;
+            staged.insert(receiver_addr, receiver_acc);
+        }
 
-        let god_account = Account {
-            nonce: 0,
-            balance: 100_000_000, 
-        };
+        // 所有检查通过后才写入
+        self.updates.extend(staged);
+        self.total_fee = total_fee;
+        Ok(fee)
+    }
 
+    pub fn total_fee(&self) -> u64 {
+        self.total_fee
+    }
 
-        let (genesis_state_root, nodes) = trie.insert(god_address, god_account);
+    /// 区块奖励加上所有交易的手续费。apply_transaction 保证了不会溢出
+    pub fn block_reward(&self) -> u64 {
+        BLOCK_REWARD + self.total_fee
+    }
 
-        // 持久化状态节点
-        storage.batch_save_state_nodes(&nodes);
+    /// 将 Coinbase 记入矿工账户
+    pub fn apply_coinbase(&mut self, miner: Address, value: u64) -> Result<(), String> {
+        let mut miner_acc = self.account(&miner)?;
+        miner_acc.balance = miner_acc.balance.checked_add(value)
+            .ok_or_else(|| format!("Coinbase overflows miner balance of {:?}", miner))?;
+        self.updates.insert(miner, miner_acc);
+        Ok(())
+    }
+
+    /// 计算新的 state root 及需要持久化的节点，状态节点缺失时返回 Err
+    pub fn commit(self) -> Result<ExecutedState, String> {
+        let (state_root, new_nodes) = self.state.insert_batch(self.updates)?;
+        Ok(ExecutedState { state_root, new_nodes, _parent: self.state.clone() })
+    }
+}
+
+pub struct Blockchain {
+    pub tip: H256,
+    pub storage: Arc<Storage>,
+    pub chain_id: u64,
+}
+
+impl Blockchain {
+    pub fn new(path: &str, genesis: &GenesisConfig) -> Result<Self, String> {
+        Self::from_storage(Arc::new(Storage::new(path)), genesis)
+    }
 
-        let genesis_block = Block::genesis(genesis_state_root);
+    /// 打开已有的链或写入创世块。数据库与创世配置不匹配时返回 Err
+    pub fn from_storage(storage: Arc<Storage>, genesis: &GenesisConfig) -> Result<Self, String> {
+        if let Some(tip) = storage.get_tip() {
+            info!("Restoring blockchain from DB");
+            // 没有链 ID 的数据库由旧版本创建，其中的交易格式已不兼容，只能重新同步
+            let chain_id = storage.get_chain_id().ok_or(
+                "Database has no chain ID: it was created by an older version with an incompatible \
+                 transaction format. Remove the data directory and resync from peers"
+            )?;
+            if chain_id != genesis.chain_id {
+                return Err(format!(
+                    "Database belongs to chain {}, but genesis config specifies chain {}. \
+                     Use the genesis file this database was created with, or a different --data-dir",
+                    chain_id, genesis.chain_id
+                ));
+            }
+            // 更早的数据库已在上面因缺少链 ID 被拒绝，这里只迁移有链 ID 的数据库
+            if storage.get_state_format() < STATE_NODE_FORMAT {
+                info!("Compressing state trie nodes");
+                let rewritten = compress_state_nodes(&storage);
+                storage.save_state_format(STATE_NODE_FORMAT);
+                storage.flush();
+                info!("Rewrote {} state nodes as extensions", rewritten);
+            }
+            let chain = Self { tip, storage, chain_id };
+            // 旧版本创建的数据库没有高度索引
+            if chain.storage.get_canonical(chain.get_height(&tip)) != Some(tip) {
+                info!("Indexing the longest chain by height");
+                chain.index_longest_chain();
+            }
+            if chain.storage.block_heights.is_empty() {
+                info!("Indexing all blocks by height");
+                chain.index_block_heights();
+            }
+            return Ok(chain);
+        }
+
+        info!("Initializing Genesis State for chain {}", genesis.chain_id);
+
+        let genesis_state_root = Self::build_genesis_state(&storage, genesis);
+        let genesis_block = Self::genesis_with_state(genesis, genesis_state_root);
         let genesis_hash = genesis_block.hash();
 
         info!("Genesis Block Created. Hash: {:?}, State Root: {:?}", genesis_hash, genesis_state_root);
@@ -75,29 +227,76 @@ impl Blockchain {
         storage.insert_item(&storage.blocks, genesis_hash.as_ref(), &genesis_block);
         storage.insert_item(&storage.meta, b"tip", &genesis_hash);
         storage.insert_item(&storage.meta, genesis_hash.as_ref(), &0u64); // Height = 0
+        storage.save_canonical(&[(0, genesis_hash)]);
+        storage.save_block_height(0, &genesis_hash);
+        storage.save_chain_id(genesis.chain_id);
+        storage.save_state_format(STATE_NODE_FORMAT);
 
         // 刷盘
         storage.flush();
 
-        Self {
+        Ok(Self {
             tip: genesis_hash,
             storage,
+            chain_id: genesis.chain_id,
+        })
+    }
+
+    /// 写入初始账户 (默认为 God 账户)，返回创世状态根
+    fn build_genesis_state(storage: &Arc<Storage>, genesis: &GenesisConfig) -> H256 {
+        let mut trie = StateTrie::new(storage.clone());
+        for (address, balance) in &genesis.allocations {
+            let account = Account {
+                nonce: 0,
+                balance: *balance,
+            };
+            let (root, nodes) = trie.insert(*address, account).expect("Genesis state is built in memory");
+
+            // 持久化状态节点
+            storage.batch_save_state_nodes(&nodes);
+            trie = StateTrie::new_from_root(root, storage.clone());
         }
+        trie.root_hash
+    }
+
+    /// 不打开数据库计算创世区块，供只同步区块头的轻节点使用
+    pub fn genesis_block(genesis: &GenesisConfig) -> Block {
+        let storage = Arc::new(Storage::new_temporary());
+        Self::genesis_with_state(genesis, Self::build_genesis_state(&storage, genesis))
+    }
+
+    fn genesis_with_state(genesis: &GenesisConfig, state_root: H256) -> Block {
+        Block::genesis(genesis.chain_id, genesis.difficulty, 0, state_root)
     }
 
     // --- 获取信息相关函数保持不变 ---
-    pub fn get_account(&self, addr: &Address) -> Account {
-        let state = self.get_state_at_tip();
-        state.get(addr).unwrap_or_default()
+    pub fn get_account(&self, addr: &Address) -> Result<Account, String> {
+        let state = self.get_state_at_tip()?;
+        Ok(state.get(addr)?.unwrap_or_default())
+    }
+
+    /// 区块 `block_hash` 执行后的状态，读取期间不会被回收。
+    /// 区块不存在或状态已被剪枝时返回 Err
+    pub fn state_at(&self, block_hash: &H256) -> Result<StateTrie, String> {
+        let block = self.get_block(block_hash).ok_or_else(|| format!("Block {} not found", block_hash))?;
+        StateTrie::open(block.state_root, self.storage.clone())
+            .map_err(|e| format!("State of block {} is not available: {}", block_hash, e))
+    }
+
+    /// 最长链上高度为 `height` 的区块
+    pub fn block_at_height(&self, height: u64) -> Option<H256> {
+        if height > self.get_height(&self.tip) {
+            return None;
+        }
+        self.storage.get_canonical(height)
     }
 
     pub fn flush(&self) {
         self.storage.flush();
     }
 
-    pub fn get_state_at_tip(&self) -> StateTrie {
-        let tip_block = self.get_block(&self.tip).unwrap();
-        StateTrie::new_from_root(tip_block.state_root, self.storage.clone())
+    pub fn get_state_at_tip(&self) -> Result<StateTrie, String> {
+        self.state_at(&self.tip)
     }
 
     pub fn tip(&self) -> H256 {
@@ -120,6 +319,33 @@ impl Blockchain {
         self.storage.get_item(&self.storage.meta, hash.as_ref()).unwrap_or(0)
     }
 
+    /// 最长链上位于 `locator` 中第一个已知区块之后的区块头，最多 `max` 个。
+    /// `locator` 由对方从 tip 往回排列，都不在最长链上时从创世块之后开始
+    pub fn headers_after(&self, locator: &[H256], max: usize) -> Vec<BlockHeader> {
+        let start = locator.iter()
+            .filter(|hash| self.contains_block(hash))
+            .map(|hash| (self.get_height(hash), *hash))
+            .find(|(height, hash)| self.block_at_height(*height) == Some(*hash))
+            .map_or(1, |(height, _)| height + 1);
+        (start..)
+            .take(max)
+            .map_while(|height| self.block_at_height(height))
+            .filter_map(|hash| self.get_block(&hash).map(|b| b.header()))
+            .collect()
+    }
+
+    /// 已打包交易的包含证明
+    pub fn transaction_proof(&self, tx_hash: &H256) -> Option<TransactionProof> {
+        let receipt = self.storage.get_receipt(tx_hash)?;
+        let block = self.get_block(&receipt.block_hash)?;
+        TransactionProof::new(&block, receipt.index as usize)
+    }
+
+    /// 账户在区块 `block_hash` 执行后状态中的证明，区块或其状态不存在时返回 None
+    pub fn account_proof(&self, address: &Address, block_hash: &H256) -> Option<AccountProof> {
+        self.state_at(block_hash).ok()?.prove(address)
+    }
+
     pub fn all_blocks_in_longest_chain(&self) -> Vec<H256> {
         let mut chain = Vec::new();
         let mut curr = self.tip;
@@ -129,13 +355,60 @@ impl Blockchain {
             if height == 0 { break; }
             let block = self.get_block(&curr).unwrap();
             curr = block.get_parent();
+            // 从快照启动的节点没有快照区块之前的历史
+            if !self.contains_block(&curr) { break; }
         }
         chain.reverse();
         chain
     }
 
+    /// 以已恢复状态的快照区块为新的起点: 保存区块和高度并设为 tip。
+    /// 不下载之前的历史区块，所以只能在还停留在创世块的数据库上进行。
+    /// 清单来自对方，其中的高度不可信: `height` 必须来自已同步的区块头或用户指定
+    pub fn install_snapshot(&mut self, manifest: &SnapshotManifest, height: u64) -> Result<(), String> {
+        if self.get_height(&self.tip) != 0 {
+            return Err("Database already has blocks beyond genesis".to_string());
+        }
+        if height == 0 {
+            return Err("Snapshot block cannot be at height 0".to_string());
+        }
+        let block = &manifest.block;
+        let block_hash = block.hash();
+        if block.get_difficulty() != self.get_difficulty() {
+            return Err("Snapshot block difficulty differs from this chain".to_string());
+        }
+        if block_hash > block.get_difficulty() {
+            return Err(format!("PoW difficulty not satisfied by snapshot block {}", block_hash));
+        }
+        if MerkleTree::new(&block.data).root() != block.get_merkle_root() {
+            return Err("Invalid Merkle Root in snapshot block".to_string());
+        }
+        if StateTrie::open(block.state_root, self.storage.clone()).is_err() {
+            return Err(format!("State of snapshot block {} has not been restored", block_hash));
+        }
+
+        self.storage.insert_item(&self.storage.blocks, block_hash.as_ref(), block);
+        self.storage.batch_save_receipts(&Receipt::for_block(block));
+        self.storage.insert_item(&self.storage.meta, block_hash.as_ref(), &height);
+        self.storage.save_canonical(&[(height, block_hash)]);
+        self.storage.save_block_height(height, &block_hash);
+        self.storage.insert_item(&self.storage.meta, b"tip", &block_hash);
+        self.storage.flush();
+        self.tip = block_hash;
+        info!("Installed snapshot of block {} at height {}", block_hash, height);
+        Ok(())
+    }
+
+    pub fn execute_block(storage: Arc<Storage>, block: &Block) -> Result<ExecutedState, String> {
+        // 先做廉价的大小检查，避免超大区块占用验证线程
+        if block.data.len() > MAX_BLOCK_TRANSACTIONS {
+            return Err(format!("Too many transactions: {} > {}", block.data.len(), MAX_BLOCK_TRANSACTIONS));
+        }
+        let block_size = block.serialized_size();
+        if block_size > MAX_BLOCK_SIZE {
+            return Err(format!("Block size {} exceeds limit {}", block_size, MAX_BLOCK_SIZE));
+        }
 
-    pub fn execute_block(storage: Arc<Storage>, block: &Block) -> Result<(H256, HashMap<H256, Node>), String> {
         let block_hash = block.hash();
         let parent_hash = block.get_parent();
 
@@ -156,18 +429,29 @@ impl Blockchain {
              return Err("Difficulty mismatch with parent".to_string());
         }
 
-        // 验证交易签名与 Coinbase 数额
-        let mut total_fee: u64 = 0;
+        // 验证时间戳: 晚于父块，且不超前本地时钟太多。时间锁依赖该规则
+        if block.get_timestamp() <= parent_block.get_timestamp() {
+            return Err(format!("Block timestamp {} not after parent timestamp {}", block.get_timestamp(), parent_block.get_timestamp()));
+        }
+        if block.get_timestamp() > now_millis() + MAX_FUTURE_BLOCK_TIME {
+            return Err(format!("Block timestamp {} is too far in the future", block.get_timestamp()));
+        }
+
+        // 验证交易链 ID、签名、gas 与锁定时间
+        let chain_id = storage.get_chain_id().ok_or("Chain ID missing in DB")?;
+        let height = storage.get_item::<u64>(&storage.meta, parent_hash.as_ref()).unwrap_or(0) + 1;
+        let mut block_gas: u64 = 0;
         for (idx, tx) in block.data.iter().enumerate() {
-            if !tx.verify() {
-                 return Err(format!("Invalid signature in tx index {}", idx));
+            if let Err(e) = tx.validate(chain_id) {
+                 return Err(format!("Invalid tx index {}: {}", idx, e));
+            }
+            if !tx.transaction.is_unlocked(height, block.get_timestamp()) {
+                return Err(format!("Tx index {} is locked until {}", idx, tx.transaction.lock.unwrap()));
             }
-            total_fee += tx.transaction.gas_price * tx.transaction.gas_limit; 
+            block_gas = block_gas.saturating_add(tx.gas_used());
         }
-
-        let expected_reward = BLOCK_REWARD + total_fee;
-        if block.coinbase.value != expected_reward {
-            return Err(format!("Coinbase value mismatch. Expected: {}, Got: {}", expected_reward, block.coinbase.value));
+        if block_gas > BLOCK_GAS_LIMIT {
+            return Err(format!("Block gas {} exceeds limit {}", block_gas, BLOCK_GAS_LIMIT));
         }
 
         // 验证 Merkle Root
@@ -177,54 +461,33 @@ impl Blockchain {
         }
 
         // 验证 state_root
-        let state = StateTrie::new_from_root(parent_block.state_root, storage.clone());
+        let state = StateTrie::open(parent_block.state_root, storage.clone())
+            .map_err(|e| format!("State of parent block {:?} is not available: {}", parent_hash, e))?;
         
-        let mut account_updates: HashMap<Address, Account> = HashMap::new();
+        let mut transition = StateTransition::new(&state);
 
         for tx in &block.data {
-            let sender_addr = tx.sender_address();
-            let receiver_addr = tx.transaction.to;
-            let total_cost = tx.transaction.value + tx.transaction.gas_price * tx.transaction.gas_limit;
-
-            let mut sender_acc = account_updates.get(&sender_addr).cloned()
-                .unwrap_or_else(|| state.get(&sender_addr).unwrap_or_default());
-
-            // 验证 Nonce
-            if tx.transaction.nonce != sender_acc.nonce {
-                return Err(format!("Invalid nonce for tx {:?}, expected {}, got {}", tx.hash(), sender_acc.nonce, tx.transaction.nonce));
-            }
-            // 验证余额
-            if sender_acc.balance < total_cost {
-                return Err(format!("Insufficient balance for tx {:?}", tx.hash()));
-            }
-
-            // 执行转账
-            sender_acc.balance -= total_cost;
-            sender_acc.nonce += 1;
-            account_updates.insert(sender_addr, sender_acc);
+            transition.apply_transaction(tx)?;
+        }
 
-            let mut receiver_acc = account_updates.get(&receiver_addr).cloned()
-                .unwrap_or_else(|| state.get(&receiver_addr).unwrap_or_default());
-            receiver_acc.balance += tx.transaction.value;
-            account_updates.insert(receiver_addr, receiver_acc);
+        // 验证 Coinbase 数额
+        let expected_reward = transition.block_reward();
+        if block.coinbase.value != expected_reward {
+            return Err(format!("Coinbase value mismatch. Expected: {}, Got: {}", expected_reward, block.coinbase.value));
         }
 
         //  处理 Coinbase
-        let miner_addr = block.coinbase.to;
-        let mut miner_acc = account_updates.get(&miner_addr).cloned()
-            .unwrap_or_else(|| state.get(&miner_addr).unwrap_or_default());
-        miner_acc.balance += block.coinbase.value;
-        account_updates.insert(miner_addr, miner_acc);
+        transition.apply_coinbase(block.coinbase.to, block.coinbase.value)?;
 
         //  计算新 Root (Batch Insert - CPU 密集型)
-        let (final_root, new_nodes) = state.insert_batch(account_updates);
+        let executed = transition.commit()?;
 
         //  验证 Root 是否匹配
-        if final_root != block.state_root {
-            return Err(format!("State root mismatch! Calc: {:?}, Block: {:?}", final_root, block.state_root));
+        if executed.state_root != block.state_root {
+            return Err(format!("State root mismatch! Calc: {:?}, Block: {:?}", executed.state_root, block.state_root));
         }
 
-        Ok((block_hash, new_nodes))
+        Ok(executed)
     }
 
 
@@ -251,11 +514,13 @@ impl Blockchain {
         let parent_height = self.get_height(&parent_hash);
         let current_height = parent_height + 1;
         self.storage.insert_item(&self.storage.meta, block_hash.as_ref(), &current_height);
+        self.storage.save_block_height(current_height, &block_hash);
         
         //  更新 Tip (如果更长)
         let tip_height = self.get_height(&self.tip);
         if current_height > tip_height {
             info!("New Tip: {} Height: {}", block_hash, current_height);
+            self.switch_canonical(self.tip, block_hash);
             self.tip = block_hash;
             self.storage.insert_item(&self.storage.meta, b"tip", &block_hash);
         } else {
@@ -263,4 +528,391 @@ impl Blockchain {
         }
         
     }
-}
\ No newline at end of file
+
+    /// Receipt 和高度索引只记录 canonical 区块: tip 从 `old_tip` 切换到 `new_tip` 时，
+    /// 删除离开最长链的区块的 Receipt，写入新加入最长链的区块的 Receipt 和高度
+    fn switch_canonical(&self, old_tip: H256, new_tip: H256) {
+        let (mut old, mut new) = (old_tip, new_tip);
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let mut heights = Vec::new();
+        while old != new {
+            let new_is_higher = self.get_height(&new) >= self.get_height(&old);
+            let hash = if new_is_higher { new } else { old };
+            // 从快照启动的节点没有快照区块之前的历史
+            let block = match self.get_block(&hash) {
+                Some(block) => block,
+                None => break,
+            };
+            if new_is_higher {
+                heights.push((self.get_height(&new), new));
+                new = block.get_parent();
+                added.extend(Receipt::for_block(&block));
+            } else {
+                old = block.get_parent();
+                removed.extend(block.data.iter().map(|tx| tx.hash()));
+            }
+        }
+        self.storage.replace_receipts(&removed, &added);
+        self.storage.save_canonical(&heights);
+    }
+
+    /// 从 tip 往回重建整条最长链的高度索引
+    fn index_longest_chain(&self) {
+        let heights: Vec<(u64, H256)> = self.all_blocks_in_longest_chain()
+            .into_iter()
+            .map(|hash| (self.get_height(&hash), hash))
+            .collect();
+        self.storage.save_canonical(&heights);
+        self.storage.flush();
+    }
+
+    /// 为所有区块建立高度索引，只在迁移旧数据库时扫描一次
+    fn index_block_heights(&self) {
+        for key in self.storage.blocks.iter().keys() {
+            let key = key.expect("Failed to read block key");
+            let mut bytes = [0u8; 32];
+            bytes.copy_from_slice(&key);
+            let hash = H256::from(bytes);
+            self.storage.save_block_height(self.get_height(&hash), &hash);
+        }
+        self.storage.flush();
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::{Account, Blockchain, SnapshotManifest, StateTransition};
+    use super::genesis::{GenesisConfig, DEFAULT_CHAIN_ID};
+    use crate::database::Storage;
+    use crate::miner::{now_millis, BLOCK_REWARD, MAX_BLOCK_SIZE, MAX_BLOCK_TRANSACTIONS, MAX_FUTURE_BLOCK_TIME};
+    use crate::types::address::Address;
+    use crate::types::block::{Block, BlockHeader};
+    use crate::types::hash::{Hashable, H256};
+    use crate::types::key_pair;
+    use crate::types::multisig::{MultisigPolicy, MultisigProof};
+    use crate::types::state_trie::StateTrie;
+    use crate::types::transaction::{sign, sign_with, SignedTransaction, Transaction, TxOutput, TX_BASE_GAS, TX_OUTPUT_GAS, TX_SIGNATURE_GAS};
+    use proptest::prelude::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn state_with(accounts: &[(Address, Account)]) -> StateTrie {
+        let storage = Arc::new(Storage::new_temporary());
+        let trie = StateTrie::new(storage.clone());
+        let (root, nodes) = trie.insert_batch(accounts.iter().cloned().collect::<HashMap<_, _>>()).unwrap();
+        storage.batch_save_state_nodes(&nodes);
+        StateTrie::new_from_root(root, storage)
+    }
+
+    fn transfer(key: &Ed25519KeyPair, gas_price: u64, to: Address, value: u64) -> SignedTransaction {
+        sign_with(Transaction::new(DEFAULT_CHAIN_ID, 0, gas_price, TX_BASE_GAS, to, value, vec![]), key)
+    }
+
+    fn address_of(key: &Ed25519KeyPair) -> Address {
+        Address::from_public_key_bytes(key.public_key().as_ref())
+    }
+
+    fn account(balance: u64) -> Account {
+        Account { nonce: 0, balance }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn receiver_overflow_is_rejected(headroom in 0u64..1000, extra in 1u64..1_000_000) {
+            let key = key_pair::random();
+            let sender = address_of(&key);
+            let receiver = Address::from([7u8; 20]);
+            let value = headroom + extra;
+            let state = state_with(&[(sender, account(value + TX_BASE_GAS)), (receiver, account(u64::MAX - headroom))]);
+
+            let mut transition = StateTransition::new(&state);
+            let err = transition.apply_transaction(&transfer(&key, 1, receiver, value)).unwrap_err();
+            prop_assert!(err.contains("Receiver balance overflow"), "{}", err);
+            prop_assert_eq!(transition.account(&sender).unwrap().balance, value + TX_BASE_GAS);
+            prop_assert_eq!(transition.account(&sender).unwrap().nonce, 0);
+            prop_assert_eq!(transition.account(&receiver).unwrap().balance, u64::MAX - headroom);
+            prop_assert_eq!(transition.total_fee(), 0);
+        }
+
+        #[test]
+        fn sender_cost_overflow_is_rejected(gas_price in (u64::MAX / TX_BASE_GAS / 2)..=(u64::MAX / TX_BASE_GAS), value in (u64::MAX / 2)..=u64::MAX) {
+            let key = key_pair::random();
+            let sender = address_of(&key);
+            let state = state_with(&[(sender, account(u64::MAX))]);
+
+            let tx = transfer(&key, gas_price, Address::default(), value);
+            prop_assert!(tx.validate(DEFAULT_CHAIN_ID).is_err());
+            let mut transition = StateTransition::new(&state);
+            prop_assert!(transition.apply_transaction(&tx).is_err());
+            prop_assert_eq!(transition.account(&sender).unwrap().balance, u64::MAX);
+        }
+
+        #[test]
+        fn coinbase_overflow_is_rejected(headroom in 0u64..1000, extra in 1u64..1000) {
+            let miner = Address::from([9u8; 20]);
+            let state = state_with(&[(miner, account(u64::MAX - headroom))]);
+
+            let mut transition = StateTransition::new(&state);
+            prop_assert!(transition.apply_coinbase(miner, headroom + extra).is_err());
+            prop_assert_eq!(transition.account(&miner).unwrap().balance, u64::MAX - headroom);
+            prop_assert!(transition.apply_coinbase(miner, headroom).is_ok());
+        }
+
+        #[test]
+        fn fee_total_overflow_is_rejected(gas_price in (u64::MAX / TX_BASE_GAS / 2 + 1)..=(u64::MAX / TX_BASE_GAS)) {
+            let (key_a, key_b) = (key_pair::random(), key_pair::random());
+            let state = state_with(&[(address_of(&key_a), account(u64::MAX)), (address_of(&key_b), account(u64::MAX))]);
+            let fee = gas_price * TX_BASE_GAS;
+
+            let mut transition = StateTransition::new(&state);
+            let first = transition.apply_transaction(&transfer(&key_a, gas_price, Address::default(), 0));
+            // 单笔手续费加上区块奖励也可能溢出
+            if fee.checked_add(BLOCK_REWARD).is_some() {
+                prop_assert_eq!(first, Ok(fee));
+            } else {
+                prop_assert!(first.is_err());
+            }
+            let before = transition.total_fee();
+            let err = transition.apply_transaction(&transfer(&key_b, gas_price, Address::default(), 0)).unwrap_err();
+            prop_assert!(err.contains("Total fee overflow"), "{}", err);
+            prop_assert_eq!(transition.total_fee(), before);
+            prop_assert_eq!(transition.account(&address_of(&key_b)).unwrap().balance, u64::MAX);
+            prop_assert!(transition.block_reward() >= before);
+        }
+
+        #[test]
+        fn valid_transfers_conserve_balance(balance in 0u64..1_000_000, value in 0u64..1_000_000, gas_price in 0u64..100) {
+            let key = key_pair::random();
+            let sender = address_of(&key);
+            let receiver = Address::from([3u8; 20]);
+            let state = state_with(&[(sender, account(balance))]);
+
+            let mut transition = StateTransition::new(&state);
+            match transition.apply_transaction(&transfer(&key, gas_price, receiver, value)) {
+                Ok(fee) => {
+                    let after = transition.account(&sender).unwrap().balance + transition.account(&receiver).unwrap().balance;
+                    prop_assert_eq!(after + fee, balance);
+                    prop_assert_eq!(transition.account(&sender).unwrap().nonce, 1);
+                }
+                Err(_) => prop_assert!(balance < value + gas_price * TX_BASE_GAS),
+            }
+        }
+    }
+
+    fn block_with(data: Vec<SignedTransaction>) -> Block {
+        Block::new(H256::default(), 0, H256::default(), 0, H256::default(), Transaction::default(), data)
+    }
+
+    #[test]
+    fn rejects_oversized_blocks() {
+        let storage = Arc::new(Storage::new_temporary());
+
+        let tx = SignedTransaction {
+            transaction: Transaction::new(DEFAULT_CHAIN_ID, 0, 1, 10, Address::default(), 1, vec![]),
+            ..Default::default()
+        };
+        let too_many = block_with(vec![tx; MAX_BLOCK_TRANSACTIONS + 1]);
+        let err = Blockchain::execute_block(storage.clone(), &too_many).unwrap_err();
+        assert!(err.contains("Too many transactions"), "{}", err);
+
+        let huge = SignedTransaction {
+            transaction: Transaction::new(DEFAULT_CHAIN_ID, 0, 1, 10, Address::default(), 1, vec![0u8; MAX_BLOCK_SIZE as usize]),
+            ..Default::default()
+        };
+        let too_big = block_with(vec![huge]);
+        let err = Blockchain::execute_block(storage, &too_big).unwrap_err();
+        assert!(err.contains("exceeds limit"), "{}", err);
+    }
+
+    #[test]
+    fn block_timestamp_must_advance_and_not_run_ahead() {
+        let genesis = GenesisConfig { difficulty: H256::from([255u8; 32]), ..GenesisConfig::default() };
+        let chain = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &genesis).unwrap();
+        let parent = chain.get_block(&chain.tip()).unwrap();
+        let coinbase = Transaction::new(DEFAULT_CHAIN_ID, 0, 0, 0, Address::default(), BLOCK_REWARD, vec![]);
+        let state = chain.get_state_at_tip().unwrap();
+        let mut transition = StateTransition::new(&state);
+        transition.apply_coinbase(Address::default(), BLOCK_REWARD).unwrap();
+        let state_root = transition.commit().unwrap().state_root;
+        let block_at = |timestamp: u128| {
+            Block::new(chain.tip(), 0, parent.get_difficulty(), timestamp, state_root, coinbase.clone(), vec![])
+        };
+
+        let err = Blockchain::execute_block(chain.storage.clone(), &block_at(parent.get_timestamp())).unwrap_err();
+        assert!(err.contains("not after parent"), "{}", err);
+        let err = Blockchain::execute_block(chain.storage.clone(), &block_at(now_millis() + MAX_FUTURE_BLOCK_TIME + 60_000)).unwrap_err();
+        assert!(err.contains("future"), "{}", err);
+        assert!(Blockchain::execute_block(chain.storage.clone(), &block_at(now_millis())).is_ok());
+    }
+
+    #[test]
+    fn receipts_follow_the_canonical_chain() {
+        let mut chain = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &GenesisConfig::default()).unwrap();
+        let genesis = chain.tip();
+        let key = key_pair::random();
+        let (tx_a, tx_b) = (transfer(&key, 1, Address::default(), 1), transfer(&key, 2, Address::default(), 1));
+        let child = |parent: H256, nonce: u32, data: Vec<SignedTransaction>| {
+            Block::new(parent, nonce, H256::default(), 0, H256::default(), Transaction::default(), data)
+        };
+
+        let a1 = child(genesis, 1, vec![tx_a.clone()]);
+        chain.commit_block(&a1, HashMap::new());
+        assert_eq!(chain.storage.get_receipt(&tx_a.hash()).map(|r| r.block_hash), Some(a1.hash()));
+
+        // 分叉块不写入 Receipt
+        let b1 = child(genesis, 2, vec![tx_b.clone()]);
+        chain.commit_block(&b1, HashMap::new());
+        assert!(chain.storage.get_receipt(&tx_b.hash()).is_none());
+
+        // 重组后 Receipt 指向新的最长链
+        let b2 = child(b1.hash(), 3, vec![]);
+        chain.commit_block(&b2, HashMap::new());
+        assert!(chain.storage.get_receipt(&tx_a.hash()).is_none());
+        assert_eq!(chain.storage.get_receipt(&tx_b.hash()).map(|r| r.block_hash), Some(b1.hash()));
+    }
+
+    #[test]
+    fn height_index_follows_the_canonical_chain() {
+        let mut chain = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &GenesisConfig::default()).unwrap();
+        let genesis = chain.tip();
+        let child = |parent: H256, nonce: u32| {
+            Block::new(parent, nonce, H256::default(), 0, H256::default(), Transaction::default(), vec![])
+        };
+        let a1 = child(genesis, 1);
+        let a2 = child(a1.hash(), 2);
+        let b1 = child(genesis, 3);
+        let b2 = child(b1.hash(), 4);
+        let b3 = child(b2.hash(), 5);
+        for block in [&a1, &a2, &b1, &b2] {
+            chain.commit_block(block, HashMap::new());
+        }
+        assert_eq!(chain.block_at_height(2), Some(a2.hash()));
+        assert_eq!(chain.block_at_height(3), None);
+
+        chain.commit_block(&b3, HashMap::new());
+        let hashes = |headers: Vec<BlockHeader>| -> Vec<H256> {
+            headers.iter().map(|h| h.hash()).collect()
+        };
+        assert_eq!(chain.block_at_height(1), Some(b1.hash()));
+        // 离开最长链的区块不能作为定位点
+        assert_eq!(hashes(chain.headers_after(&[a2.hash(), a1.hash(), genesis], 10)), vec![b1.hash(), b2.hash(), b3.hash()]);
+        assert_eq!(hashes(chain.headers_after(&[b1.hash()], 1)), vec![b2.hash()]);
+        assert!(chain.headers_after(&[b3.hash()], 10).is_empty());
+
+        // 旧数据库在打开时补建索引
+        chain.storage.canonical.clear().unwrap();
+        let reopened = Blockchain::from_storage(chain.storage.clone(), &GenesisConfig::default()).unwrap();
+        assert_eq!(reopened.block_at_height(2), Some(b2.hash()));
+        assert_eq!(reopened.block_at_height(0), Some(genesis));
+    }
+
+    #[test]
+    fn snapshot_height_comes_from_the_caller() {
+        let genesis = GenesisConfig { difficulty: H256::from([255u8; 32]), ..GenesisConfig::default() };
+        let mut chain = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &genesis).unwrap();
+        let parent = chain.get_block(&chain.tip()).unwrap();
+        // 状态与创世块相同，新数据库中已经存在
+        let block = Block::new(chain.tip(), 0, parent.get_difficulty(), 1, parent.state_root, Transaction::default(), vec![]);
+        let manifest = SnapshotManifest { block: block.clone(), height: u64::MAX, accounts: 0, chunk_hashes: vec![] };
+
+        assert!(chain.install_snapshot(&manifest, 0).is_err());
+        chain.install_snapshot(&manifest, 5).unwrap();
+        assert_eq!(chain.tip(), block.hash());
+        assert_eq!(chain.get_height(&block.hash()), 5);
+        assert_eq!(chain.block_at_height(5), Some(block.hash()));
+    }
+
+    #[test]
+    fn genesis_depends_on_chain_id() {
+        let mainnet = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &GenesisConfig::default()).unwrap();
+        let other_config = GenesisConfig { chain_id: 2, ..GenesisConfig::default() };
+        let other = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &other_config).unwrap();
+
+        assert_eq!(mainnet.chain_id, DEFAULT_CHAIN_ID);
+        assert_eq!(other.chain_id, 2);
+        assert_ne!(mainnet.tip(), other.tip());
+        // 重启时使用数据库中记录的链 ID
+        let restored = Blockchain::from_storage(other.storage.clone(), &other_config).unwrap();
+        assert_eq!(restored.tip(), other.tip());
+        // 链 ID 不匹配或缺失时拒绝打开
+        assert!(Blockchain::from_storage(other.storage.clone(), &GenesisConfig::default()).is_err());
+        other.storage.meta.remove(b"chain_id").unwrap();
+        assert!(Blockchain::from_storage(other.storage.clone(), &other_config).is_err());
+    }
+
+    #[test]
+    fn multisig_account_needs_threshold_signatures() {
+        let keys: Vec<_> = (0..3).map(|_| key_pair::random()).collect();
+        let policy = MultisigPolicy::new(2, keys.iter().map(|k| k.public_key().as_ref().to_vec()).collect()).unwrap();
+        let treasury = policy.address();
+        let receiver = Address::from([4u8; 20]);
+        let state = state_with(&[(treasury, account(1_000))]);
+
+        let gas = TX_BASE_GAS + 2 * TX_SIGNATURE_GAS;
+        let t = Transaction::new(DEFAULT_CHAIN_ID, 0, 1, gas, receiver, 100, vec![]);
+        let mut proof = MultisigProof { policy: policy.clone(), signatures: vec![] };
+        let mut tx = SignedTransaction { transaction: t.clone(), multisig: Some(proof.clone()), ..Default::default() };
+        for key in &keys[..2] {
+            let index = policy.key_index(key.public_key().as_ref()).unwrap();
+            proof.add_signature(index, sign(&t, key).as_ref().to_vec());
+            assert!(tx.validate(DEFAULT_CHAIN_ID).is_err());
+            tx.multisig = Some(proof.clone());
+        }
+        assert!(tx.validate(DEFAULT_CHAIN_ID).is_ok());
+        assert_eq!(tx.sender_address(), treasury);
+
+        let mut transition = StateTransition::new(&state);
+        assert_eq!(transition.apply_transaction(&tx), Ok(gas));
+        assert_eq!(transition.account(&treasury).unwrap().balance, 1_000 - 100 - gas);
+
+        // 每个签名都要付 gas，多出的签名超出 gas_limit
+        let mut extra = proof.clone();
+        extra.add_signature(policy.key_index(keys[2].public_key().as_ref()).unwrap(), sign(&t, &keys[2]).as_ref().to_vec());
+        tx.multisig = Some(extra);
+        assert!(tx.validate(DEFAULT_CHAIN_ID).is_err());
+        assert_eq!(transition.account(&receiver).unwrap().balance, 100);
+    }
+
+    #[test]
+    fn batch_outputs_apply_atomically() {
+        let key = key_pair::random();
+        let sender = address_of(&key);
+        let (alice, bob, full) = (Address::from([1u8; 20]), Address::from([2u8; 20]), Address::from([3u8; 20]));
+        let state = state_with(&[(sender, account(1_000)), (full, account(u64::MAX))]);
+        let batch = |outputs: Vec<TxOutput>| {
+            let mut t = Transaction::new_batch(DEFAULT_CHAIN_ID, 0, 1, 0, outputs);
+            t.gas_limit = t.gas_used();
+            sign_with(t, &key)
+        };
+
+        // 最后一个输出溢出时，前面的输出也不生效
+        let mut transition = StateTransition::new(&state);
+        let failing = batch(vec![TxOutput { to: alice, value: 10 }, TxOutput { to: full, value: 1 }]);
+        assert!(failing.validate(DEFAULT_CHAIN_ID).is_ok());
+        assert!(transition.apply_transaction(&failing).is_err());
+        assert_eq!(transition.account(&alice).unwrap().balance, 0);
+        assert_eq!(transition.account(&sender).unwrap().balance, 1_000);
+
+        // 同一接收者可以出现多次，手续费随输出数增加
+        let tx = batch(vec![
+            TxOutput { to: alice, value: 10 },
+            TxOutput { to: bob, value: 20 },
+            TxOutput { to: alice, value: 5 },
+        ]);
+        let gas = TX_BASE_GAS + 3 * TX_OUTPUT_GAS;
+        assert_eq!(transition.apply_transaction(&tx), Ok(gas));
+        assert_eq!(transition.account(&alice).unwrap().balance, 15);
+        assert_eq!(transition.account(&bob).unwrap().balance, 20);
+        assert_eq!(transition.account(&sender).unwrap().balance, 1_000 - 35 - gas);
+        assert_eq!(transition.account(&sender).unwrap().nonce, 1);
+
+        // 批量交易不能同时使用 to/value
+        let mut mixed = tx.transaction.clone();
+        mixed.value = 1;
+        let mixed = sign_with(mixed, &key);
+        assert!(mixed.validate(DEFAULT_CHAIN_ID).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use super::Blockchain;
+use crate::database::Storage;
+use crate::types::block::Block;
+use crate::types::hash::{H256, Hashable};
+use crate::types::state_trie::{Node, NodeData};
+
+use log::info;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// 后台检查是否需要回收的间隔
+const PRUNE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// 清扫时每批删除的节点数
+const SWEEP_BATCH: usize = 10_000;
+
+/// 剪枝模式的状态保留策略。不剪枝 (归档模式) 时保留所有状态
+#[derive(Debug, Clone, Copy)]
+pub struct PruneConfig {
+    /// 保留最近多少个高度的状态，包括这些高度上的分叉区块
+    pub keep_recent: u64,
+    /// 另外保留主链上高度为其倍数的区块的状态，0 表示不保留检查点
+    pub checkpoint_interval: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct PruneStats {
+    pub retained_states: usize,
+    pub live_nodes: usize,
+    pub removed_nodes: usize,
+}
+
+/// 需要保留状态的区块的状态根: tip 往回 `keep_recent` 个高度内的所有区块，以及主链上的检查点
+pub fn retained_state_roots(storage: &Storage, tip: H256, config: &PruneConfig) -> Vec<H256> {
+    let height_of = |hash: &H256| storage.get_item::<u64>(&storage.meta, hash.as_ref()).unwrap_or(0);
+    let state_root = |hash: &H256| storage.get_item::<Block>(&storage.blocks, hash.as_ref()).map(|b| b.state_root);
+    let cutoff = height_of(&tip).saturating_sub(config.keep_recent.saturating_sub(1));
+
+    let mut roots = Vec::new();
+    // 保留范围内的区块 (包括分叉) 由高度索引给出，不需要扫描所有区块
+    for height in cutoff..=height_of(&tip) {
+        for hash in storage.blocks_at_height(height) {
+            roots.extend(state_root(&hash));
+        }
+    }
+
+    if config.checkpoint_interval > 0 {
+        for height in (0..cutoff).step_by(config.checkpoint_interval as usize) {
+            if let Some(hash) = storage.get_canonical(height) {
+                roots.extend(state_root(&hash));
+            }
+        }
+    }
+    roots
+}
+
+/// 标记从 `roots` 及正在读取的状态可达的节点，删除其余节点。期间写入的节点不会被删除
+pub fn collect_garbage(storage: &Storage, roots: &[H256]) -> PruneStats {
+    let mut live: HashSet<H256> = HashSet::new();
+    live.insert(NodeData::Empty.hash());
+    mark(storage, roots.to_vec(), &mut live);
+    let mut live_nodes = 0;
+    storage.begin_state_sweep(|readers| {
+        mark(storage, readers, &mut live);
+        live_nodes = live.len();
+        live
+    });
+
+    // 存活节点由 Storage 跳过
+    let mut removed_nodes = 0;
+    let mut batch = Vec::new();
+    for key in storage.state_nodes.iter().keys() {
+        let key = key.expect("Failed to read state node key");
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&key);
+        batch.push(H256::from(bytes));
+        if batch.len() >= SWEEP_BATCH {
+            removed_nodes += storage.remove_unpinned_state_nodes(&batch);
+            batch.clear();
+        }
+    }
+    removed_nodes += storage.remove_unpinned_state_nodes(&batch);
+
+    PruneStats {
+        retained_states: roots.len(),
+        live_nodes,
+        removed_nodes,
+    }
+}
+
+/// 把从 `roots` 可达的节点加入 `live`，已标记的子树不再遍历
+fn mark(storage: &Storage, mut stack: Vec<H256>, live: &mut HashSet<H256>) {
+    // 直接读数据库，避免遍历冲掉节点缓存
+    while let Some(hash) = stack.pop() {
+        if !live.insert(hash) {
+            continue;
+        }
+        if let Some(node) = storage.get_item::<Node>(&storage.state_nodes, hash.as_ref()) {
+            match node.data {
+                NodeData::Branch(left, right) => stack.extend([left, right]),
+                NodeData::Extension(_, child) => stack.push(child),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// 回收一次。只在读取 tip 时持有区块链的锁
+pub fn prune(blockchain: &Arc<Mutex<Blockchain>>, config: &PruneConfig) -> PruneStats {
+    let storage = blockchain.lock().unwrap().storage.clone();
+    // 先开始记录新写入的节点，再读取 tip，之后提交的区块不会丢失节点
+    storage.begin_state_gc();
+    let tip = blockchain.lock().unwrap().tip();
+    let roots = retained_state_roots(&storage, tip, config);
+    let stats = collect_garbage(&storage, &roots);
+    storage.end_state_gc();
+    stats
+}
+
+/// 在后台线程中定期回收，tip 没有变化时跳过
+pub fn start(blockchain: &Arc<Mutex<Blockchain>>, config: PruneConfig) {
+    let blockchain = blockchain.clone();
+    thread::Builder::new()
+        .name("state-pruner".to_string())
+        .spawn(move || {
+            let mut last_tip = None;
+            loop {
+                thread::sleep(PRUNE_CHECK_INTERVAL);
+                let tip = blockchain.lock().unwrap().tip();
+                if last_tip == Some(tip) {
+                    continue;
+                }
+                let stats = prune(&blockchain, &config);
+                info!(
+                    "Pruned {} state nodes, {} live nodes in {} retained states",
+                    stats.removed_nodes, stats.live_nodes, stats.retained_states
+                );
+                last_tip = Some(tip);
+            }
+        })
+        .unwrap();
+    info!(
+        "State pruning enabled: keeping {} recent blocks and a checkpoint every {} blocks",
+        config.keep_recent, config.checkpoint_interval
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::{collect_garbage, retained_state_roots, PruneConfig};
+    use crate::blockchain::genesis::GenesisConfig;
+    use crate::blockchain::{Account, Blockchain};
+    use crate::database::Storage;
+    use crate::types::address::Address;
+    use crate::types::block::Block;
+    use crate::types::hash::{Hashable, H256};
+    use crate::types::state_trie::{Node, NodeData, StateTrie};
+    use crate::types::transaction::Transaction;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+
+    #[test]
+    fn retains_recent_blocks_forks_and_checkpoints() {
+        let mut chain = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &GenesisConfig::default()).unwrap();
+        let genesis = chain.tip();
+        let child = |parent: H256, nonce: u8| {
+            Block::new(parent, nonce as u32, H256::default(), 0, H256::from([nonce; 32]), Transaction::default(), vec![])
+        };
+        // 主链 a1..a6，a1 上的旧分叉 c2，a4 上的新分叉 b5
+        let mut main = vec![child(genesis, 1)];
+        for nonce in 2..=6 {
+            main.push(child(main.last().unwrap().hash(), nonce));
+        }
+        let c2 = child(main[0].hash(), 20);
+        let b5 = child(main[3].hash(), 50);
+        for block in main.iter().chain([&c2, &b5]) {
+            chain.commit_block(block, HashMap::new());
+        }
+
+        let config = PruneConfig { keep_recent: 2, checkpoint_interval: 2 };
+        let roots: HashSet<H256> = retained_state_roots(&chain.storage, chain.tip(), &config).into_iter().collect();
+        let genesis_root = chain.get_block(&genesis).unwrap().state_root;
+        let expected: HashSet<H256> = [5, 6, 50, 2, 4].iter().map(|n| H256::from([*n; 32]))
+            .chain([genesis_root])
+            .collect();
+        assert_eq!(roots, expected);
+    }
+
+    #[test]
+    fn sweeps_unreachable_states_only() {
+        let storage = Arc::new(Storage::new_temporary());
+        let mut trie = StateTrie::new(storage.clone());
+        let mut roots = Vec::new();
+        for round in 1..=5u64 {
+            let updates: HashMap<Address, Account> = (0..8u8)
+                .map(|i| (Address::from([i * 31; 20]), Account { nonce: 0, balance: round * 10 + i as u64 }))
+                .collect();
+            let (root, nodes) = trie.insert_batch(updates).unwrap();
+            storage.batch_save_state_nodes(&nodes);
+            trie = StateTrie::new_from_root(root, storage.clone());
+            roots.push(root);
+        }
+        let before = storage.state_nodes.len();
+
+        // 正在读取的状态不会被删除
+        let reading = StateTrie::open(roots[1], storage.clone()).unwrap();
+        storage.begin_state_gc();
+        let stats = collect_garbage(&storage, &roots[3..]);
+        assert!(stats.removed_nodes > 0);
+        assert_eq!(storage.state_nodes.len(), before - stats.removed_nodes);
+        // 清扫期间不能开始读取已被删除的状态
+        assert!(StateTrie::open(roots[0], storage.clone()).is_err());
+        assert!(StateTrie::open(roots[1], storage.clone()).is_ok());
+        storage.end_state_gc();
+        drop(reading);
+
+        for (round, root) in roots.iter().enumerate() {
+            let balance = StateTrie::new_from_root(*root, storage.clone()).get(&Address::from([31u8; 20]));
+            match round {
+                0 | 2 => assert!(balance.is_err()),
+                _ => assert_eq!(balance.unwrap().map(|a| a.balance), Some((round as u64 + 1) * 10 + 1)),
+            }
+        }
+        assert!(StateTrie::new_from_root(roots[0], storage.clone()).prove(&Address::from([0u8; 20])).is_none());
+
+        // 回收期间写入的节点不会被删除
+        let late = Node::new(NodeData::Leaf(Address::from([9u8; 20]), Account { nonce: 1, balance: 1 }));
+        storage.begin_state_gc();
+        storage.batch_save_state_nodes(&HashMap::from([(late.hash, late.clone())]));
+        assert_eq!(storage.remove_unpinned_state_nodes(&[late.hash]), 0);
+        storage.end_state_gc();
+        assert_eq!(storage.remove_unpinned_state_nodes(&[late.hash]), 1);
+        assert!(storage.get_state_node(&late.hash).is_none());
+    }
+}
@@ -0,0 +1,239 @@
+use super::Account;
+use crate::database::Storage;
+use crate::types::address::Address;
+use crate::types::block::Block;
+use crate::types::hash::{H256, Hashable};
+use crate::types::state_trie::StateTrie;
+
+use ring::digest;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// 每个分块包含的账户数
+pub const SNAPSHOT_CHUNK_ACCOUNTS: usize = 4096;
+/// 快照文件开头的魔数和格式版本
+const SNAPSHOT_MAGIC: &[u8; 8] = b"STATESN1";
+
+/// 快照清单: 快照所在的区块及各分块的哈希。区块的 `state_root` 是重建状态的校验目标
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotManifest {
+    pub block: Block,
+    pub height: u64,
+    pub accounts: u64,
+    pub chunk_hashes: Vec<H256>,
+}
+
+impl SnapshotManifest {
+    pub fn block_hash(&self) -> H256 {
+        self.block.hash()
+    }
+}
+
+/// 按地址顺序排列的一段账户
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SnapshotChunk {
+    pub accounts: Vec<(Address, Account)>,
+}
+
+impl Hashable for SnapshotChunk {
+    fn hash(&self) -> H256 {
+        let encoded = bincode::serialize(self).expect("Serialization failed");
+        digest::digest(&digest::SHA256, &encoded).into()
+    }
+}
+
+/// 某个区块执行后的全部账户状态
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub manifest: SnapshotManifest,
+    pub chunks: Vec<SnapshotChunk>,
+}
+
+impl Snapshot {
+    /// 从状态树的叶子导出区块 `block_hash` 的状态，区块不存在或状态已被剪枝时返回 Err
+    pub fn build(storage: &Arc<Storage>, block_hash: &H256) -> Result<Self, String> {
+        let block = storage.get_item::<Block>(&storage.blocks, block_hash.as_ref())
+            .ok_or_else(|| format!("Block {} not found", block_hash))?;
+        let height = storage.get_item::<u64>(&storage.meta, block_hash.as_ref()).unwrap_or(0);
+        let trie = StateTrie::open(block.state_root, storage.clone())
+            .map_err(|e| format!("State of block {} is not available: {}", block_hash, e))?;
+
+        let accounts = trie.accounts()?;
+        let chunks: Vec<SnapshotChunk> = accounts
+            .chunks(SNAPSHOT_CHUNK_ACCOUNTS)
+            .map(|accounts| SnapshotChunk { accounts: accounts.to_vec() })
+            .collect();
+        let manifest = SnapshotManifest {
+            block,
+            height,
+            accounts: accounts.len() as u64,
+            chunk_hashes: chunks.iter().map(|c| c.hash()).collect(),
+        };
+        Ok(Self { manifest, chunks })
+    }
+
+    /// 文件格式: 魔数，清单，然后依次是各个分块
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("Cannot create snapshot file: {}", e))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(SNAPSHOT_MAGIC).map_err(|e| e.to_string())?;
+        bincode::serialize_into(&mut writer, &self.manifest).map_err(|e| e.to_string())?;
+        for chunk in &self.chunks {
+            bincode::serialize_into(&mut writer, chunk).map_err(|e| e.to_string())?;
+        }
+        writer.flush().map_err(|e| e.to_string())
+    }
+
+    /// 读取快照文件，只检查格式。分块和状态根由 `StateRestore` 校验
+    pub fn read_from<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Cannot open snapshot file: {}", e))?;
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err("Not a state snapshot file".to_string());
+        }
+        let manifest: SnapshotManifest = bincode::deserialize_from(&mut reader)
+            .map_err(|e| format!("Invalid snapshot manifest: {}", e))?;
+        let mut chunks = Vec::with_capacity(manifest.chunk_hashes.len());
+        for index in 0..manifest.chunk_hashes.len() {
+            let chunk = bincode::deserialize_from(&mut reader)
+                .map_err(|e| format!("Invalid snapshot chunk {}: {}", index, e))?;
+            chunks.push(chunk);
+        }
+        Ok(Self { manifest, chunks })
+    }
+}
+
+/// 按顺序接收分块并重建状态树。每个分块对照清单中的哈希，全部接收后对照区块的状态根
+pub struct StateRestore {
+    storage: Arc<Storage>,
+    manifest: SnapshotManifest,
+    trie: StateTrie,
+    next: usize,
+    accounts: u64,
+}
+
+impl StateRestore {
+    pub fn new(storage: Arc<Storage>, manifest: SnapshotManifest) -> Self {
+        Self {
+            trie: StateTrie::new(storage.clone()),
+            storage,
+            manifest,
+            next: 0,
+            accounts: 0,
+        }
+    }
+
+    /// 下一个需要的分块序号，全部接收后返回 None
+    pub fn next_chunk(&self) -> Option<u32> {
+        (self.next < self.manifest.chunk_hashes.len()).then_some(self.next as u32)
+    }
+
+    pub fn add_chunk(&mut self, chunk: SnapshotChunk) -> Result<(), String> {
+        let expected = self.manifest.chunk_hashes.get(self.next).ok_or("Snapshot has no more chunks")?;
+        if chunk.hash() != *expected {
+            return Err(format!("Snapshot chunk {} does not match the manifest", self.next));
+        }
+        self.accounts += chunk.accounts.len() as u64;
+        let updates: HashMap<Address, Account> = chunk.accounts.into_iter().collect();
+        let (root, nodes) = self.trie.insert_batch(updates)?;
+        self.storage.batch_save_state_nodes(&nodes);
+        // 登记重建中的状态，避免在接收下一个分块之前被回收
+        self.trie = StateTrie::open(root, self.storage.clone())?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// 检查重建的状态根，成功时返回清单
+    pub fn finish(self) -> Result<SnapshotManifest, String> {
+        if self.next_chunk().is_some() {
+            return Err(format!("Snapshot incomplete: {} of {} chunks", self.next, self.manifest.chunk_hashes.len()));
+        }
+        if self.accounts != self.manifest.accounts {
+            return Err(format!("Snapshot has {} accounts, manifest says {}", self.accounts, self.manifest.accounts));
+        }
+        if self.trie.root_hash != self.manifest.block.state_root {
+            return Err(format!(
+                "Rebuilt state root {} does not match block state root {}",
+                self.trie.root_hash, self.manifest.block.state_root
+            ));
+        }
+        Ok(self.manifest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Snapshot, StateRestore, SNAPSHOT_CHUNK_ACCOUNTS};
+    use crate::blockchain::{Account, Blockchain};
+    use crate::blockchain::genesis::GenesisConfig;
+    use crate::database::Storage;
+    use crate::types::address::Address;
+    use crate::types::block::Block;
+    use crate::types::hash::Hashable;
+    use crate::types::state_trie::StateTrie;
+    use crate::types::transaction::Transaction;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn restores_exported_state() {
+        let genesis = GenesisConfig::default();
+        let source = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &genesis).unwrap();
+        let storage = source.storage.clone();
+        let accounts: HashMap<Address, Account> = (0..SNAPSHOT_CHUNK_ACCOUNTS as u32 * 2 + 7)
+            .map(|i| {
+                let mut bytes = [0u8; 20];
+                bytes[..4].copy_from_slice(&i.wrapping_mul(2_654_435_761).to_be_bytes());
+                (Address::from(bytes), Account { nonce: i as u64, balance: 1 })
+            })
+            .collect();
+        let (state_root, nodes) = source.get_state_at_tip().unwrap().insert_batch(accounts.clone()).unwrap();
+        storage.batch_save_state_nodes(&nodes);
+        // 快照只校验状态，不执行区块，所以无需满足工作量证明
+        let tip = source.get_block(&source.tip()).unwrap();
+        let block = Block::new(source.tip(), 0, tip.get_difficulty(), 1, state_root, Transaction::default(), vec![]);
+        storage.insert_item(&storage.blocks, block.hash().as_ref(), &block);
+
+        let snapshot = Snapshot::build(&storage, &block.hash()).unwrap();
+        assert_eq!(snapshot.chunks.len(), 3);
+        let exported: Vec<Address> = snapshot.chunks.iter().flat_map(|c| c.accounts.iter().map(|(a, _)| *a)).collect();
+        assert!(exported.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let path = std::env::temp_dir().join(format!("snapshot-test-{}", std::process::id()));
+        snapshot.write_to(&path).unwrap();
+        let snapshot = Snapshot::read_from(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let target = Arc::new(Storage::new_temporary());
+        let mut restore = StateRestore::new(target.clone(), snapshot.manifest.clone());
+        let mut tampered = snapshot.chunks[1].clone();
+        tampered.accounts[0].1.balance += 1;
+        restore.add_chunk(snapshot.chunks[0].clone()).unwrap();
+        assert!(restore.add_chunk(tampered).is_err());
+        assert_eq!(restore.next_chunk(), Some(1));
+        for chunk in &snapshot.chunks[1..] {
+            restore.add_chunk(chunk.clone()).unwrap();
+        }
+        restore.finish().unwrap();
+
+        let restored = StateTrie::new_from_root(state_root, target);
+        for (address, account) in accounts.iter().take(100) {
+            assert_eq!(restored.get(address).unwrap().map(|a| a.nonce), Some(account.nonce));
+        }
+
+        // 清单中的哈希与状态根不符时拒绝
+        let mut manifest = snapshot.manifest.clone();
+        manifest.block = tip;
+        let mut restore = StateRestore::new(Arc::new(Storage::new_temporary()), manifest);
+        for chunk in snapshot.chunks {
+            restore.add_chunk(chunk).unwrap();
+        }
+        assert!(restore.finish().is_err());
+    }
+}
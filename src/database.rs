@@ -1,12 +1,20 @@
-use sled::{Db, IVec, Tree};
+use sled::{Db, Tree};
 use serde::{Serialize, Deserialize};
 use crate::types::hash::H256;
+use crate::types::receipt::Receipt;
+use crate::types::state_trie::Node;
+use crate::types::node_cache::{CacheStats, NodeCache, DEFAULT_STATE_CACHE_NODES};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 // 定义 Bucket (类似 SQL 的表)
 const BLOCK_TREE: &str = "blocks";
 const STATE_TREE: &str = "state_nodes";
 const META_TREE: &str = "meta";
+const RECEIPT_TREE: &str = "receipts";
+const CANONICAL_TREE: &str = "canonical";
+const BLOCK_HEIGHT_TREE: &str = "block_heights";
 
 #[derive(Clone)]
 pub struct Storage {
@@ -14,6 +22,43 @@ pub struct Storage {
     pub blocks: Tree,
     pub state_nodes: Tree,
     pub meta: Tree,
+    pub receipts: Tree,
+    /// 最长链的高度索引: 高度 (大端) -> 区块哈希
+    pub canonical: Tree,
+    /// 所有区块 (包括分叉) 的高度索引: 高度 (大端) + 区块哈希 -> 空
+    pub block_heights: Tree,
+    /// 已解码状态节点的缓存，克隆的 Storage 共享同一个缓存
+    node_cache: Arc<Mutex<NodeCache>>,
+    /// 状态回收与写入、读取之间的协调
+    state_gc: Arc<Mutex<StateGc>>,
+}
+
+#[derive(Default)]
+struct StateGc {
+    /// 回收期间为 Some: 回收开始后写入的节点，回收不会删除它们
+    written: Option<HashSet<H256>>,
+    /// 清扫期间为 Some: 标记为存活的节点
+    live: Option<HashSet<H256>>,
+    /// 正在读取的状态根及读取者个数
+    readers: HashMap<H256, usize>,
+}
+
+/// 状态读取登记，drop 时注销。登记期间状态回收不会删除该状态的节点
+pub struct StatePin {
+    state_gc: Arc<Mutex<StateGc>>,
+    root: H256,
+}
+
+impl Drop for StatePin {
+    fn drop(&mut self) {
+        let mut gc = self.state_gc.lock().unwrap();
+        if let Some(count) = gc.readers.get_mut(&self.root) {
+            *count -= 1;
+            if *count == 0 {
+                gc.readers.remove(&self.root);
+            }
+        }
+    }
 }
 
 impl Storage {
@@ -23,43 +68,217 @@ impl Storage {
             .flush_every_ms(Some(1000)) // 每1000ms自动刷一次盘
             .open()
             .expect("Failed to open database");
+        Self::from_db(db)
+    }
+
+    /// 临时数据库，drop 后自动删除。用于测试和轻节点计算创世状态
+    pub fn new_temporary() -> Self {
+        let db = sled::Config::default()
+            .temporary(true)
+            .open()
+            .expect("Failed to open database");
+        Self::from_db(db)
+    }
+
+    fn from_db(db: Db) -> Self {
         let blocks = db.open_tree(BLOCK_TREE).expect("Failed to open block tree");
         let state_nodes = db.open_tree(STATE_TREE).expect("Failed to open state tree");
         let meta = db.open_tree(META_TREE).expect("Failed to open meta tree");
+        let receipts = db.open_tree(RECEIPT_TREE).expect("Failed to open receipt tree");
+        let canonical = db.open_tree(CANONICAL_TREE).expect("Failed to open canonical tree");
+        let block_heights = db.open_tree(BLOCK_HEIGHT_TREE).expect("Failed to open block height tree");
 
-        Self { db, blocks, state_nodes, meta }
+        let node_cache = Arc::new(Mutex::new(NodeCache::new(DEFAULT_STATE_CACHE_NODES)));
+        let state_gc = Arc::new(Mutex::new(StateGc::default()));
+        Self { db, blocks, state_nodes, meta, receipts, canonical, block_heights, node_cache, state_gc }
     }
 
-    
     pub fn insert_item<T: Serialize>(&self, tree: &Tree, key: &[u8], value: &T) {
         let bytes = bincode::serialize(value).expect("Serialization failed");
         tree.insert(key, bytes).expect("DB insert failed");
     }
 
     pub fn get_item<T: for<'a> Deserialize<'a>>(&self, tree: &Tree, key: &[u8]) -> Option<T> {
-        match tree.get(key).expect("DB read failed") {
-            Some(data) => Some(bincode::deserialize(&data).expect("Deserialization failed")),
-            None => None,
-        }
+        tree.get(key)
+            .expect("DB read failed")
+            .map(|data| bincode::deserialize(&data).expect("Deserialization failed"))
     }
 
 
-    pub fn save_state_node<T: Serialize>(&self, hash: &H256, node: &T) {
+    pub fn save_state_node(&self, hash: &H256, node: &Node) {
+        self.pin_state_nodes(std::iter::once(hash));
         self.insert_item(&self.state_nodes, hash.as_ref(), node);
+        self.node_cache.lock().unwrap().insert(node.clone());
     }
 
-    // 批量写入状态节点 (原子操作)
-    pub fn batch_save_state_nodes<T: Serialize>(&self, nodes: &std::collections::HashMap<H256, T>) {
+    // 批量写入状态节点 (原子操作)，新节点同时放入缓存供下一个区块读取
+    pub fn batch_save_state_nodes(&self, nodes: &HashMap<H256, Node>) {
+        self.pin_state_nodes(nodes.keys());
         let mut batch = sled::Batch::default();
         for (hash, node) in nodes {
             let bytes = bincode::serialize(node).unwrap();
             batch.insert(hash.as_ref(), bytes);
         }
         self.state_nodes.apply_batch(batch).expect("Batch apply failed");
+        let mut cache = self.node_cache.lock().unwrap();
+        for node in nodes.values() {
+            cache.insert(node.clone());
+        }
+    }
+
+    pub fn get_state_node(&self, hash: &H256) -> Option<Node> {
+        if let Some(node) = self.node_cache.lock().unwrap().get(hash) {
+            return Some(node);
+        }
+        let node: Node = self.get_item(&self.state_nodes, hash.as_ref())?;
+        self.node_cache.lock().unwrap().insert(node.clone());
+        Some(node)
+    }
+
+    fn pin_state_nodes<'a>(&self, hashes: impl Iterator<Item = &'a H256>) {
+        if let Some(written) = self.state_gc.lock().unwrap().written.as_mut() {
+            written.extend(hashes);
+        }
+    }
+
+    /// 登记正在读取以 `root` 为根的状态。清扫期间只能登记存活或回收开始后写入的状态，
+    /// 其余状态可能已被部分删除，返回 Err
+    pub fn pin_state(&self, root: H256) -> Result<StatePin, String> {
+        let mut gc = self.state_gc.lock().unwrap();
+        if let Some(live) = &gc.live {
+            let written = gc.written.as_ref().is_some_and(|w| w.contains(&root));
+            if !live.contains(&root) && !written {
+                return Err(format!("State {} is being pruned", root));
+            }
+        }
+        *gc.readers.entry(root).or_insert(0) += 1;
+        Ok(StatePin { state_gc: self.state_gc.clone(), root })
+    }
+
+    /// 开始状态回收: 之后写入的节点在 `end_state_gc` 之前不会被删除
+    pub fn begin_state_gc(&self) {
+        self.state_gc.lock().unwrap().written = Some(HashSet::new());
+    }
+
+    /// 进入清扫阶段。`mark` 收到正在读取的状态根，返回包括它们在内的全部存活节点。
+    /// 标记期间持有锁，之后登记的读取都会对照存活节点检查
+    pub fn begin_state_sweep<F>(&self, mark: F)
+    where
+        F: FnOnce(Vec<H256>) -> HashSet<H256>,
+    {
+        let mut gc = self.state_gc.lock().unwrap();
+        let readers = gc.readers.keys().cloned().collect();
+        gc.live = Some(mark(readers));
+    }
+
+    pub fn end_state_gc(&self) {
+        let mut gc = self.state_gc.lock().unwrap();
+        gc.written = None;
+        gc.live = None;
+    }
+
+    /// 删除状态节点，跳过存活的和回收开始后重新写入的节点。返回删除的个数
+    pub fn remove_unpinned_state_nodes(&self, hashes: &[H256]) -> usize {
+        // 持有锁直到删除完成，同时写入的相同节点会在删除之后落盘
+        let gc = self.state_gc.lock().unwrap();
+        let mut batch = sled::Batch::default();
+        let mut cache = self.node_cache.lock().unwrap();
+        let mut removed = 0;
+        for hash in hashes {
+            let written = gc.written.as_ref().is_some_and(|w| w.contains(hash));
+            let live = gc.live.as_ref().is_some_and(|l| l.contains(hash));
+            if written || live {
+                continue;
+            }
+            batch.remove(hash.as_ref());
+            cache.remove(hash);
+            removed += 1;
+        }
+        self.state_nodes.apply_batch(batch).expect("Batch apply failed");
+        removed
+    }
+
+    /// 状态节点缓存的容量 (节点数)，0 表示关闭
+    pub fn set_state_cache_capacity(&self, capacity: usize) {
+        self.node_cache.lock().unwrap().set_capacity(capacity);
+    }
+
+    pub fn state_cache_stats(&self) -> CacheStats {
+        self.node_cache.lock().unwrap().stats()
+    }
+
+    // Receipt 以交易哈希为 key
+    pub fn batch_save_receipts(&self, receipts: &[Receipt]) {
+        self.replace_receipts(&[], receipts);
+    }
+
+    /// 删除 `removed` 中交易的 Receipt 并写入 `receipts`，用于重组时切换 canonical 分支
+    pub fn replace_receipts(&self, removed: &[H256], receipts: &[Receipt]) {
+        let mut batch = sled::Batch::default();
+        for tx_hash in removed {
+            batch.remove(tx_hash.as_ref());
+        }
+        for receipt in receipts {
+            let bytes = bincode::serialize(receipt).unwrap();
+            batch.insert(receipt.tx_hash.as_ref(), bytes);
+        }
+        self.receipts.apply_batch(batch).expect("Batch apply failed");
+    }
+
+    pub fn get_receipt(&self, tx_hash: &H256) -> Option<Receipt> {
+        self.get_item(&self.receipts, tx_hash.as_ref())
+    }
+
+    /// 记录最长链上各高度的区块，覆盖原有记录
+    pub fn save_canonical(&self, blocks: &[(u64, H256)]) {
+        let mut batch = sled::Batch::default();
+        for (height, hash) in blocks {
+            batch.insert(&height.to_be_bytes(), bincode::serialize(hash).unwrap());
+        }
+        self.canonical.apply_batch(batch).expect("Batch apply failed");
+    }
+
+    pub fn get_canonical(&self, height: u64) -> Option<H256> {
+        self.get_item(&self.canonical, &height.to_be_bytes())
+    }
+
+    /// 记录区块的高度，分叉区块也会记录
+    pub fn save_block_height(&self, height: u64, hash: &H256) {
+        let mut key = height.to_be_bytes().to_vec();
+        key.extend_from_slice(hash.as_ref());
+        self.block_heights.insert(key, &[]).expect("Failed to insert item");
+    }
+
+    /// 某个高度上的所有区块
+    pub fn blocks_at_height(&self, height: u64) -> Vec<H256> {
+        self.block_heights
+            .scan_prefix(height.to_be_bytes())
+            .keys()
+            .map(|key| {
+                let key = key.expect("Failed to read block height key");
+                let mut bytes = [0u8; 32];
+                bytes.copy_from_slice(&key[8..]);
+                H256::from(bytes)
+            })
+            .collect()
+    }
+
+    // 链 ID 在创世时写入，验证交易时使用
+    pub fn save_chain_id(&self, chain_id: u64) {
+        self.insert_item(&self.meta, b"chain_id", &chain_id);
+    }
+
+    pub fn get_chain_id(&self) -> Option<u64> {
+        self.get_item(&self.meta, b"chain_id")
+    }
+
+    // 状态节点的存储格式，打开旧数据库时据此决定是否迁移
+    pub fn save_state_format(&self, version: u32) {
+        self.insert_item(&self.meta, b"state_format", &version);
     }
 
-    pub fn get_state_node<T: for<'a> Deserialize<'a>>(&self, hash: &H256) -> Option<T> {
-        self.get_item(&self.state_nodes, hash.as_ref())
+    pub fn get_state_format(&self) -> u32 {
+        self.get_item(&self.meta, b"state_format").unwrap_or(0)
     }
 
     // Tip Hash 用于重启恢复
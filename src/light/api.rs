@@ -0,0 +1,137 @@
+use super::LightClient;
+use crate::api::{json_response, not_found, parse_address, parse_hash, AccountInfo, ChainInfo};
+use crate::network::message::Message;
+use crate::network::server::Handle as NetworkServerHandle;
+use crate::types::hash::Hashable;
+use crate::types::transaction::SignedTransaction;
+
+use log::{info, error, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Response, Server as HTTPServer, Request, Method};
+use url::Url;
+
+/// 轻节点的 API: 与全节点相同的路径和返回格式，但只支持查询链信息、
+/// 账户、交易证明和提交交易。查询结果都经过本地区块头验证
+pub struct Server {
+    handle: HTTPServer,
+    network: NetworkServerHandle,
+    client: Arc<LightClient>,
+}
+
+impl Server {
+    pub fn start(addr: std::net::SocketAddr, network: &NetworkServerHandle, client: &Arc<LightClient>) {
+        let handle = HTTPServer::http(addr).unwrap();
+        let server = Self {
+            handle,
+            network: network.clone(),
+            client: client.clone(),
+        };
+
+        info!("Light API Server started at http://{}", addr);
+
+        thread::spawn(move || {
+            for mut req in server.handle.incoming_requests() {
+                let response = handle_request(&mut req, &server.network, &server.client, addr);
+                if let Err(e) = req.respond(response) {
+                    error!("Failed to send response: {}", e);
+                }
+            }
+        });
+    }
+}
+
+fn handle_request(
+    req: &mut Request,
+    network: &NetworkServerHandle,
+    client: &LightClient,
+    addr: std::net::SocketAddr,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
+    let url = match base_url.join(req.url()) {
+        Ok(u) => u,
+        Err(e) => return json_response::<()>(false, &format!("Invalid URL: {}", e), None),
+    };
+    let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    match (req.method(), url.path()) {
+        (Method::Get, "/network/ping") => {
+            network.broadcast(Message::Ping(String::from("API Ping")));
+            json_response::<()>(true, "Ping broadcasted", None)
+        }
+
+        (Method::Get, "/blockchain/info") => {
+            let headers = client.headers();
+            let info = ChainInfo {
+                chain_id: headers.chain_id,
+                tip: headers.tip().to_string(),
+                height: headers.height(),
+            };
+            json_response(true, "Chain info", Some(info))
+        }
+
+        // 账户状态取自对等节点，用 tip 区块头的状态根验证
+        (Method::Get, "/blockchain/account") => {
+            let addr_str = match params.get("address") {
+                Some(a) => a,
+                None => return json_response::<()>(false, "Missing address parameter", None),
+            };
+            let address = match parse_address(addr_str) {
+                Ok(a) => a,
+                Err(e) => return json_response::<()>(false, &e, None),
+            };
+
+            match client.account(&address) {
+                Ok((account, block_hash, height)) => {
+                    let info = AccountInfo {
+                        address: addr_str.to_string(),
+                        nonce: account.nonce,
+                        balance: account.balance,
+                    };
+                    let message = format!("Account info verified at block {} (height {})", block_hash, height);
+                    json_response(true, &message, Some(info))
+                }
+                Err(e) => json_response::<()>(false, &format!("No verified account state: {}", e), None),
+            }
+        }
+
+        (Method::Get, "/transaction/proof") => {
+            let hash = match params.get("hash").map(|h| parse_hash(h)) {
+                Some(Ok(h)) => h,
+                Some(Err(e)) => return json_response::<()>(false, e, None),
+                None => return json_response::<()>(false, "Missing hash parameter", None),
+            };
+
+            match client.transaction(&hash) {
+                Ok((proof, height)) => {
+                    let message = format!("Inclusion proof verified at height {}", height);
+                    json_response(true, &message, Some(proof))
+                }
+                Err(e) => json_response::<()>(false, &e, None),
+            }
+        }
+
+        // 轻节点没有交易池，直接把交易发给对等节点
+        (Method::Post, "/transaction/submit") => {
+            let mut content = String::new();
+            req.as_reader().read_to_string(&mut content).unwrap();
+
+            let tx: SignedTransaction = match serde_json::from_str(&content) {
+                Ok(t) => t,
+                Err(e) => return json_response::<()>(false, &format!("Invalid Transaction JSON: {}", e), None),
+            };
+            let chain_id = client.headers().chain_id;
+            if let Err(e) = tx.validate(chain_id) {
+                warn!("Received invalid transaction: {}", e);
+                return json_response::<()>(false, &e, None);
+            }
+
+            let hash = tx.hash();
+            network.broadcast(Message::Transactions(vec![tx]));
+            json_response(true, "Transaction submitted", Some(hash.to_string()))
+        }
+
+        _ => not_found(),
+    }
+}
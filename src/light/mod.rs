@@ -0,0 +1,319 @@
+pub mod api;
+pub mod worker;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use crate::blockchain::{Account, Blockchain};
+use crate::blockchain::genesis::GenesisConfig;
+use crate::network::message::Message;
+use crate::network::server::Handle as ServerHandle;
+use crate::types::address::Address;
+use crate::types::block::BlockHeader;
+use crate::types::hash::{H256, Hashable};
+use crate::types::proof::{AccountProof, TransactionProof};
+
+/// 等待全节点返回证明的时间
+const PROOF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 等待某个请求回复的通道
+type Waiters<K, P> = Mutex<HashMap<K, Vec<Sender<P>>>>;
+
+/// 轻节点保存的区块头链: 只验证工作量证明和父块链接，不执行交易
+pub struct HeaderChain {
+    headers: HashMap<H256, (BlockHeader, u64)>,
+    /// 主链的高度索引: 第 i 个元素是主链上高度为 i 的区块
+    canonical: Vec<H256>,
+    genesis: H256,
+    tip: H256,
+    pub chain_id: u64,
+}
+
+impl HeaderChain {
+    pub fn new(genesis: &GenesisConfig) -> Self {
+        let header = Blockchain::genesis_block(genesis).header();
+        let hash = header.hash();
+        let mut headers = HashMap::new();
+        headers.insert(hash, (header, 0));
+        Self {
+            headers,
+            canonical: vec![hash],
+            genesis: hash,
+            tip: hash,
+            chain_id: genesis.chain_id,
+        }
+    }
+
+    pub fn tip(&self) -> H256 {
+        self.tip
+    }
+
+    pub fn height(&self) -> u64 {
+        self.headers[&self.tip].1
+    }
+
+    pub fn get(&self, hash: &H256) -> Option<&BlockHeader> {
+        self.headers.get(hash).map(|(header, _)| header)
+    }
+
+    pub fn get_height(&self, hash: &H256) -> Option<u64> {
+        self.headers.get(hash).map(|(_, height)| *height)
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.headers.contains_key(hash)
+    }
+
+    /// 验证后加入区块头，已存在时返回 false。与全节点相同，最高的链为主链
+    pub fn insert(&mut self, header: BlockHeader) -> Result<bool, String> {
+        let hash = header.hash();
+        if self.headers.contains_key(&hash) {
+            return Ok(false);
+        }
+        let (parent, parent_height) = self.headers.get(&header.parent)
+            .ok_or_else(|| format!("Unknown parent {} of header {}", header.parent, hash))?;
+        if hash > header.difficulty {
+            return Err(format!("PoW difficulty not satisfied by header {}", hash));
+        }
+        if header.difficulty != parent.difficulty {
+            return Err(format!("Difficulty mismatch with parent in header {}", hash));
+        }
+
+        let height = parent_height + 1;
+        self.headers.insert(hash, (header, height));
+        if height > self.height() {
+            self.tip = hash;
+            self.index_canonical();
+        }
+        Ok(true)
+    }
+
+    /// tip 改变后更新高度索引: 从新 tip 往回覆盖，直到与原主链汇合
+    fn index_canonical(&mut self) {
+        let height = self.height() as usize;
+        self.canonical.resize(height + 1, H256::default());
+        let mut hash = self.tip;
+        for h in (0..=height).rev() {
+            if self.canonical[h] == hash {
+                break;
+            }
+            self.canonical[h] = hash;
+            hash = self.headers[&hash].0.parent;
+        }
+    }
+
+    /// 主链上从 tip 往回的区块哈希，先逐个后按 2 的幂间隔，最后是创世块
+    pub fn locator(&self) -> Vec<H256> {
+        let mut locator = Vec::new();
+        let mut hash = self.tip;
+        let mut step = 1;
+        while hash != self.genesis {
+            locator.push(hash);
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            for _ in 0..step {
+                if hash == self.genesis {
+                    break;
+                }
+                hash = self.headers[&hash].0.parent;
+            }
+        }
+        locator.push(self.genesis);
+        locator
+    }
+
+    /// 区块是否在主链上
+    pub fn is_canonical(&self, hash: &H256) -> bool {
+        match self.get_height(hash) {
+            Some(height) => self.canonical_at(height) == Some(*hash),
+            None => false,
+        }
+    }
+
+    /// 主链上高度为 `height` 的区块
+    pub fn canonical_at(&self, height: u64) -> Option<H256> {
+        self.canonical.get(height as usize).copied()
+    }
+}
+
+/// 轻节点: 同步区块头，查询时向全节点请求证明并用本地区块头验证
+pub struct LightClient {
+    headers: Mutex<HeaderChain>,
+    server: ServerHandle,
+    account_waiters: Waiters<(Address, H256), Option<AccountProof>>,
+    tx_waiters: Waiters<H256, Option<TransactionProof>>,
+}
+
+impl LightClient {
+    pub fn new(genesis: &GenesisConfig, server: &ServerHandle) -> Self {
+        Self {
+            headers: Mutex::new(HeaderChain::new(genesis)),
+            server: server.clone(),
+            account_waiters: Mutex::new(HashMap::new()),
+            tx_waiters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn headers(&self) -> MutexGuard<'_, HeaderChain> {
+        self.headers.lock().unwrap()
+    }
+
+    /// 向所有对等节点请求新的区块头
+    pub fn request_headers(&self) {
+        let locator = self.headers().locator();
+        self.server.broadcast(Message::GetHeaders(locator));
+    }
+
+    /// 账户在主链 tip 的状态，返回 (账户, 区块哈希, 高度)
+    pub fn account(&self, address: &Address) -> Result<(Account, H256, u64), String> {
+        let (block_hash, height, state_root) = {
+            let headers = self.headers();
+            let tip = headers.tip();
+            (tip, headers.height(), headers.get(&tip).unwrap().state_root)
+        };
+
+        let (sender, receiver) = unbounded();
+        let key = (*address, block_hash);
+        self.account_waiters.lock().unwrap().entry(key).or_default().push(sender.clone());
+        self.server.broadcast(Message::GetAccountProof(*address, block_hash));
+
+        let result = wait_for_proof(&receiver, |proof| {
+            let proof = proof.ok_or("Peer has no state for this block")?;
+            if proof.address != *address {
+                return Err("Proof is for another address".to_string());
+            }
+            Ok(proof.verify(&state_root)?.unwrap_or_default())
+        });
+        remove_waiter(&self.account_waiters, &key, &sender);
+        result.map(|account| (account, block_hash, height))
+    }
+
+    /// 交易在主链上的包含证明及所在高度
+    pub fn transaction(&self, hash: &H256) -> Result<(TransactionProof, u64), String> {
+        let (sender, receiver) = unbounded();
+        self.tx_waiters.lock().unwrap().entry(*hash).or_default().push(sender.clone());
+        self.server.broadcast(Message::GetTransactionProof(*hash));
+
+        let result = wait_for_proof(&receiver, |proof| {
+            let proof = proof.ok_or("Transaction not found in any block")?;
+            if proof.tx_hash != *hash {
+                return Err("Proof is for another transaction".to_string());
+            }
+            proof.verify()?;
+            let headers = self.headers();
+            let block_hash = proof.block_hash();
+            if !headers.is_canonical(&block_hash) {
+                return Err(format!("Block {} is not on the synced header chain", block_hash));
+            }
+            let height = headers.get_height(&block_hash).unwrap();
+            Ok((proof, height))
+        });
+        remove_waiter(&self.tx_waiters, hash, &sender);
+        result
+    }
+
+    pub fn deliver_account_proof(&self, address: Address, block_hash: H256, proof: Option<AccountProof>) {
+        if let Some(waiters) = self.account_waiters.lock().unwrap().get(&(address, block_hash)) {
+            for waiter in waiters {
+                waiter.send(proof.clone()).ok();
+            }
+        }
+    }
+
+    pub fn deliver_transaction_proof(&self, hash: H256, proof: Option<TransactionProof>) {
+        if let Some(waiters) = self.tx_waiters.lock().unwrap().get(&hash) {
+            for waiter in waiters {
+                waiter.send(proof.clone()).ok();
+            }
+        }
+    }
+}
+
+/// 依次验证各个对等节点的回复，返回第一个通过验证的结果。
+/// 超时前没有合法回复时返回最后一个错误
+fn wait_for_proof<P, T, F>(receiver: &Receiver<P>, mut check: F) -> Result<T, String>
+where
+    F: FnMut(P) -> Result<T, String>,
+{
+    let deadline = Instant::now() + PROOF_TIMEOUT;
+    let mut last_error = "No peer answered".to_string();
+    while let Ok(response) = receiver.recv_deadline(deadline) {
+        match check(response) {
+            Ok(result) => return Ok(result),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn remove_waiter<K, P>(waiters: &Waiters<K, P>, key: &K, sender: &Sender<P>)
+where
+    K: std::hash::Hash + Eq,
+{
+    let mut waiters = waiters.lock().unwrap();
+    if let Some(list) = waiters.get_mut(key) {
+        list.retain(|s| !s.same_channel(sender));
+        if list.is_empty() {
+            waiters.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HeaderChain;
+    use crate::blockchain::Blockchain;
+    use crate::blockchain::genesis::GenesisConfig;
+    use crate::types::block::{Block, BlockHeader};
+    use crate::types::hash::{H256, Hashable};
+    use crate::types::transaction::Transaction;
+
+    fn child(parent: &BlockHeader, timestamp: u128) -> BlockHeader {
+        let block = Block::new(parent.hash(), 0, parent.difficulty, timestamp, parent.state_root, Transaction::default(), vec![]);
+        block.header()
+    }
+
+    #[test]
+    fn headers_follow_the_longest_chain() {
+        let genesis = GenesisConfig { difficulty: H256::from([255u8; 32]), ..GenesisConfig::default() };
+        let mut chain = HeaderChain::new(&genesis);
+        let root = Blockchain::genesis_block(&genesis).header();
+        assert_eq!(chain.tip(), root.hash());
+
+        let a1 = child(&root, 1);
+        let a2 = child(&a1, 2);
+        let b1 = child(&root, 3);
+        assert!(chain.insert(a2.clone()).is_err());
+        assert_eq!(chain.insert(a1.clone()), Ok(true));
+        assert_eq!(chain.insert(a1.clone()), Ok(false));
+        assert_eq!(chain.insert(b1.clone()), Ok(true));
+        // 同高度的分叉不替换 tip
+        assert_eq!(chain.tip(), a1.hash());
+        assert_eq!(chain.insert(a2.clone()), Ok(true));
+        assert_eq!((chain.tip(), chain.height()), (a2.hash(), 2));
+        assert!(chain.is_canonical(&a1.hash()));
+        assert!(!chain.is_canonical(&b1.hash()));
+        assert_eq!(chain.locator(), vec![a2.hash(), a1.hash(), root.hash()]);
+
+        // 更长的分叉成为主链后高度索引随之切换
+        let b2 = child(&b1, 6);
+        let b3 = child(&b2, 7);
+        chain.insert(b2).unwrap();
+        assert_eq!(chain.canonical_at(1), Some(a1.hash()));
+        chain.insert(b3.clone()).unwrap();
+        assert_eq!(chain.canonical_at(1), Some(b1.hash()));
+        assert_eq!(chain.canonical_at(3), Some(b3.hash()));
+        assert_eq!(chain.canonical_at(4), None);
+        assert!(!chain.is_canonical(&a2.hash()));
+
+        // 工作量证明或难度不对的区块头被拒绝
+        let mut wrong_difficulty = child(&a2, 4);
+        wrong_difficulty.difficulty = H256::from([254u8; 32]);
+        assert!(chain.insert(wrong_difficulty).is_err());
+        let mut no_work = child(&a2, 5);
+        no_work.difficulty = H256::default();
+        assert!(chain.insert(no_work).is_err());
+    }
+}
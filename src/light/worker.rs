@@ -0,0 +1,94 @@
+use super::LightClient;
+use crate::network::message::Message;
+use crate::network::peer;
+use crate::network::worker::MAX_HEADERS_PER_MESSAGE;
+use std::sync::Arc;
+use std::thread;
+use log::{debug, warn, error, info};
+
+/// 轻节点的 P2P 消息处理: 只关心区块头和证明，其余消息忽略
+#[derive(Clone)]
+pub struct Worker {
+    msg_chan: smol::channel::Receiver<(Vec<u8>, peer::Handle)>,
+    num_worker: usize,
+    client: Arc<LightClient>,
+}
+
+impl Worker {
+    pub fn new(
+        num_worker: usize,
+        msg_src: smol::channel::Receiver<(Vec<u8>, peer::Handle)>,
+        client: &Arc<LightClient>,
+    ) -> Self {
+        Self {
+            msg_chan: msg_src,
+            num_worker,
+            client: client.clone(),
+        }
+    }
+
+    pub fn start(self) {
+        let num_worker = self.num_worker;
+        for i in 0..num_worker {
+            let cloned = self.clone();
+            thread::spawn(move || {
+                cloned.worker_loop();
+                warn!("Light worker thread {} exited", i);
+            });
+        }
+    }
+
+    fn worker_loop(&self) {
+        loop {
+            let result = smol::block_on(self.msg_chan.recv());
+            if let Err(e) = result {
+                error!("light worker terminated {}", e);
+                break;
+            }
+            let (msg, mut peer) = result.unwrap();
+            let msg: Message = bincode::deserialize(&msg).unwrap();
+            match msg {
+                Message::Ping(nonce) => {
+                    peer.write(Message::Pong(nonce));
+                }
+                Message::NewBlockHashes(hashes) => {
+                    let headers = self.client.headers();
+                    if hashes.iter().any(|h| !headers.contains(h)) {
+                        peer.write(Message::GetHeaders(headers.locator()));
+                    }
+                }
+                Message::Headers(list) => {
+                    let count = list.len();
+                    let mut added = 0;
+                    let mut headers = self.client.headers();
+                    for header in list {
+                        match headers.insert(header) {
+                            Ok(true) => added += 1,
+                            Ok(false) => {}
+                            Err(e) => {
+                                warn!("Invalid header from peer: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    if added > 0 {
+                        info!("Synced {} headers, height {}", added, headers.height());
+                        // 消息已满说明对方还有更多区块头
+                        if count == MAX_HEADERS_PER_MESSAGE {
+                            peer.write(Message::GetHeaders(headers.locator()));
+                        }
+                    }
+                }
+                Message::AccountState(address, block_hash, proof) => {
+                    self.client.deliver_account_proof(address, block_hash, proof.map(|p| *p));
+                }
+                Message::TransactionInclusion(hash, proof) => {
+                    self.client.deliver_transaction_proof(hash, proof.map(|p| *p));
+                }
+                _ => {
+                    debug!("Light node ignoring message");
+                }
+            }
+        }
+    }
+}
@@ -10,6 +10,7 @@ pub mod miner;
 pub mod network;
 pub mod wallet;
 pub mod database;
+pub mod light;
 
 use clap::{clap_app, ArgMatches};
 use log::{error, info, warn};
@@ -18,19 +19,31 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use crate::blockchain::Blockchain;
+use crate::blockchain::genesis::GenesisConfig;
+use crate::blockchain::pruner::{self, PruneConfig};
+use crate::blockchain::snapshot::{Snapshot, StateRestore};
 use crate::types::mempool::Mempool;
+use crate::types::fee_estimator::FeeEstimator;
 use crate::network::message::Message;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::convert::TryInto;
 
 // Client 和 Server 都需要用到
 use crate::wallet::wallet::Wallet; 
-use ring::signature::Ed25519KeyPair;
-use ring::rand::SystemRandom;
-use ring::rand::SecureRandom; // 确保引用 SecureRandom trait
+use crate::wallet::keystore::{KeyKind, Keystore, KeystoreEntry};
+use crate::wallet::hd;
+use crate::wallet::batch;
+use crate::wallet::session::Session;
+use crate::wallet::offline::{self, MultisigRequest, UnsignedRequest};
+use crate::types::multisig::MultisigPolicy;
+use crate::types::proof::TransactionProof;
+use crate::types::address::Address;
+use crate::wallet::wallet::first_free_nonce;
+use crate::types::transaction::{SignedTransaction, Transaction, TxLock, TX_SIGNATURE_GAS};
 
 // 定义默认 API 地址
 const DEFAULT_API_ADDR: &str = "127.0.0.1:7000";
+// 钱包转账默认的 gas_limit (不带 data 的转账只消耗基础 gas)
+const DEFAULT_GAS_LIMIT: u64 = crate::types::transaction::TX_BASE_GAS;
 
 fn main() {
     let matches = clap_app!(Bitcoin =>
@@ -44,18 +57,44 @@ fn main() {
             (@arg known_peer: -c --connect ... [PEER] "Peers to connect to")
             (@arg p2p_workers: --("p2p-workers") [INT] default_value("4") "Number of P2P workers")
             (@arg data_dir: --data [PATH] default_value("./db/db1") "Path to database directory")
+            (@arg genesis: --genesis [PATH] "Genesis config (JSON) with chain ID and allocations")
+            (@arg state_cache: --("state-cache") [NODES] default_value("100000") "Number of decoded state trie nodes to cache, 0 to disable")
+            (@arg prune: --prune [BLOCKS] "Keep state only for the last BLOCKS heights and checkpoints (default: archive mode, keep all state)")
+            (@arg prune_checkpoint: --("prune-checkpoint") [INTERVAL] default_value("1000") "With --prune, also keep the state of every INTERVAL-th canonical block (0 for none)")
+            (@arg light: --light "Run a light node that syncs headers and verifies proofs from peers")
+            (@arg fast_sync: --("fast-sync") "On an empty database, start from a peer's state snapshot instead of replaying all blocks")
+        )
+        (@subcommand snapshot =>
+            (about: "Exports or imports state snapshots (the node must not be running)")
+            (@subcommand export =>
+                (about: "Writes the account state at a block to a snapshot file")
+                (@arg data_dir: --data [PATH] default_value("./db/db1") "Path to database directory")
+                (@arg genesis: --genesis [PATH] "Genesis config (JSON) with chain ID and allocations")
+                (@arg block: --block [HASH] "Block whose state to export (default: tip)")
+                (@arg output: +required "Snapshot file to write")
+            )
+            (@subcommand import =>
+                (about: "Starts an empty database from a snapshot file")
+                (@arg data_dir: --data [PATH] default_value("./db/db1") "Path to database directory")
+                (@arg genesis: --genesis [PATH] "Genesis config (JSON) with chain ID and allocations")
+                (@arg block: --block <HASH> "Trusted hash of the snapshot block, e.g. from a node you run")
+                (@arg height: --height <HEIGHT> "Trusted height of the snapshot block")
+                (@arg input: +required "Snapshot file to read")
+            )
         )
         (@subcommand client =>
             (about: "Interactive wallet to control the node")
             (@arg api_addr: --api [ADDR] default_value(DEFAULT_API_ADDR) "Target API address")
+            (@arg keystore: --keystore [PATH] default_value("./keystore") "Directory of encrypted key files")
         )
     ).get_matches();
 
     match matches.subcommand() {
         ("server", Some(sub_m)) => run_server(sub_m),
         ("client", Some(sub_m)) => run_client(sub_m),
+        ("snapshot", Some(sub_m)) => run_snapshot(sub_m),
         _ => {
-            println!("Please specify 'server' or 'client'. See --help.");
+            println!("Please specify 'server', 'client' or 'snapshot'. See --help.");
         }
     }
 }
@@ -70,9 +109,32 @@ fn run_server(matches: &ArgMatches) {
     let p2p_workers = matches.value_of("p2p_workers").unwrap().parse::<usize>().expect("Invalid Worker Count");
     let data_dir = matches.value_of("data_dir").unwrap();
 
+    let genesis = match matches.value_of("genesis") {
+        Some(path) => GenesisConfig::load(path).expect("Invalid genesis config"),
+        None => GenesisConfig::default(),
+    };
+
+    if matches.is_present("light") {
+        return run_light(matches, p2p_addr, api_addr, p2p_workers, &genesis);
+    }
+
     // 核心组件初始化
-    let blockchain = Arc::new(Mutex::new(Blockchain::new(data_dir)));
+    let state_cache = matches.value_of("state_cache").unwrap().parse::<usize>().expect("Invalid state cache size");
+    let prune_config = matches.value_of("prune").map(|blocks| PruneConfig {
+        keep_recent: blocks.parse::<u64>().ok().filter(|n| *n > 0).expect("Invalid number of blocks to keep"),
+        checkpoint_interval: matches.value_of("prune_checkpoint").unwrap().parse::<u64>().expect("Invalid checkpoint interval"),
+    });
+    let blockchain = match Blockchain::new(data_dir, &genesis) {
+        Ok(blockchain) => Arc::new(Mutex::new(blockchain)),
+        Err(e) => { error!("Cannot open blockchain: {}", e); return; }
+    };
+    blockchain.lock().unwrap().storage.set_state_cache_capacity(state_cache);
+    match prune_config {
+        Some(config) => pruner::start(&blockchain, config),
+        None => info!("Archive mode: keeping the state of every block"),
+    }
     let mempool = Arc::new(Mutex::new(Mempool::new()));
+    let fee_estimator = Arc::new(Mutex::new(FeeEstimator::from_chain(&blockchain.lock().unwrap())));
 
     // Network Server
     let (msg_tx, msg_rx) = smol::channel::bounded(10000);
@@ -81,7 +143,7 @@ fn run_server(matches: &ArgMatches) {
 
     println!("==========================================================");
     println!("⛏️  MINER CONFIGURATION");
-    println!("Please enter the HEX ADDRESS to receive mining rewards.");
+    println!("Please enter the ADDRESS to receive mining rewards.");
     println!("(If you don't have one, run 'client' in another terminal to generate one)");
     println!("==========================================================");
 
@@ -93,37 +155,42 @@ fn run_server(matches: &ArgMatches) {
         warn!("No address provided. Using a dummy address (00...00). Rewards will be lost!");
         crate::types::address::Address::from([0u8; 20])
     } else {
-        let bytes = hex::decode(addr_input).expect("Invalid Hex format");
-        let array: [u8; 20] = bytes.try_into().expect("Address must be 20 bytes");
-        crate::types::address::Address::from(array)
+        addr_input.parse::<crate::types::address::Address>().expect("Invalid address")
     };
 
-    info!("Miner configured to receive rewards at: {:?}", miner_address);
+    info!("Miner configured to receive rewards at: {}", miner_address);
 
     // Miner & Workers (不再传入 Wallet，只传入 Address)
     let (miner_ctx, miner, finished_block_chan) = miner::new(&blockchain, &mempool, miner_address);
-    let miner_worker_ctx = miner::worker::Worker::new(&server, finished_block_chan, &blockchain, &mempool, &miner);
-    let worker_ctx = network::worker::Worker::new(p2p_workers, msg_rx, &server, &blockchain, &mempool, &miner);
+    let miner_worker_ctx = miner::worker::Worker::new(&server, finished_block_chan, &blockchain, &mempool, &fee_estimator, &miner);
 
+    let known_peers: Vec<String> = matches.values_of("known_peer").map_or(Vec::new(), |p| p.map(|x| x.to_owned()).collect());
+    let fast_sync = matches.is_present("fast_sync") && {
+        let chain = blockchain.lock().unwrap();
+        chain.get_height(&chain.tip()) == 0
+    };
+    if fast_sync {
+        // 先连接对等节点，在网络 worker 启动前完成快照下载
+        for peer in &known_peers {
+            connect_peer(&server, peer);
+        }
+        match network::fast_sync::run(&server, &msg_rx, &blockchain, &genesis) {
+            Ok(height) => info!("Fast sync finished at height {}", height),
+            Err(e) => warn!("Fast sync failed, replaying blocks from genesis: {}", e),
+        }
+    }
+
+    let worker_ctx = network::worker::Worker::new(p2p_workers, msg_rx, &server, &blockchain, &mempool, &fee_estimator, &miner);
     worker_ctx.start();
 
     // Known Peers logic (same as before)
-    if let Some(known_peers) = matches.values_of("known_peer") {
-        let known_peers: Vec<String> = known_peers.map(|x| x.to_owned()).collect();
+    if !known_peers.is_empty() {
         let server = server.clone();
         thread::spawn(move || {
             for peer in known_peers {
-                let addr = match peer.parse::<net::SocketAddr>() {
-                    Ok(x) => x,
-                    Err(e) => { error!("Invalid peer: {}", e); continue; }
-                };
-                info!("Connect peer: {}", addr);
-                match server.connect(addr) {
-                    Ok(_) => {
-                        server.broadcast(Message::GetBlockchain);
-                        server.broadcast(Message::GetMempool);
-                    }
-                    Err(e) => warn!("Connect failed: {}", e),
+                if fast_sync || connect_peer(&server, &peer) {
+                    server.broadcast(Message::GetBlockchain);
+                    server.broadcast(Message::GetMempool);
                 }
                 thread::sleep(Duration::from_millis(500));
             }
@@ -138,7 +205,7 @@ fn run_server(matches: &ArgMatches) {
     miner_worker_ctx.start();
 
     // API Server Start (不再传入 Wallet)
-    api::Server::start(api_addr, &miner, &server, &blockchain, &mempool);
+    api::Server::start(api_addr, &miner, &server, &blockchain, &mempool, &fee_estimator);
 
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
@@ -158,13 +225,137 @@ fn run_server(matches: &ArgMatches) {
     info!("Goodbye!");
 }
 
+/// 连接对等节点，成功时返回 true
+fn connect_peer(server: &network::server::Handle, peer: &str) -> bool {
+    let addr = match peer.parse::<net::SocketAddr>() {
+        Ok(x) => x,
+        Err(e) => { error!("Invalid peer: {}", e); return false; }
+    };
+    info!("Connect peer: {}", addr);
+    match server.connect(addr) {
+        Ok(_) => true,
+        Err(e) => { warn!("Connect failed: {}", e); false }
+    }
+}
+
+// --- Snapshot Logic ---
+fn run_snapshot(matches: &ArgMatches) {
+    let (command, sub_m) = match matches.subcommand() {
+        (command, Some(sub_m)) => (command, sub_m),
+        _ => {
+            println!("Please specify 'export' or 'import'. See --help.");
+            return;
+        }
+    };
+    let genesis = match sub_m.value_of("genesis") {
+        Some(path) => GenesisConfig::load(path).expect("Invalid genesis config"),
+        None => GenesisConfig::default(),
+    };
+    let mut blockchain = match Blockchain::new(sub_m.value_of("data_dir").unwrap(), &genesis) {
+        Ok(blockchain) => blockchain,
+        Err(e) => { println!("❌ Cannot open blockchain: {}", e); return; }
+    };
+    let result = match command {
+        "export" => export_snapshot(&blockchain, sub_m),
+        _ => import_snapshot(&mut blockchain, sub_m),
+    };
+    match result {
+        Ok(message) => println!("✅ {}", message),
+        Err(e) => println!("❌ {}", e),
+    }
+}
+
+fn export_snapshot(blockchain: &Blockchain, matches: &ArgMatches) -> Result<String, String> {
+    let block_hash = match matches.value_of("block") {
+        Some(hash) => api::parse_hash(hash)?,
+        None => blockchain.tip(),
+    };
+    let snapshot = Snapshot::build(&blockchain.storage, &block_hash)?;
+    let output = matches.value_of("output").unwrap();
+    snapshot.write_to(output)?;
+    Ok(format!(
+        "Exported {} accounts of block {} (height {}) in {} chunks to {}",
+        snapshot.manifest.accounts, block_hash, snapshot.manifest.height, snapshot.chunks.len(), output
+    ))
+}
+
+fn import_snapshot(blockchain: &mut Blockchain, matches: &ArgMatches) -> Result<String, String> {
+    if blockchain.get_height(&blockchain.tip()) != 0 {
+        return Err("Database already has blocks beyond genesis".to_string());
+    }
+    // 文件只证明区块满足难度，区块和高度必须由用户从可信来源提供
+    let block_hash = api::parse_hash(matches.value_of("block").unwrap())?;
+    let height: u64 = matches.value_of("height").unwrap().parse()
+        .map_err(|_| "Height must be a number".to_string())?;
+    let snapshot = Snapshot::read_from(matches.value_of("input").unwrap())?;
+    if snapshot.manifest.block_hash() != block_hash {
+        return Err(format!("Snapshot is of block {}, not {}", snapshot.manifest.block_hash(), block_hash));
+    }
+    if snapshot.manifest.height != height {
+        return Err(format!("Snapshot claims height {}, not {}", snapshot.manifest.height, height));
+    }
+    let mut restore = StateRestore::new(blockchain.storage.clone(), snapshot.manifest);
+    for chunk in snapshot.chunks {
+        restore.add_chunk(chunk)?;
+    }
+    let manifest = restore.finish()?;
+    blockchain.install_snapshot(&manifest, height)?;
+    Ok(format!(
+        "Imported {} accounts; chain starts at block {} (height {})",
+        manifest.accounts, block_hash, height
+    ))
+}
+
+/// 轻节点: 不保存区块和状态，只同步区块头，查询时向全节点请求证明
+fn run_light(matches: &ArgMatches, p2p_addr: net::SocketAddr, api_addr: net::SocketAddr, p2p_workers: usize, genesis: &GenesisConfig) {
+    let (msg_tx, msg_rx) = smol::channel::bounded(10000);
+    let (server_ctx, server) = network::server::new(p2p_addr, msg_tx).unwrap();
+    server_ctx.start().unwrap();
+
+    let client = Arc::new(light::LightClient::new(genesis, &server));
+    light::worker::Worker::new(p2p_workers, msg_rx, &client).start();
+
+    if let Some(known_peers) = matches.values_of("known_peer") {
+        for peer in known_peers {
+            let addr = match peer.parse::<net::SocketAddr>() {
+                Ok(x) => x,
+                Err(e) => { error!("Invalid peer: {}", e); continue; }
+            };
+            info!("Connect peer: {}", addr);
+            if let Err(e) = server.connect(addr) {
+                warn!("Connect failed: {}", e);
+            }
+        }
+    }
+
+    // 定期请求区块头，同时覆盖启动同步和漏掉的新区块通知
+    let syncer = client.clone();
+    thread::spawn(move || loop {
+        syncer.request_headers();
+        thread::sleep(Duration::from_secs(10));
+    });
+
+    light::api::Server::start(api_addr, &server, &client);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        info!("Shutting down...");
+        r.store(false, Ordering::SeqCst);
+    }).expect("Error setting Ctrl-C handler");
+
+    while running.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(100));
+    }
+    info!("Goodbye!");
+}
+
 // --- Client Logic (The Real Wallet) ---
 use std::io::{self, Write};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
 #[derive(Deserialize)]
 struct AccountResponse {
-    success: bool,
     data: Option<AccountInfo>,
 }
 
@@ -174,34 +365,267 @@ struct AccountInfo {
     balance: u64,
 }
 
+#[derive(Deserialize)]
+struct PendingTxResponse {
+    data: Option<Vec<PendingTx>>,
+}
+
+#[derive(Deserialize)]
+struct PendingTx {
+    nonce: u64,
+}
+
+/// 节点 Mempool 中由 `address` 发出的交易的 nonce
+fn fetch_pending_nonces(base_url: &str, address: &crate::types::address::Address) -> Option<Vec<u64>> {
+    let url = format!("{}/mempool/transactions?sender={}", base_url, address);
+    let resp = reqwest::blocking::get(url).ok()?;
+    let txs = resp.json::<PendingTxResponse>().ok()?.data?;
+    Some(txs.into_iter().map(|tx| tx.nonce).collect())
+}
+
+#[derive(Deserialize)]
+struct SubmitResponse {
+    success: bool,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ChainInfoResponse {
+    data: Option<ChainInfo>,
+}
+
+#[derive(Deserialize)]
+struct ChainInfo {
+    chain_id: u64,
+}
+
+fn fetch_chain_id(base_url: &str) -> Option<u64> {
+    let resp = reqwest::blocking::get(format!("{}/blockchain/info", base_url)).ok()?;
+    resp.json::<ChainInfoResponse>().ok()?.data.map(|info| info.chain_id)
+}
+
+#[derive(Deserialize)]
+struct ProofResponse {
+    message: String,
+    data: Option<TransactionProof>,
+}
+
+/// 从节点获取交易包含证明
+fn fetch_transaction_proof(base_url: &str, hash: &str) -> Result<TransactionProof, String> {
+    let url = format!("{}/transaction/proof?hash={}", base_url, hash);
+    let resp = reqwest::blocking::get(url).map_err(|e| format!("Request failed: {}", e))?;
+    let r = resp.json::<ProofResponse>().map_err(|e| format!("Invalid response: {}", e))?;
+    r.data.ok_or(r.message)
+}
+
+#[derive(Deserialize)]
+struct FeeEstimateResponse {
+    data: Option<FeeEstimateInfo>,
+}
+
+#[derive(Deserialize)]
+struct FeeEstimateInfo {
+    target_blocks: u64,
+    gas_price: u64,
+    sampled_blocks: usize,
+    pending_transactions: usize,
+}
+
+fn fetch_fee_estimate(base_url: &str, target_blocks: u64) -> Option<FeeEstimateInfo> {
+    let url = format!("{}/fee/estimate?target_blocks={}", base_url, target_blocks);
+    let resp = reqwest::blocking::get(url).ok()?;
+    resp.json::<FeeEstimateResponse>().ok()?.data
+}
+
+fn prompt(label: &str) -> String {
+    print!("{}", label);
+    io::stdout().flush().ok();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).ok();
+    input.trim().to_string()
+}
+
+/// 输入两次新口令，不一致时返回 None
+fn prompt_new_passphrase() -> Option<String> {
+    let passphrase = rpassword::prompt_password("New passphrase > ").unwrap();
+    let confirm = rpassword::prompt_password("Repeat passphrase > ").unwrap();
+    if passphrase != confirm {
+        println!("Passphrases do not match");
+        return None;
+    }
+    Some(passphrase)
+}
+
+/// 链上账户状态，不存在的账户 nonce 与余额均为 0
+fn fetch_account(base_url: &str, address: &crate::types::address::Address) -> Result<AccountInfo, String> {
+    let url = format!("{}/blockchain/account?address={}", base_url, address);
+    let resp = reqwest::blocking::get(url).map_err(|e| e.to_string())?;
+    let info = resp.json::<AccountResponse>().map_err(|e| e.to_string())?.data;
+    Ok(info.unwrap_or(AccountInfo { nonce: 0, balance: 0 }))
+}
+
+/// 账户在链上有余额或发送过交易时视为已使用
+fn account_used(base_url: &str, address: &crate::types::address::Address) -> Result<bool, String> {
+    let info = fetch_account(base_url, address)?;
+    Ok(info.balance > 0 || info.nonce > 0)
+}
+
+/// 未手动指定 gas_price 时使用节点的手续费估算
+fn resolve_gas_price(base_url: &str, arg: Option<&str>) -> Result<u64, String> {
+    if let Some(p) = arg {
+        return p.parse().map_err(|_| "Invalid gas price".to_string());
+    }
+    let est = fetch_fee_estimate(base_url, api::DEFAULT_FEE_TARGET_BLOCKS)
+        .ok_or("Failed to fetch fee estimate, specify gas_price manually")?;
+    println!("Using estimated gas price {} (target {} blocks)", est.gas_price, est.target_blocks);
+    Ok(est.gas_price)
+}
+
+/// 提交已签名交易，节点拒绝时返回错误信息
+fn submit_transaction(base_url: &str, tx: &SignedTransaction) -> Result<String, String> {
+    let client = reqwest::blocking::Client::new();
+    let resp = client.post(format!("{}/transaction/submit", base_url))
+        .json(tx) // 直接发送 SignedTransaction 对象
+        .send()
+        .map_err(|e| format!("Submission Failed: {}", e))?;
+    let r = resp.json::<SubmitResponse>().map_err(|e| format!("Invalid response: {}", e))?;
+    if r.success {
+        Ok(r.message)
+    } else {
+        Err(format!("Rejected: {}", r.message))
+    }
+}
+
+/// 从参数中取出 `flag <value>`，flag 可以出现在任意位置
+fn take_flag<'a>(args: &mut Vec<&'a str>, flag: &str) -> Result<Option<&'a str>, String> {
+    match args.iter().position(|a| *a == flag) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args[i + 1];
+            args.drain(i..i + 2);
+            Ok(Some(value))
+        }
+        Some(_) => Err(format!("Missing value after {}", flag)),
+        None => Ok(None),
+    }
+}
+
+/// 取出 `--after-height <h>` 或 `--after-time <unix_secs>`
+fn parse_lock(args: &mut Vec<&str>) -> Result<Option<TxLock>, String> {
+    let height = take_flag(args, "--after-height")?;
+    let time = take_flag(args, "--after-time")?;
+    match (height, time) {
+        (Some(_), Some(_)) => Err("Use only one of --after-height and --after-time".to_string()),
+        (Some(h), None) => h.parse().map(|h| Some(TxLock::Height(h))).map_err(|_| format!("Invalid height {}", h)),
+        (None, Some(t)) => t.parse().map(|t| Some(TxLock::Time(t))).map_err(|_| format!("Invalid time {}", t)),
+        (None, None) => Ok(None),
+    }
+}
+
+/// 解析会话账户名、多签策略名或地址
+fn resolve_target(session: &Session, keystore: &Keystore, target: &str) -> Result<Address, String> {
+    if !session.contains(target) {
+        if let Ok(policy) = keystore.load_policy(target) {
+            return Ok(policy.address());
+        }
+    }
+    session.resolve_address(target)
+}
+
+/// 标记出已在会话中解锁的账户
+fn print_accounts(accounts: &[KeystoreEntry], session: Option<&Session>) {
+    for entry in accounts {
+        let unlocked = session.is_some_and(|s| s.contains(&entry.name));
+        let marker = if unlocked { "*" } else { " " };
+        let kind = match entry.kind {
+            KeyKind::Ed25519 => "key",
+            KeyKind::Mnemonic => "hd",
+        };
+        println!("{} {:<16} {:<4} {}", marker, entry.name, kind, entry.address);
+    }
+}
+
+/// `name` 或 `name/<index>`，index 为 HD 账户的派生地址序号
+fn parse_account_ref(input: &str) -> Result<(&str, u32), String> {
+    match input.split_once('/') {
+        Some((name, index)) => match index.parse::<u32>() {
+            // 地址索引在派生时会加上 hardened 偏移，超出范围的索引会与较小的索引得到同一地址
+            Ok(i) if i < hd::HARDENED_OFFSET => Ok((name, i)),
+            _ => Err(format!("Invalid index {}: must be below {}", index, hd::HARDENED_OFFSET)),
+        },
+        None => Ok((input, 0)),
+    }
+}
+
+/// 会话中的账户名，HD 账户的非 0 地址为 `name/<index>`
+fn account_label(name: &str, index: u32) -> String {
+    if index == 0 {
+        name.to_string()
+    } else {
+        format!("{}/{}", name, index)
+    }
+}
+
+/// 输入口令解锁 keystore 中的账户
+fn unlock_account(keystore: &Keystore, name: &str, index: u32) -> Result<Wallet, String> {
+    let passphrase = rpassword::prompt_password("Passphrase > ").unwrap();
+    let key_pair = keystore.unlock(name, &passphrase)?.key_pair(index)?;
+    Ok(Wallet::new(key_pair))
+}
+
 fn run_client(matches: &ArgMatches) {
     let api_addr = matches.value_of("api_addr").unwrap();
     let base_url = format!("http://{}", api_addr);
+    let keystore = Keystore::new(matches.value_of("keystore").unwrap());
 
     println!("==========================================================");
     println!("🔐  WALLET LOGIN");
-    println!("Enter your Private Key Seed (Hex) to login.");
-    println!("(Press ENTER to generate a NEW key)");
+    println!("Enter the name of a keystore account to unlock (name/<index> for HD addresses).");
+    println!("(Press ENTER to create a NEW account, or enter - to continue without one,");
+    println!(" e.g. to build or submit offline-signed transactions)");
     println!("==========================================================");
 
-    let secret_input = rpassword::prompt_password("Seed > ").unwrap();
-    let secret_input = secret_input.trim();
-    let rng = SystemRandom::new();
+    match keystore.list() {
+        Ok(accounts) => print_accounts(&accounts, None),
+        Err(e) => println!("Cannot read keystore: {}", e),
+    }
 
-    let keypair = if secret_input.is_empty() {
-        let mut seed_bytes = [0u8; 32];
-        rng.fill(&mut seed_bytes).unwrap();
-        println!("\n[!] GENERATED NEW WALLET. SAVE THIS SEED:\n{}\n", hex::encode(seed_bytes));
-        Ed25519KeyPair::from_seed_unchecked(&seed_bytes).unwrap()
-    } else {
-        let seed_bytes = hex::decode(secret_input).expect("Invalid hex");
-        Ed25519KeyPair::from_seed_unchecked(&seed_bytes).expect("Invalid seed")
+    let unlocked = loop {
+        let input = prompt("Account > ");
+        if input == "-" {
+            break None;
+        }
+        let (mut name, index) = match parse_account_ref(&input) {
+            Ok((name, index)) => (name.to_string(), index),
+            Err(e) => { println!("{}", e); continue; }
+        };
+        if name.is_empty() {
+            name = prompt("New account name > ");
+            let passphrase = match prompt_new_passphrase() {
+                Some(p) => p,
+                None => continue,
+            };
+            match keystore.create(&name, &passphrase) {
+                Ok(address) => println!("Created account {} with address {}", name, address),
+                Err(e) => { println!("Failed to create account: {}", e); continue; }
+            }
+        }
+        match unlock_account(&keystore, &name, index) {
+            Ok(wallet) => break Some((account_label(&name, index), wallet)),
+            Err(e) => println!("Cannot unlock {}: {}", name, e),
+        }
     };
 
-    // 初始化本地 Wallet (仅包含密钥)
-    let wallet = Wallet::new(keypair);
-    let my_address = wallet.get_my_address();
-    println!("Wallet initialized. Address: {}", hex::encode(&my_address));
+    // 会话中可以继续解锁其他账户，转账默认从当前账户发出
+    let mut session = match unlocked {
+        Some((label, wallet)) => {
+            println!("Wallet {} unlocked. Address: {}", label, wallet.get_my_address());
+            Session::new(label, wallet)
+        }
+        None => {
+            println!("No account unlocked. Use unlock <name> before commands that sign.");
+            Session::default()
+        }
+    };
 
     let stdin = io::stdin();
     loop {
@@ -217,9 +641,42 @@ fn run_client(matches: &ArgMatches) {
         match parts[0] {
             "help" => {
                 println!("Commands:");
-                println!("  info                    - Show local wallet address");
-                println!("  balance                 - Query network for balance");
-                println!("  transfer <addr> <amt>   - Create & Sign & Submit Tx");
+                println!("  info                    - Show current account address");
+                println!("  balance [all]           - Query balance of current (or every unlocked) account");
+                println!("  accounts                - List accounts unlocked in this session");
+                println!("  unlock <name>[/index]   - Unlock another keystore account");
+                println!("  use <name>[/index]      - Switch the current account");
+                println!("  transfer <to> <amt> [gas_price] [--from <name>] [--after-height <h> | --after-time <secs>]");
+                println!("                          - Create & Sign & Submit Tx (fee estimated if omitted)");
+                println!("                            <to> is an address or an unlocked account name");
+                println!("                            a locked transfer waits in the mempool until the height/time");
+                println!("  batch-transfer <csv> [gas_price] [--from <name>]");
+                println!("                          - Pay every <to>,<amount> line of a CSV file in one transaction");
+                println!("  fee [target_blocks]     - Estimate gas price to confirm within N blocks");
+                println!("  tx build <to> <amt> [gas_price] --from <addr|name> --out <file>");
+                println!("                          - Write an unsigned transaction for offline signing");
+                println!("  tx review <file>        - Show an unsigned or signed transaction file");
+                println!("  tx sign <in> <out>      - Sign a transaction file (works offline)");
+                println!("  tx submit <file>        - Submit a signed transaction file");
+                println!("  tx proof <hash> <out>   - Save the proof that a transaction is in a block");
+                println!("  verify-proof <file> [block_hash]");
+                println!("                          - Check a proof offline against a block header");
+                println!("  multisig create <name> <m> <key|account>...");
+                println!("                          - Create an M-of-N policy from public keys or unlocked accounts");
+                println!("  multisig list           - List multisig policies and their addresses");
+                println!("  multisig sign <in> <out>- Add signatures of unlocked approvers");
+                println!("  multisig merge <out> <in>...");
+                println!("                          - Combine signatures collected separately");
+                println!("  multisig finalize <in> <out>");
+                println!("                          - Produce a signed file once the threshold is met");
+                println!("  wallet list             - List keystore accounts");
+                println!("  wallet create <name>    - Generate a new encrypted account");
+                println!("  wallet import <name>    - Encrypt an existing hex seed");
+                println!("  wallet export <name>    - Decrypt and show the hex seed or mnemonic");
+                println!("  wallet create-hd <name> - Generate a new mnemonic (HD) account");
+                println!("  wallet restore <name>   - Restore an HD account from its mnemonic");
+                println!("  wallet derive <name> <i>- Show the i-th address of an HD account");
+                println!("  wallet scan <name> [gap]- Find and unlock HD addresses with balance or history");
                 println!("  miner start <lambda>    - Control miner via API");
                 println!("  miner stop              - Pause mining");
                 println!("  miner update            - Force refresh block template");
@@ -227,24 +684,185 @@ fn run_client(matches: &ArgMatches) {
             }
             "exit" => break,
             "info" => {
-                println!("My Address: {}", hex::encode(&my_address));
+                let wallet = match session.current() {
+                    Ok(w) => w,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                println!("Account: {}", session.current_name().unwrap_or_default());
+                println!("My Address: {}", wallet.get_my_address());
+                println!("Public Key: {}", hex::encode(wallet.get_public_key_bytes()));
                 println!("(Copy this address to the server to receive mining rewards)");
             },
-            "balance" => {
-                // 1. 调用 API 查询我的账户状态
-                let url = format!("{}/blockchain/account?address={}", base_url, my_address);
-                match reqwest::blocking::get(&url) {
-                    Ok(resp) => {
-                        let acc_res: AccountResponse = resp.json().unwrap_or(AccountResponse { success: false, data: None });
-                        if let Some(info) = acc_res.data {
-                            println!("Balance: {}, Nonce: {}", info.balance, info.nonce);
-                        } else {
-                            println!("Account not found or empty.");
+            "balance" if parts.get(1) == Some(&"all") => {
+                // 汇总会话中所有账户的余额
+                let mut total: u64 = 0;
+                for (name, address) in session.addresses() {
+                    match fetch_account(&base_url, &address) {
+                        Ok(info) => {
+                            println!("  {:<20} {} Balance: {}, Nonce: {}", name, address, info.balance, info.nonce);
+                            total = total.saturating_add(info.balance);
                         }
-                    },
+                        Err(e) => println!("  {:<20} {} API Error: {}", name, address, e),
+                    }
+                }
+                println!("Total balance: {}", total);
+            },
+            "balance" => {
+                // 1. 调用 API 查询当前账户状态
+                let address = match session.current() {
+                    Ok(w) => w.get_my_address(),
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                match fetch_account(&base_url, &address) {
+                    Ok(info) => println!("Balance: {}, Nonce: {}", info.balance, info.nonce),
                     Err(e) => println!("API Error: {}", e),
                 }
             },
+            "accounts" => {
+                for (name, address) in session.addresses() {
+                    let marker = if session.current_name() == Some(name.as_str()) { "*" } else { " " };
+                    println!("{} {:<20} {}", marker, name, address);
+                }
+            }
+            "unlock" if parts.len() == 2 => {
+                let (name, index) = match parse_account_ref(parts[1]) {
+                    Ok(r) => r,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                let label = account_label(name, index);
+                if session.contains(&label) {
+                    println!("Account {} is already unlocked", label);
+                    continue;
+                }
+                match unlock_account(&keystore, name, index).and_then(|wallet| session.add(label.clone(), wallet)) {
+                    Ok(address) => println!("Unlocked {}: {}", label, address),
+                    Err(e) => println!("Cannot unlock {}: {}", label, e),
+                }
+            }
+            "use" if parts.len() == 2 => {
+                match session.use_account(parts[1]) {
+                    Ok(address) => println!("Using {}: {}", parts[1], address),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            "wallet" => {
+                let name = parts.get(2).copied().unwrap_or_default();
+                match parts.get(1).copied() {
+                    Some("list") => match keystore.list() {
+                        Ok(accounts) => print_accounts(&accounts, Some(&session)),
+                        Err(e) => println!("Cannot read keystore: {}", e),
+                    },
+                    Some("create-hd") if !name.is_empty() => {
+                        let mnemonic = match hd::generate_mnemonic() {
+                            Ok(m) => m,
+                            Err(e) => { println!("{}", e); continue; }
+                        };
+                        let passphrase = match prompt_new_passphrase() {
+                            Some(p) => p,
+                            None => continue,
+                        };
+                        match keystore.import_mnemonic(name, &mnemonic, &passphrase) {
+                            Ok(address) => {
+                                println!("Created HD account {} with first address {}", name, address);
+                                println!("[!] WRITE DOWN THIS MNEMONIC, it restores every address of the account:\n{}", mnemonic);
+                            }
+                            Err(e) => println!("Failed to create account: {}", e),
+                        }
+                    }
+                    Some("restore") if !name.is_empty() => {
+                        // 助记词不回显
+                        let phrase = rpassword::prompt_password("Mnemonic > ").unwrap();
+                        let mnemonic = match hd::parse_mnemonic(phrase.trim()) {
+                            Ok(m) => m,
+                            Err(e) => { println!("{}", e); continue; }
+                        };
+                        let passphrase = match prompt_new_passphrase() {
+                            Some(p) => p,
+                            None => continue,
+                        };
+                        match keystore.import_mnemonic(name, &mnemonic, &passphrase) {
+                            Ok(address) => println!("Restored HD account {} with first address {}", name, address),
+                            Err(e) => println!("Failed to restore account: {}", e),
+                        }
+                    }
+                    Some("derive") if !name.is_empty() => {
+                        let index: u32 = match parts.get(3).map(|i| i.parse()) {
+                            Some(Ok(i)) => i,
+                            _ => { println!("Usage: wallet derive <name> <index>"); continue; }
+                        };
+                        let passphrase = rpassword::prompt_password("Passphrase > ").unwrap();
+                        match keystore.unlock(name, &passphrase).and_then(|key| key.key_pair(index)) {
+                            Ok(key_pair) => println!("{}: {}", account_label(name, index), Wallet::new(key_pair).get_my_address()),
+                            Err(e) => println!("Failed to derive address: {}", e),
+                        }
+                    }
+                    Some("scan") if !name.is_empty() => {
+                        let gap_limit = match parts.get(3).map(|g| g.parse::<u32>()) {
+                            Some(Ok(g)) if g > 0 => g,
+                            Some(_) => { println!("Invalid gap limit"); continue; }
+                            None => hd::DEFAULT_GAP_LIMIT,
+                        };
+                        let passphrase = rpassword::prompt_password("Passphrase > ").unwrap();
+                        let master = match keystore.unlock(name, &passphrase) {
+                            Ok(wallet::keystore::UnlockedKey::Hd(master)) => master,
+                            Ok(_) => { println!("{} is not an HD account", name); continue; }
+                            Err(e) => { println!("Cannot unlock {}: {}", name, e); continue; }
+                        };
+                        println!("Scanning addresses (gap limit {})...", gap_limit);
+                        match hd::scan(&master, gap_limit, |address| account_used(&base_url, address)) {
+                            Ok(used) if used.is_empty() => println!("No used addresses found"),
+                            Ok(used) => {
+                                // 找到的地址直接加入会话
+                                for (index, address) in used {
+                                    let label = account_label(name, index);
+                                    let wallet = Wallet::new(master.derive_address_key(index).key_pair());
+                                    let status = if session.add(label.clone(), wallet).is_ok() { "unlocked" } else { "already unlocked" };
+                                    println!("  {}: {} ({})", label, address, status);
+                                }
+                            }
+                            Err(e) => println!("Scan failed: {}", e),
+                        }
+                    }
+                    Some("create") if !name.is_empty() => {
+                        let passphrase = match prompt_new_passphrase() {
+                            Some(p) => p,
+                            None => continue,
+                        };
+                        match keystore.create(name, &passphrase) {
+                            Ok(address) => println!("Created account {} with address {}", name, address),
+                            Err(e) => println!("Failed to create account: {}", e),
+                        }
+                    }
+                    Some("import") if !name.is_empty() => {
+                        // seed 不回显
+                        let seed_input = rpassword::prompt_password("Seed (hex) > ").unwrap();
+                        let seed = match hex::decode(seed_input.trim()) {
+                            Ok(s) => s,
+                            Err(_) => { println!("Invalid hex seed"); continue; }
+                        };
+                        let passphrase = match prompt_new_passphrase() {
+                            Some(p) => p,
+                            None => continue,
+                        };
+                        match keystore.import(name, &seed, &passphrase) {
+                            Ok(address) => println!("Imported account {} with address {}", name, address),
+                            Err(e) => println!("Failed to import account: {}", e),
+                        }
+                    }
+                    Some("export") if !name.is_empty() => {
+                        let passphrase = rpassword::prompt_password("Passphrase > ").unwrap();
+                        match keystore.export(name, &passphrase) {
+                            Ok((KeyKind::Ed25519, seed)) => println!("[!] SEED OF {} (keep it secret):\n{}", name, hex::encode(seed)),
+                            Ok((KeyKind::Mnemonic, entropy)) => match bip39::Mnemonic::from_entropy(&entropy) {
+                                Ok(mnemonic) => println!("[!] MNEMONIC OF {} (keep it secret):\n{}", name, mnemonic),
+                                Err(e) => println!("Corrupted mnemonic: {}", e),
+                            },
+                            Err(e) => println!("Failed to export account: {}", e),
+                        }
+                    }
+                    _ => println!("Usage: wallet <list|create|create-hd|import|restore|export|derive|scan> [name] [index|gap]"),
+                }
+            }
             "miner" => {
                 if parts.len() < 2 {
                     println!("Usage: miner <start|stop|update> [lambda]");
@@ -296,7 +914,7 @@ fn run_client(matches: &ArgMatches) {
                 let hash = parts[1];
                 let url = format!("{}/blockchain/block?hash={}", base_url, hash);
                 
-                match reqwest::blocking::get(&url) {
+                match reqwest::blocking::get(url) {
                     Ok(resp) => {
                         // 直接漂亮地打印 JSON
                         let json: serde_json::Value = resp.json().unwrap_or(serde_json::Value::Null);
@@ -309,63 +927,395 @@ fn run_client(matches: &ArgMatches) {
                     Err(e) => println!("Error: {}", e),
                 }
             }
+            "fee" => {
+                let target = match parts.get(1).map(|t| t.parse::<u64>()) {
+                    Some(Ok(t)) => t,
+                    Some(Err(_)) => { println!("Usage: fee [target_blocks]"); continue; }
+                    None => api::DEFAULT_FEE_TARGET_BLOCKS,
+                };
+                match fetch_fee_estimate(&base_url, target) {
+                    Some(est) => println!(
+                        "Gas price {} to confirm within {} blocks ({} blocks sampled, {} pending txs)",
+                        est.gas_price, est.target_blocks, est.sampled_blocks, est.pending_transactions
+                    ),
+                    None => println!("Failed to fetch fee estimate"),
+                }
+            }
             "transfer" => {
-                if parts.len() < 3 {
-                    println!("Usage: transfer <to_addr_hex> <amount>");
+                let mut args = parts[1..].to_vec();
+                let from = match take_flag(&mut args, "--from") {
+                    Ok(f) => f,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                let lock = match parse_lock(&mut args) {
+                    Ok(l) => l,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                if args.len() < 2 {
+                    println!("Usage: transfer <to> <amount> [gas_price] [--from <name>] [--after-height <h> | --after-time <unix_secs>]");
                     continue;
                 }
-                let to_hex = parts[1];
-                let amount: u64 = parts[2].parse().unwrap_or(0);
+                let amount: u64 = match args[1].parse() {
+                    Ok(a) => a,
+                    Err(_) => { println!("Invalid amount"); continue; }
+                };
 
                 // 解析目标地址
-                let to_bytes = match hex::decode(to_hex) {
-                    Ok(b) => b,
-                    Err(_) => { println!("Invalid hex address"); continue; }
-                };
-                let to_array: [u8; 20] = match to_bytes.try_into() {
+                let to_addr = match resolve_target(&session, &keystore, args[0]) {
                     Ok(a) => a,
-                    Err(_) => { println!("Address must be 20 bytes"); continue; }
+                    Err(e) => { println!("{}", e); continue; }
                 };
-                let to_addr = crate::types::address::Address::from(to_array);
+                let wallet = match session.get_mut(from) {
+                    Ok(w) => w,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                let from_address = wallet.get_my_address();
 
                 // 1. 获取当前 Nonce 和 Balance
-                println!("Fetching account state...");
-                let url = format!("{}/blockchain/account?address={}", base_url, my_address);
-                let acc_info = match reqwest::blocking::get(&url) {
-                    Ok(resp) => {
-                         let r: AccountResponse = resp.json().unwrap();
-                         r.data.unwrap_or(AccountInfo { nonce: 0, balance: 0 })
-                    },
-                    Err(_) => { println!("Failed to fetch account info"); continue; }
+                println!("Fetching account state of {}...", from_address);
+                let acc_info = match fetch_account(&base_url, &from_address) {
+                    Ok(info) => info,
+                    Err(e) => { println!("Failed to fetch account info: {}", e); continue; }
                 };
 
-                if acc_info.balance < amount {
-                    println!("Insufficient funds (Balance: {})", acc_info.balance);
+                let gas_price = match resolve_gas_price(&base_url, args.get(2).copied()) {
+                    Ok(p) => p,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+
+                let total_cost = amount.saturating_add(gas_price.saturating_mul(DEFAULT_GAS_LIMIT));
+                if acc_info.balance < total_cost {
+                    println!("Insufficient funds (Balance: {}, Needed: {})", acc_info.balance, total_cost);
                     continue;
                 }
 
+                let chain_id = match fetch_chain_id(&base_url) {
+                    Some(id) => id,
+                    None => { println!("Failed to fetch chain ID"); continue; }
+                };
+
+                // 链上 nonce 之后还可能有尚未打包的交易
+                let pending_nonces = match fetch_pending_nonces(&base_url, &from_address) {
+                    Some(n) => n,
+                    None => { println!("Failed to fetch pending transactions"); continue; }
+                };
+                let nonce = wallet.next_nonce(acc_info.nonce, &pending_nonces);
+
                 // 2. 本地构造并签名交易
                 println!("Signing transaction...");
-                let signed_tx = wallet.create_signed_transaction(
-                    to_addr,
-                    amount,
-                    1, // price
-                    10, // limit
-                    acc_info.nonce // 使用网络上的 nonce
-                );
+                let signed_tx = match lock {
+                    Some(lock) => {
+                        let tx = Transaction::new(chain_id, nonce, gas_price, DEFAULT_GAS_LIMIT, to_addr, amount, vec![]);
+                        println!("Transaction cannot be mined before {}", lock);
+                        wallet.sign_transaction(tx.with_lock(lock))
+                    }
+                    None => wallet.create_signed_transaction(
+                        chain_id,
+                        to_addr,
+                        amount,
+                        gas_price,
+                        DEFAULT_GAS_LIMIT,
+                        nonce,
+                    ),
+                };
 
                 // 3. 提交签名后的交易
                 println!("Submitting transaction...");
-                let client = reqwest::blocking::Client::new();
-                let res = client.post(format!("{}/transaction/submit", base_url))
-                    .json(&signed_tx) // 直接发送 SignedTransaction 对象
-                    .send();
-
-                match res {
-                    Ok(resp) => println!("Response: {}", resp.text().unwrap_or_default()),
-                    Err(e) => println!("Submission Failed: {}", e),
+                match submit_transaction(&base_url, &signed_tx) {
+                    Ok(message) => {
+                        wallet.mark_pending(nonce);
+                        println!("Submitted with nonce {}: {}", nonce, message);
+                    }
+                    Err(e) => println!("{}", e),
                 }
             }
+            "batch-transfer" => {
+                let mut args = parts[1..].to_vec();
+                let from = match take_flag(&mut args, "--from") {
+                    Ok(f) => f,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                if args.is_empty() {
+                    println!("Usage: batch-transfer <csv> [gas_price] [--from <name>]");
+                    continue;
+                }
+                let content = match std::fs::read_to_string(args[0]) {
+                    Ok(c) => c,
+                    Err(e) => { println!("Cannot read {}: {}", args[0], e); continue; }
+                };
+                let outputs = match batch::parse_csv(&content, |to| resolve_target(&session, &keystore, to)) {
+                    Ok(o) => o,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+
+                let wallet = match session.get_mut(from) {
+                    Ok(w) => w,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                let from_address = wallet.get_my_address();
+                let acc_info = match fetch_account(&base_url, &from_address) {
+                    Ok(info) => info,
+                    Err(e) => { println!("Failed to fetch account info: {}", e); continue; }
+                };
+                let gas_price = match resolve_gas_price(&base_url, args.get(1).copied()) {
+                    Ok(p) => p,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                let (chain_id, pending_nonces) = match (fetch_chain_id(&base_url), fetch_pending_nonces(&base_url, &from_address)) {
+                    (Some(id), Some(n)) => (id, n),
+                    _ => { println!("Failed to fetch chain state"); continue; }
+                };
+                let nonce = wallet.next_nonce(acc_info.nonce, &pending_nonces);
+
+                let signed_tx = wallet.create_signed_batch(chain_id, outputs, gas_price, nonce);
+                let tx = &signed_tx.transaction;
+                let total_cost = tx.max_cost().unwrap_or(u64::MAX);
+                if acc_info.balance < total_cost {
+                    println!("Insufficient funds (Balance: {}, Needed: {})", acc_info.balance, total_cost);
+                    continue;
+                }
+                println!("{}", offline::review(&from_address, tx));
+                if !prompt("Submit this batch? [y/N] ").eq_ignore_ascii_case("y") {
+                    println!("Cancelled");
+                    continue;
+                }
+
+                match submit_transaction(&base_url, &signed_tx) {
+                    Ok(message) => {
+                        wallet.mark_pending(nonce);
+                        println!("Submitted with nonce {}: {}", nonce, message);
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            // 不访问网络，区块哈希需要从可信来源获得
+            "verify-proof" if parts.len() == 2 || parts.len() == 3 => {
+                let proof: TransactionProof = match std::fs::read_to_string(parts[1])
+                    .map_err(|e| e.to_string())
+                    .and_then(|c| serde_json::from_str(&c).map_err(|e| format!("Invalid proof file: {}", e)))
+                {
+                    Ok(p) => p,
+                    Err(e) => { println!("{}", e); continue; }
+                };
+                let block_hash = proof.block_hash().to_string();
+                match (proof.verify(), parts.get(2)) {
+                    (Err(e), _) => println!("INVALID: {}", e),
+                    (Ok(()), Some(expected)) if !expected.eq_ignore_ascii_case(&block_hash) => {
+                        println!("INVALID: proof is for block {}, not {}", block_hash, expected);
+                    }
+                    (Ok(()), expected) => {
+                        println!("Transaction {} is #{} of {} in block {}", proof.tx_hash, proof.index, proof.leaf_count, block_hash);
+                        if expected.is_none() {
+                            println!("Check that this block hash is on the chain you trust.");
+                        }
+                    }
+                }
+            }
+            // 离线签名: 在线机器 build/submit，离线机器 sign
+            "tx" => match parts.get(1).copied() {
+                Some("build") => {
+                    let mut args = parts[2..].to_vec();
+                    let (from, out) = match (take_flag(&mut args, "--from"), take_flag(&mut args, "--out")) {
+                        (Ok(Some(from)), Ok(Some(out))) => (from, out),
+                        _ => { println!("Usage: tx build <to> <amount> [gas_price] --from <addr|name> --out <file>"); continue; }
+                    };
+                    if args.len() < 2 {
+                        println!("Usage: tx build <to> <amount> [gas_price] --from <addr|name> --out <file>");
+                        continue;
+                    }
+                    let (from_address, to_addr) = match (resolve_target(&session, &keystore, from), resolve_target(&session, &keystore, args[0])) {
+                        (Ok(f), Ok(t)) => (f, t),
+                        (Err(e), _) | (_, Err(e)) => { println!("{}", e); continue; }
+                    };
+                    let amount: u64 = match args[1].parse() {
+                        Ok(a) => a,
+                        Err(_) => { println!("Invalid amount"); continue; }
+                    };
+
+                    let acc_info = match fetch_account(&base_url, &from_address) {
+                        Ok(info) => info,
+                        Err(e) => { println!("Failed to fetch account info: {}", e); continue; }
+                    };
+                    let gas_price = match resolve_gas_price(&base_url, args.get(2).copied()) {
+                        Ok(p) => p,
+                        Err(e) => { println!("{}", e); continue; }
+                    };
+                    let (chain_id, pending_nonces) = match (fetch_chain_id(&base_url), fetch_pending_nonces(&base_url, &from_address)) {
+                        (Some(id), Some(n)) => (id, n),
+                        _ => { println!("Failed to fetch chain state"); continue; }
+                    };
+                    let nonce = first_free_nonce(acc_info.nonce, &pending_nonces.into_iter().collect());
+
+                    // 多签交易的每个签名都要付 gas，按阈值个签名预留
+                    let signatures = keystore.list_policies().unwrap_or_default().into_iter()
+                        .find(|(_, policy)| policy.address() == from_address)
+                        .map_or(0, |(_, policy)| policy.threshold as u64);
+                    let gas_limit = DEFAULT_GAS_LIMIT + signatures * TX_SIGNATURE_GAS;
+                    let tx = Transaction::new(chain_id, nonce, gas_price, gas_limit, to_addr, amount, vec![]);
+                    if tx.max_cost().is_none_or(|cost| acc_info.balance < cost) {
+                        println!("Warning: balance {} does not cover the maximum cost", acc_info.balance);
+                    }
+                    let request = UnsignedRequest::new(from_address, tx);
+                    match offline::save(out, &request) {
+                        Ok(()) => {
+                            println!("Unsigned transaction written to {}:\n{}", out, request.review());
+                            println!("Sign it on the offline machine with: tx sign {} <signed_file>", out);
+                        }
+                        Err(e) => println!("Failed to write {}: {}", out, e),
+                    }
+                }
+                Some("review") if parts.len() == 3 => {
+                    match (offline::load_signed(parts[2]), offline::load_unsigned(parts[2])) {
+                        (Ok(signed), _) => println!("Signed transaction:\n{}", signed.review()),
+                        (_, Ok(unsigned)) => println!("Unsigned transaction:\n{}", unsigned.review()),
+                        (Err(e), _) => println!("{}", e),
+                    }
+                }
+                Some("sign") if parts.len() == 4 => {
+                    // 不访问网络，可以在离线机器上运行
+                    let request = match offline::load_unsigned(parts[2]) {
+                        Ok(r) => r,
+                        Err(e) => { println!("{}", e); continue; }
+                    };
+                    let wallet = match session.find(&request.from) {
+                        Some((_, wallet)) => wallet,
+                        None => { println!("Account {} is not unlocked in this session", request.from); continue; }
+                    };
+                    println!("Review the transaction before signing:\n{}", request.review());
+                    if prompt("Sign this transaction? [y/N] ").to_lowercase() != "y" {
+                        println!("Aborted");
+                        continue;
+                    }
+                    match request.sign(wallet).and_then(|signed| offline::save(parts[3], &signed)) {
+                        Ok(()) => println!("Signed transaction written to {}", parts[3]),
+                        Err(e) => println!("Failed to sign: {}", e),
+                    }
+                }
+                Some("submit") if parts.len() == 3 => {
+                    let request = match offline::load_signed(parts[2]) {
+                        Ok(r) => r,
+                        Err(e) => { println!("{}", e); continue; }
+                    };
+                    println!("Submitting:\n{}", request.review());
+                    let tx = &request.signed;
+                    match submit_transaction(&base_url, tx) {
+                        Ok(message) => {
+                            if let Some((_, wallet)) = session.find(&tx.sender_address()) {
+                                wallet.mark_pending(tx.transaction.nonce);
+                            }
+                            println!("{}", message);
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                Some("proof") if parts.len() == 4 => {
+                    let proof = match fetch_transaction_proof(&base_url, parts[2]) {
+                        Ok(p) => p,
+                        Err(e) => { println!("{}", e); continue; }
+                    };
+                    if let Err(e) = proof.verify() {
+                        println!("Node returned an invalid proof: {}", e);
+                        continue;
+                    }
+                    match offline::save(parts[3], &proof) {
+                        Ok(()) => println!("Proof for block {} written to {}", proof.block_hash(), parts[3]),
+                        Err(e) => println!("Failed to write {}: {}", parts[3], e),
+                    }
+                }
+                _ => println!("Usage: tx <build|review|sign|submit|proof> ..."),
+            },
+            // 多签: 策略地址由 tx build 构造交易，签名人依次 sign (或分别 sign 后 merge)，最后 finalize 并 tx submit
+            "multisig" => match parts.get(1).copied() {
+                Some("create") if parts.len() >= 5 => {
+                    let name = parts[2];
+                    let threshold: u32 = match parts[3].parse() {
+                        Ok(t) => t,
+                        Err(_) => { println!("Invalid threshold"); continue; }
+                    };
+                    // 公钥可以是 hex，也可以是会话中的账户名
+                    let keys: Result<Vec<Vec<u8>>, String> = parts[4..].iter()
+                        .map(|k| match session.wallets().find(|(name, _)| name == k) {
+                            Some((_, wallet)) => Ok(wallet.get_public_key_bytes()),
+                            None => hex::decode(k).map_err(|_| format!("Unknown account or invalid public key {}", k)),
+                        })
+                        .collect();
+                    match keys.and_then(|keys| MultisigPolicy::new(threshold, keys)) {
+                        Ok(policy) => match keystore.save_policy(name, &policy) {
+                            Ok(()) => println!("Created {}-of-{} policy {} with address {}", policy.threshold, policy.public_keys.len(), name, policy.address()),
+                            Err(e) => println!("Failed to save policy: {}", e),
+                        },
+                        Err(e) => println!("Invalid policy: {}", e),
+                    }
+                }
+                Some("list") => match keystore.list_policies() {
+                    Ok(policies) => {
+                        for (name, policy) in policies {
+                            println!("  {:<16} {}-of-{} {}", name, policy.threshold, policy.public_keys.len(), policy.address());
+                        }
+                    }
+                    Err(e) => println!("Cannot read policies: {}", e),
+                },
+                Some("sign") if parts.len() == 4 => {
+                    // 输入可以是 tx build 的未签名文件，也可以是已有部分签名的文件
+                    let mut request = match (offline::load_multisig(parts[2]), offline::load_unsigned(parts[2])) {
+                        (Ok(r), _) => r,
+                        (_, Ok(unsigned)) => {
+                            let policy = keystore.list_policies().unwrap_or_default().into_iter()
+                                .map(|(_, policy)| policy)
+                                .find(|policy| policy.address() == unsigned.from);
+                            match policy.map(|policy| MultisigRequest::from_unsigned(&unsigned, policy)) {
+                                Some(Ok(r)) => r,
+                                Some(Err(e)) => { println!("{}", e); continue; }
+                                None => { println!("No local policy for {}", unsigned.from); continue; }
+                            }
+                        }
+                        (Err(e), _) => { println!("{}", e); continue; }
+                    };
+                    println!("Review the transaction before signing:\n{}", request.review());
+                    if prompt("Sign this transaction? [y/N] ").to_lowercase() != "y" {
+                        println!("Aborted");
+                        continue;
+                    }
+                    // 用会话中所有属于该策略的账户签名
+                    let mut signers = Vec::new();
+                    for (name, wallet) in session.wallets() {
+                        if request.sign(wallet).is_ok() {
+                            signers.push(name.clone());
+                        }
+                    }
+                    if signers.is_empty() {
+                        println!("No unlocked account is a signer of this policy");
+                        continue;
+                    }
+                    match offline::save(parts[3], &request) {
+                        Ok(()) => println!(
+                            "Signed by {}. {}/{} signatures written to {}",
+                            signers.join(", "), request.proof.signatures.len(), request.proof.policy.threshold, parts[3]
+                        ),
+                        Err(e) => println!("Failed to write {}: {}", parts[3], e),
+                    }
+                }
+                Some("merge") if parts.len() >= 5 => {
+                    let mut files = parts[3..].iter().map(offline::load_multisig);
+                    let merged = files.next().expect("at least two inputs").and_then(|mut request| {
+                        for other in files {
+                            request.merge(&other?)?;
+                        }
+                        Ok(request)
+                    });
+                    match merged.and_then(|request| offline::save(parts[2], &request).map(|_| request)) {
+                        Ok(request) => println!("{}/{} signatures written to {}", request.proof.signatures.len(), request.proof.policy.threshold, parts[2]),
+                        Err(e) => println!("Failed to merge: {}", e),
+                    }
+                }
+                Some("finalize") if parts.len() == 4 => {
+                    match offline::load_multisig(parts[2]).and_then(|r| r.finalize()).and_then(|signed| offline::save(parts[3], &signed)) {
+                        Ok(()) => println!("Signed transaction written to {}, submit it with: tx submit {}", parts[3], parts[3]),
+                        Err(e) => println!("Failed to finalize: {}", e),
+                    }
+                }
+                _ => println!("Usage: multisig <create|list|sign|merge|finalize> ..."),
+            },
             _ => println!("Unknown command."),
         }
     }
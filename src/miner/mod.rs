@@ -1,23 +1,30 @@
 pub mod worker;
 
-use log::info;
+use log::{error, info};
 use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
 use std::time::{self, SystemTime, UNIX_EPOCH};
 use std::thread;
 use crate::types::block::Block;
 use crate::blockchain::Blockchain;
 use crate::types::hash::{Hashable, H256};
-use rand::Rng;
 use std::sync::{Arc, Mutex};
-use crate::types::merkle::MerkleTree;
 use crate::types::mempool::Mempool;
-use std::collections::HashMap;
 use crate::types::address::Address;
-use crate::types::transaction::{Transaction, SignedTransaction};
-use crate::types::state_trie::{StateTrie, Node};
-use crate::blockchain::Account; // 引入 Account
+use crate::types::transaction::Transaction;
+use crate::blockchain::{ExecutedState, StateTransition};
 
 pub const BLOCK_REWARD: u64 = 50;
+/// 单个区块中所有交易 gas_used 之和的上限
+pub const BLOCK_GAS_LIMIT: u64 = 10_000;
+/// 区块序列化后的最大字节数
+pub const MAX_BLOCK_SIZE: u64 = 1 << 20;
+/// 单个区块最多包含的交易数
+pub const MAX_BLOCK_TRANSACTIONS: usize = 1_000;
+/// 区块时间戳最多可以超前本地时钟的毫秒数
+pub const MAX_FUTURE_BLOCK_TIME: u128 = 2 * 60 * 60 * 1000;
+
+/// 挖出的区块及执行它得到的新状态
+pub type FinishedBlock = (Block, ExecutedState);
 
 enum ControlSignal {
     Start(u64), // the number controls the lambda of interval between block generation
@@ -37,7 +44,7 @@ pub struct Context {
     /// Channel for receiving control signal
     control_chan: Receiver<ControlSignal>,
     operating_state: OperatingState,
-    finished_block_chan: Sender<(Block, HashMap<H256, Node>)>,
+    finished_block_chan: Sender<FinishedBlock>,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
     miner_address: Address, 
@@ -49,13 +56,18 @@ pub struct Handle {
     control_chan: Sender<ControlSignal>,
 }
 
+/// 当前 Unix 时间 (毫秒)，区块时间戳使用该单位
+pub fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
+
 pub fn new(
     blockchain: &Arc<Mutex<Blockchain>>,
     mempool: &Arc<Mutex<Mempool>>,
     miner_address: Address
 ) -> (Context, 
       Handle, 
-      Receiver<(Block, HashMap<H256, Node>)>
+      Receiver<FinishedBlock>
      ) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
     let (finished_block_sender, finished_block_receiver) = unbounded();
@@ -181,21 +193,32 @@ impl Context {
                 return;
             }
 
-            let (parent_hash, difficulty, parent_state_root, storage) = {
+            let (parent_hash, height, difficulty, parent_state, parent_timestamp, chain_id) = {
                 let chain = self.blockchain.lock().unwrap();
                 let tip = chain.tip();
                 let block = chain.get_block(&tip).unwrap(); 
-                (tip, chain.get_difficulty(), block.state_root, chain.storage.clone())
+                (tip, chain.get_height(&tip) + 1, chain.get_difficulty(), chain.state_at(&tip), block.get_timestamp(), chain.chain_id)
+            };
+            let state_trie = match parent_state {
+                Ok(state) => state,
+                Err(e) => {
+                    error!("Cannot build block template: {}", e);
+                    self.operating_state = OperatingState::Paused;
+                    continue;
+                }
             };
 
-            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+            // 时间戳必须晚于父块
+            let block_time = || now_millis().max(parent_timestamp + 1);
+            let timestamp = block_time();
             
 
-            let state_trie = StateTrie::new_from_root(parent_state_root, storage.clone());
-            
-            let mut transactions = {
+            let mut transition = StateTransition::new(&state_trie);
+
+            let transactions = {
                 let mempool = self.mempool.lock().unwrap();
-                let mut all_txs = mempool.select_transactions();
+                // 尚未解锁的交易留在 Mempool 中，之后的 nonce 也会因不连续被跳过
+                let mut all_txs = mempool.select_unlocked(height, timestamp);
 
                 all_txs.sort_by(|a, b| {
                     let sender_a = a.sender_address();
@@ -208,71 +231,64 @@ impl Context {
                 });
 
                 let mut valid_txs = Vec::new();
-                
-                let mut temp_state: HashMap<Address, (u64, u64)> = HashMap::new(); 
+                let mut block_gas: u64 = 0;
+                // 不含交易的区块大小是固定的，每笔交易按其序列化大小累加
+                let mut block_size = Block::new(
+                    parent_hash, 0, difficulty, timestamp, H256::default(), Transaction::default(), vec![]
+                ).serialized_size();
                 
                 for tx in all_txs {
-                    let sender = tx.sender_address();
-                    let total_cost = tx.transaction.value + tx.transaction.gas_price * tx.transaction.gas_limit;
-
-
-                    let (curr_nonce, curr_balance) = temp_state.entry(sender).or_insert_with(|| {
-                        state_trie.get(&sender)
-                            .map(|acc| (acc.nonce, acc.balance))
-                            .unwrap_or((0, 0))
-                    });
+                    if valid_txs.len() >= MAX_BLOCK_TRANSACTIONS {
+                        break;
+                    }
+                    if tx.validate(chain_id).is_err() {
+                        continue;
+                    }
+                    let gas_used = tx.gas_used();
+                    if block_gas + gas_used > BLOCK_GAS_LIMIT {
+                        continue;
+                    }
+                    let tx_size = bincode::serialized_size(&tx).expect("Serialization failed");
+                    if block_size + tx_size > MAX_BLOCK_SIZE {
+                        continue;
+                    }
 
-                    if tx.transaction.nonce == *curr_nonce && *curr_balance >= total_cost {
+                    // nonce 不连续、余额不足或溢出的交易直接跳过
+                    if transition.apply_transaction(&tx).is_ok() {
+                        block_gas += gas_used;
+                        block_size += tx_size;
                         valid_txs.push(tx);
-                        *curr_nonce += 1;
-                        *curr_balance -= total_cost;
-                    } else if tx.transaction.nonce > *curr_nonce {
-                        continue;
                     }
                 }
                 valid_txs
             };
 
-            let mut total_fee: u64 = 0;
-            let mut account_updates: HashMap<Address, Account> = HashMap::new();
-
-            for tx in &transactions {
-                let fee = tx.transaction.gas_price * tx.transaction.gas_limit;
-                total_fee += fee;
-
-                let sender_addr = tx.sender_address();
-                let receiver_addr = tx.transaction.to;
-
-                let mut sender_acc = account_updates.get(&sender_addr).cloned()
-                    .unwrap_or_else(|| state_trie.get(&sender_addr).unwrap_or_default());
-
-                sender_acc.balance -= (tx.transaction.value + fee);
-                sender_acc.nonce += 1;
-                account_updates.insert(sender_addr, sender_acc);
-
-                let mut receiver_acc = account_updates.get(&receiver_addr).cloned()
-                    .unwrap_or_else(|| state_trie.get(&receiver_addr).unwrap_or_default());
-                
-                receiver_acc.balance += tx.transaction.value;
-                account_updates.insert(receiver_addr, receiver_acc);
-            }
-
-            let total_reward = BLOCK_REWARD + total_fee;
+            // 奖励记在 value 字段，与 execute_block 的校验一致
+            let total_reward = transition.block_reward();
             let coinbase = Transaction::new(
+                chain_id,
                 0,                  
-                total_reward,       
                 0,                  
-                self.miner_address, 
                 0,                  
+                self.miner_address, 
+                total_reward,       
                 vec![]              
             );
 
-            let mut miner_account = account_updates.get(&self.miner_address).cloned()
-                .unwrap_or_else(|| state_trie.get(&self.miner_address).unwrap_or_default());
-            miner_account.balance += total_reward;
-            account_updates.insert(self.miner_address, miner_account);
+            if let Err(e) = transition.apply_coinbase(self.miner_address, total_reward) {
+                error!("Cannot build block template: {}", e);
+                self.operating_state = OperatingState::Paused;
+                continue;
+            }
 
-            let (final_state_root, new_nodes) = state_trie.insert_batch(account_updates);
+            let executed = match transition.commit() {
+                Ok(executed) => executed,
+                Err(e) => {
+                    error!("Cannot build block template: {}", e);
+                    self.operating_state = OperatingState::Paused;
+                    continue;
+                }
+            };
 
          
             let mut block_template = Block::new(
@@ -280,7 +296,7 @@ impl Context {
                 0, 
                 difficulty,
                 timestamp,
-                final_state_root,
+                executed.state_root,
                 coinbase,
                 transactions, 
             );
@@ -288,7 +304,7 @@ impl Context {
             let mut mined = false;
             loop {
                 if block_template.hash() <= difficulty {
-                    self.finished_block_chan.send((block_template.clone(), new_nodes.clone())).expect("Send finished block error");
+                    self.finished_block_chan.send((block_template.clone(), executed.clone())).expect("Send finished block error");
                     info!("Mined a block: {}", block_template.hash());
                     mined = true;
                     break; 
@@ -297,15 +313,13 @@ impl Context {
                 let new_nonce = block_template.get_nonce().wrapping_add(1);
                 block_template.set_nonce(&new_nonce);
                 
-                if new_nonce % 10000000 == 0 {
-                    block_template.set_timestamp(&SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis());
+                if new_nonce.is_multiple_of(10000000) {
+                    block_template.set_timestamp(&block_time());
                 }
 
-                if new_nonce % 10000 == 0 {
-                    if !self.control_chan.is_empty() {
-                        info!("Signal received, interrupting mining...");
-                        break; 
-                    }
+                if new_nonce.is_multiple_of(10000) && !self.control_chan.is_empty() {
+                    info!("Signal received, interrupting mining...");
+                    break; 
                 }
             }
 
@@ -317,10 +331,110 @@ impl Context {
 
             if let OperatingState::Run(i) = self.operating_state {
                 if i != 0 {
-                    let interval = time::Duration::from_micros(i as u64);
+                    let interval = time::Duration::from_micros(i);
                     thread::sleep(interval);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::blockchain::Blockchain;
+    use crate::blockchain::genesis::GenesisConfig;
+    use crate::database::Storage;
+    use crate::types::address::Address;
+    use crate::types::hash::H256;
+    use crate::types::key_pair;
+    use crate::types::mempool::Mempool;
+    use crate::types::hash::Hashable;
+    use crate::types::transaction::{Transaction, TxLock, TX_BASE_GAS};
+    use crate::wallet::wallet::Wallet;
+    use super::BLOCK_REWARD;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn wallet_transfers_are_mined() {
+        let mut wallet = Wallet::new(key_pair::random());
+        let sender = wallet.get_my_address();
+        let receiver = Address::from([1u8; 20]);
+        let miner_address = Address::from([2u8; 20]);
+        // 任意哈希都满足难度，测试中可以立即出块
+        let genesis = GenesisConfig {
+            difficulty: H256::from([255u8; 32]),
+            allocations: vec![(sender, 1_000)],
+            ..GenesisConfig::default()
+        };
+        let blockchain = Arc::new(Mutex::new(Blockchain::from_storage(Arc::new(Storage::new_temporary()), &genesis).unwrap()));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+
+        // 连续两笔转账，无需等待第一笔确认
+        for value in [100, 200] {
+            let nonce = wallet.next_nonce(0, &[]);
+            let tx = wallet.create_signed_transaction(genesis.chain_id, receiver, value, 2, TX_BASE_GAS, nonce);
+            mempool.lock().unwrap().insert(tx);
+            wallet.mark_pending(nonce);
+        }
+
+        let (ctx, handle, finished_block_chan) = super::new(&blockchain, &mempool, miner_address);
+        ctx.start();
+        handle.start(0);
+        let (block, _) = finished_block_chan.recv_timeout(Duration::from_secs(30)).expect("No block mined");
+        handle.exit();
+        assert_eq!(block.data.len(), 2);
+
+        let mut chain = blockchain.lock().unwrap();
+        let executed = Blockchain::execute_block(chain.storage.clone(), &block).unwrap();
+        chain.commit_block(&block, executed.new_nodes);
+
+        let fee = 2 * 2 * TX_BASE_GAS;
+        assert_eq!(chain.get_account(&receiver).unwrap().balance, 300);
+        assert_eq!(chain.get_account(&sender).unwrap().balance, 1_000 - 300 - fee);
+        assert_eq!(chain.get_account(&sender).unwrap().nonce, 2);
+        assert_eq!(chain.get_account(&miner_address).unwrap().balance, BLOCK_REWARD + fee);
+        assert_eq!(wallet.next_nonce(2, &[]), 2);
+    }
+
+    #[test]
+    fn height_locked_transfer_waits() {
+        let wallet = Wallet::new(key_pair::random());
+        let receiver = Address::from([1u8; 20]);
+        let genesis = GenesisConfig {
+            difficulty: H256::from([255u8; 32]),
+            allocations: vec![(wallet.get_my_address(), 1_000)],
+            ..GenesisConfig::default()
+        };
+        let blockchain = Arc::new(Mutex::new(Blockchain::from_storage(Arc::new(Storage::new_temporary()), &genesis).unwrap()));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let t = Transaction::new(genesis.chain_id, 0, 1, TX_BASE_GAS, receiver, 100, vec![]);
+        let locked = wallet.sign_transaction(t.with_lock(TxLock::Height(2)));
+        mempool.lock().unwrap().insert(locked.clone());
+
+        let (ctx, handle, finished_block_chan) = super::new(&blockchain, &mempool, Address::default());
+        ctx.start();
+        handle.start(0);
+        let (mut block, _) = finished_block_chan.recv_timeout(Duration::from_secs(30)).expect("No block mined");
+        assert!(block.data.is_empty());
+        {
+            let mut chain = blockchain.lock().unwrap();
+            // 在高度 1 打包锁定的交易会被拒绝
+            let mut premature = block.clone();
+            premature.data.push(locked.clone());
+            let err = Blockchain::execute_block(chain.storage.clone(), &premature).unwrap_err();
+            assert!(err.contains("locked"), "{}", err);
+
+            let executed = Blockchain::execute_block(chain.storage.clone(), &block).unwrap();
+            chain.commit_block(&block, executed.new_nodes);
+        }
+        handle.update();
+        while block.data.is_empty() {
+            block = finished_block_chan.recv_timeout(Duration::from_secs(30)).expect("No block mined").0;
+        }
+        handle.exit();
+        assert_eq!(block.data[0].hash(), locked.hash());
+        let chain = blockchain.lock().unwrap();
+        assert!(Blockchain::execute_block(chain.storage.clone(), &block).is_ok());
+    }
+}
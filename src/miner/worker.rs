@@ -1,6 +1,5 @@
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
-use log::{debug, info};
-use crate::types::block::Block;
+use crossbeam::channel::Receiver;
+use log::info;
 use crate::network::server::Handle as ServerHandle;
 use std::thread;
 use crate::blockchain::Blockchain;
@@ -8,30 +7,30 @@ use std::sync::{Arc, Mutex};
 use crate::types::hash::{H256, Hashable};
 
 use crate::network::message::Message::NewBlockHashes;
-use crate::network::peer;
 use crate::types::mempool::Mempool;
-use crate::miner::Handle;
+use crate::types::fee_estimator::FeeEstimator;
+use crate::miner::{Handle, FinishedBlock};
 
-use std::collections::HashMap;
-use crate::types::state_trie::Node;
 
 
 
 #[derive(Clone)]
 pub struct Worker {
     server: ServerHandle,
-    finished_block_chan: Receiver<(Block, HashMap<H256, Node>)>,
+    finished_block_chan: Receiver<FinishedBlock>,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
+    fee_estimator: Arc<Mutex<FeeEstimator>>,
     miner: Handle,
 }
 
 impl Worker {
     pub fn new(
         server: &ServerHandle,
-        finished_block_chan: Receiver<(Block, HashMap<H256, Node>)>,
+        finished_block_chan: Receiver<FinishedBlock>,
         blockchain: &Arc<Mutex<Blockchain>>,
         mempool: &Arc<Mutex<Mempool>>,
+        fee_estimator: &Arc<Mutex<FeeEstimator>>,
         miner: &Handle,
     ) -> Self {
         Self {
@@ -39,6 +38,7 @@ impl Worker {
             finished_block_chan,
             blockchain: blockchain.clone(),
             mempool: mempool.clone(),
+            fee_estimator: fee_estimator.clone(),
             miner: miner.clone(),
         }
     }
@@ -55,14 +55,15 @@ impl Worker {
 
     fn worker_loop(&self) {
         loop {
-            let (block, new_nodes) = self.finished_block_chan.recv().expect("Receive finished block error");
+            let (block, executed) = self.finished_block_chan.recv().expect("Receive finished block error");
             
             // TODO for student: insert this finished block to blockchain, and broadcast this block hash
             {
                 self.server.broadcast(NewBlockHashes(vec![block.hash()]));
                 {
                     let mut chain = self.blockchain.lock().unwrap();
-                    chain.commit_block(&block, new_nodes);
+                    chain.commit_block(&block, executed.new_nodes);
+                    self.fee_estimator.lock().unwrap().observe_committed_block(&chain, &block);
                 }
                 {
                     let mut mempool = self.mempool.lock().unwrap();
@@ -0,0 +1,134 @@
+use super::message::Message;
+use super::peer;
+use super::server::Handle as ServerHandle;
+use super::worker::MAX_HEADERS_PER_MESSAGE;
+use crate::blockchain::Blockchain;
+use crate::blockchain::genesis::GenesisConfig;
+use crate::blockchain::snapshot::StateRestore;
+use crate::light::HeaderChain;
+use crate::types::hash::H256;
+use log::{debug, info, warn};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 快照区块距离区块头 tip 的高度，避免选中可能被回滚的区块
+pub const SNAPSHOT_CONFIRMATIONS: u64 = 6;
+/// 只提供和请求主链上高度为其倍数的区块的快照，与剪枝的默认检查点间隔一致
+pub const SNAPSHOT_INTERVAL: u64 = 1000;
+/// 这么久没有新的区块头即认为已同步到对方的 tip
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+/// 等待快照清单或单个分块的时间，对方第一次构建快照需要遍历整个状态树
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 从对等节点的快照启动，返回快照区块的高度。
+/// 先同步区块头，选出主链上已有足够确认的区块，再逐块下载并校验其状态。
+/// 在网络 worker 启动前运行，期间直接消费消息通道
+pub fn run(
+    server: &ServerHandle,
+    msg_rx: &smol::channel::Receiver<(Vec<u8>, peer::Handle)>,
+    blockchain: &Arc<Mutex<Blockchain>>,
+    genesis: &GenesisConfig,
+) -> Result<u64, String> {
+    let mut headers = HeaderChain::new(genesis);
+    server.broadcast(Message::GetHeaders(headers.locator()));
+    let mut deadline = Instant::now() + HEADER_TIMEOUT;
+    while let Some((msg, mut peer)) = recv_until(msg_rx, deadline) {
+        match msg {
+            Message::Headers(list) => {
+                let count = list.len();
+                for header in list {
+                    if let Err(e) = headers.insert(header) {
+                        warn!("Invalid header from peer: {}", e);
+                        break;
+                    }
+                }
+                if count == MAX_HEADERS_PER_MESSAGE {
+                    peer.write(Message::GetHeaders(headers.locator()));
+                }
+                deadline = Instant::now() + HEADER_TIMEOUT;
+            }
+            other => answer_ping(other, &mut peer),
+        }
+    }
+
+    let height = snapshot_height(headers.height()).ok_or("Peers' chain is too short for a snapshot")?;
+    let target = headers.canonical_at(height).unwrap();
+    info!("Synced {} headers, requesting state snapshot of block {} at height {}", headers.height(), target, height);
+
+    server.broadcast(Message::GetSnapshotManifest(target));
+    let deadline = Instant::now() + SNAPSHOT_TIMEOUT;
+    let (manifest, mut peer) = loop {
+        let (msg, mut peer) = recv_until(msg_rx, deadline).ok_or("No peer offered a snapshot")?;
+        match msg {
+            Message::SnapshotManifest(hash, Some(manifest)) if hash == target => {
+                // 区块哈希与本地区块头一致，清单中的状态根就是可信的
+                if manifest.block_hash() == target && manifest.height == height {
+                    break (*manifest, peer);
+                }
+                warn!("Peer {} sent a manifest for another block", peer.addr());
+            }
+            other => answer_ping(other, &mut peer),
+        }
+    };
+
+    info!("Downloading {} accounts in {} chunks from {}", manifest.accounts, manifest.chunk_hashes.len(), peer.addr());
+    let storage = blockchain.lock().unwrap().storage.clone();
+    let mut restore = StateRestore::new(storage, manifest);
+    while let Some(index) = restore.next_chunk() {
+        peer.write(Message::GetSnapshotChunk(target, index));
+        let deadline = Instant::now() + SNAPSHOT_TIMEOUT;
+        loop {
+            let (msg, mut from) = recv_until(msg_rx, deadline)
+                .ok_or_else(|| format!("Timed out waiting for snapshot chunk {}", index))?;
+            match msg {
+                Message::SnapshotChunk(hash, i, chunk) if hash == target && i == index => {
+                    restore.add_chunk(chunk.ok_or("Peer no longer serves the snapshot")?)?;
+                    break;
+                }
+                other => answer_ping(other, &mut from),
+            }
+        }
+    }
+    let manifest = restore.finish()?;
+    blockchain.lock().unwrap().install_snapshot(&manifest, height)?;
+
+    // 快照之后的区块由网络 worker 按普通区块处理
+    let following: Vec<H256> = (height + 1..=headers.height())
+        .filter_map(|h| headers.canonical_at(h))
+        .collect();
+    if !following.is_empty() {
+        peer.write(Message::GetBlocks(following));
+    }
+    Ok(height)
+}
+
+/// tip 高度为 `tip_height` 时提供快照的高度: 有足够确认的最高的 `SNAPSHOT_INTERVAL` 的倍数
+pub fn snapshot_height(tip_height: u64) -> Option<u64> {
+    tip_height.checked_sub(SNAPSHOT_CONFIRMATIONS)
+        .map(|h| h - h % SNAPSHOT_INTERVAL)
+        .filter(|h| *h > 0)
+}
+
+/// 等待下一条消息，超时或通道关闭时返回 None
+fn recv_until(
+    msg_rx: &smol::channel::Receiver<(Vec<u8>, peer::Handle)>,
+    deadline: Instant,
+) -> Option<(Message, peer::Handle)> {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    let received = smol::block_on(smol::future::or(
+        async { msg_rx.recv().await.ok() },
+        async {
+            smol::Timer::after(timeout).await;
+            None
+        },
+    ));
+    received.map(|(bytes, peer)| (bincode::deserialize(&bytes).unwrap(), peer))
+}
+
+/// 快速同步期间只回应 Ping，其余消息丢弃
+fn answer_ping(msg: Message, peer: &mut peer::Handle) {
+    match msg {
+        Message::Ping(nonce) => peer.write(Message::Pong(nonce)),
+        _ => debug!("Ignoring message during fast sync"),
+    }
+}
@@ -1,6 +1,9 @@
 use serde::{Serialize, Deserialize};
 
-use crate::types::{hash::H256, block::Block, transaction::SignedTransaction};
+use crate::types::{hash::H256, block::{Block, BlockHeader}, transaction::SignedTransaction};
+use crate::types::address::Address;
+use crate::types::proof::{AccountProof, TransactionProof};
+use crate::blockchain::snapshot::{SnapshotChunk, SnapshotManifest};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
@@ -18,4 +21,17 @@ pub enum Message {
     SendBlockchain(Vec<Block>),
     GetMempool,
     SendMempool(Vec<SignedTransaction>),
+    /// 轻节点请求区块头，参数为从 tip 往回的区块哈希 (locator)
+    GetHeaders(Vec<H256>),
+    Headers(Vec<BlockHeader>),
+    /// 请求账户在某个区块之后的状态证明
+    GetAccountProof(Address, H256),
+    AccountState(Address, H256, Option<Box<AccountProof>>),
+    GetTransactionProof(H256),
+    TransactionInclusion(H256, Option<Box<TransactionProof>>),
+    /// 请求区块执行后的状态快照清单，对方没有该区块的状态时回复 None
+    GetSnapshotManifest(H256),
+    SnapshotManifest(H256, Option<Box<SnapshotManifest>>),
+    GetSnapshotChunk(H256, u32),
+    SnapshotChunk(H256, u32, Option<SnapshotChunk>),
 }
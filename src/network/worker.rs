@@ -1,21 +1,21 @@
 use super::message::Message;
 use super::peer;
 use super::server::Handle as ServerHandle;
+use super::fast_sync::snapshot_height;
 use crate::types::hash::{H256, Hashable};
 use crate::types::block::Block;
 use crate::blockchain::Blockchain;
+use crate::blockchain::snapshot::Snapshot;
 use crate::types::mempool::Mempool;
+use crate::types::fee_estimator::FeeEstimator;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
 use log::{debug, warn, error, info};
 use std::thread;
-use crate::miner::{Handle, BLOCK_REWARD};
-use crate::types::merkle::MerkleTree;
+use crate::miner::Handle;
 
-#[cfg(any(test,test_utilities))]
-use super::peer::TestReceiver as PeerTestReceiver;
-#[cfg(any(test,test_utilities))]
-use super::server::TestReceiver as ServerTestReceiver;
+/// 一条 Headers 消息最多包含的区块头数
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2_000;
 
 #[derive(Clone)]
 pub struct Worker {
@@ -25,7 +25,11 @@ pub struct Worker {
     blockchain: Arc<Mutex<Blockchain>>,
     orphan_buffer: Arc<Mutex<HashMap<H256, Vec<Block>>>>,
     mempool: Arc<Mutex<Mempool>>,
+    fee_estimator: Arc<Mutex<FeeEstimator>>,
     miner: Handle,
+    snapshot: Arc<Mutex<Option<Arc<Snapshot>>>>,
+    /// 链 ID 创世后不再变化，构造时缓存，避免校验交易时加锁
+    chain_id: u64,
 }
 
 impl Worker {
@@ -35,8 +39,10 @@ impl Worker {
         server: &ServerHandle,
         blockchain: &Arc<Mutex<Blockchain>>,
         mempool: &Arc<Mutex<Mempool>>,
+        fee_estimator: &Arc<Mutex<FeeEstimator>>,
         miner: &Handle,
     ) -> Self {
+        let chain_id = blockchain.lock().unwrap().chain_id;
         Self {
             msg_chan: msg_src,
             num_worker,
@@ -44,7 +50,10 @@ impl Worker {
             blockchain: blockchain.clone(),
             orphan_buffer: Arc::new(Mutex::new(HashMap::new())),
             mempool: mempool.clone(),
+            fee_estimator: fee_estimator.clone(),
             miner: miner.clone(),
+            snapshot: Arc::new(Mutex::new(None)),
+            chain_id,
         }
     }
 
@@ -123,7 +132,7 @@ impl Worker {
                         if !parent_exists {
                             // 父块不存在，加入孤块缓冲区
                             let mut orphans = self.orphan_buffer.lock().unwrap();
-                            orphans.entry(parent_hash).or_insert(Vec::new()).push(block.clone());
+                            orphans.entry(parent_hash).or_default().push(block.clone());
                             debug!("Orphan block {} added to buffer, waiting for {}", block_hash, parent_hash);
                             peer.write(Message::GetBlocks(vec![parent_hash]));
                             continue;
@@ -138,9 +147,10 @@ impl Worker {
                             let execution_result = Blockchain::execute_block(storage.clone(), &blk);
                             
                             match execution_result {
-                                Ok((_, new_nodes)) => {
+                                Ok(executed) => {
                                     let mut blockchain = self.blockchain.lock().unwrap();
-                                    blockchain.commit_block(&blk, new_nodes);
+                                    blockchain.commit_block(&blk, executed.new_nodes);
+                                    self.fee_estimator.lock().unwrap().observe_committed_block(&blockchain, &blk);
                                     drop(blockchain); // 提交完立即释放
                                     
                                     info!("Block committed: {}", blk_hash);
@@ -207,8 +217,8 @@ impl Worker {
                     let mut new_tx_hashes = Vec::new();
                     let mut mempool = self.mempool.lock().unwrap();
                     for tx in txs {
-                        if !tx.verify() {
-                            warn!("Invalid transaction signature received");
+                        if let Err(e) = tx.validate(self.chain_id) {
+                            warn!("Invalid transaction received: {}", e);
                             continue;
                         }
                         let hash = tx.hash();
@@ -216,6 +226,7 @@ impl Worker {
                         new_tx_hashes.push(hash);
                     }
                     drop(mempool);
+                    self.observe_transactions(&new_tx_hashes);
 
                     if !new_tx_hashes.is_empty() {
                         self.server.broadcast(Message::NewTransactionHashes(new_tx_hashes));
@@ -239,10 +250,11 @@ impl Worker {
 
                     for block in blocks {
                         match Blockchain::execute_block(storage.clone(), &block) {
-                            Ok((_, new_nodes)) => {
+                            Ok(executed) => {
                                 // 执行成功，获取锁进行提交
                                 let mut bc = self.blockchain.lock().unwrap();
-                                bc.commit_block(&block, new_nodes); // 传入缺失的 new_nodes
+                                bc.commit_block(&block, executed.new_nodes); // 传入缺失的 new_nodes
+                                self.fee_estimator.lock().unwrap().observe_committed_block(&bc, &block);
                             }
                             Err(e) => {
                                 error!("Error processing synced block {:?}: {}", block.hash(), e);
@@ -268,21 +280,23 @@ impl Worker {
                 Message::SendMempool(transactions) => {
                     debug!("Received Mempool sync: {} transactions", transactions.len());
                     let mut mempool = self.mempool.lock().unwrap();
-                    let mut count = 0;
+                    let mut new_tx_hashes = Vec::new();
                     for tx in transactions {
                         let hash = tx.hash();
                         if !mempool.contains(&hash) {
                             // 必须验证签名！防止脏数据攻击
-                            if tx.verify() {
-                                mempool.insert(tx);
-                                count += 1;
-                            } else {
-                                warn!("Invalid signature in SendMempool for tx {:?}", hash);
+                            match tx.validate(self.chain_id) {
+                                Ok(()) => {
+                                    mempool.insert(tx);
+                                    new_tx_hashes.push(hash);
+                                }
+                                Err(e) => warn!("Invalid tx {:?} in SendMempool: {}", hash, e),
                             }
                         }
                     }
                     drop(mempool);
-                    debug!("Synced {} new transactions into Mempool", count);
+                    self.observe_transactions(&new_tx_hashes);
+                    debug!("Synced {} new transactions into Mempool", new_tx_hashes.len());
                 }
                 Message::BlockHeight(peer_height) => {
                     let blockchain = self.blockchain.lock().unwrap();
@@ -303,7 +317,80 @@ impl Worker {
                     drop(blockchain);
                     peer.write(Message::BlockHeight(height));
                 }
+                // 轻节点的请求
+                Message::GetHeaders(locator) => {
+                    let headers = self.blockchain.lock().unwrap().headers_after(&locator, MAX_HEADERS_PER_MESSAGE);
+                    if !headers.is_empty() {
+                        peer.write(Message::Headers(headers));
+                    }
+                }
+                Message::GetAccountProof(address, block_hash) => {
+                    let proof = self.blockchain.lock().unwrap().account_proof(&address, &block_hash);
+                    peer.write(Message::AccountState(address, block_hash, proof.map(Box::new)));
+                }
+                Message::GetTransactionProof(hash) => {
+                    let proof = self.blockchain.lock().unwrap().transaction_proof(&hash);
+                    peer.write(Message::TransactionInclusion(hash, proof.map(Box::new)));
+                }
+                Message::Headers(_) | Message::AccountState(..) | Message::TransactionInclusion(..) => {
+                    debug!("Ignoring light client response");
+                }
+                // 快速同步的请求
+                Message::GetSnapshotManifest(block_hash) => {
+                    let manifest = self.snapshot(&block_hash).map(|s| Box::new(s.manifest.clone()));
+                    peer.write(Message::SnapshotManifest(block_hash, manifest));
+                }
+                Message::GetSnapshotChunk(block_hash, index) => {
+                    let chunk = self.snapshot(&block_hash).and_then(|s| s.chunks.get(index as usize).cloned());
+                    peer.write(Message::SnapshotChunk(block_hash, index, chunk));
+                }
+                Message::SnapshotManifest(..) | Message::SnapshotChunk(..) => {
+                    debug!("Ignoring snapshot response outside of fast sync");
+                }
             }
         }
     }
+
+    /// 区块 `block_hash` 的快照，只提供主链上 `snapshot_height` 处的区块，其余请求返回 None。
+    /// 分块请求会连续到来，所以缓存最近构建的一个。构建时不持有缓存的锁
+    fn snapshot(&self, block_hash: &H256) -> Option<Arc<Snapshot>> {
+        if let Some(snapshot) = self.snapshot.lock().unwrap().as_ref().filter(|s| s.manifest.block_hash() == *block_hash) {
+            return Some(snapshot.clone());
+        }
+        let storage = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let height = snapshot_height(blockchain.get_height(&blockchain.tip()))?;
+            if blockchain.block_at_height(height) != Some(*block_hash) {
+                debug!("Not serving a snapshot of block {}", block_hash);
+                return None;
+            }
+            blockchain.storage.clone()
+        };
+        match Snapshot::build(&storage, block_hash) {
+            Ok(snapshot) => {
+                info!("Built state snapshot of block {}: {} accounts", block_hash, snapshot.manifest.accounts);
+                let snapshot = Arc::new(snapshot);
+                *self.snapshot.lock().unwrap() = Some(snapshot.clone());
+                Some(snapshot)
+            }
+            Err(e) => {
+                debug!("Cannot serve snapshot: {}", e);
+                None
+            }
+        }
+    }
+
+    /// 记录新交易进入 Mempool 时的链高度，供手续费估算计算打包延迟
+    fn observe_transactions(&self, hashes: &[H256]) {
+        if hashes.is_empty() {
+            return;
+        }
+        let blockchain = self.blockchain.lock().unwrap();
+        let height = blockchain.get_height(&blockchain.tip());
+        drop(blockchain);
+        let mut fee_estimator = self.fee_estimator.lock().unwrap();
+        for hash in hashes {
+            fee_estimator.observe_transaction(*hash, height);
+        }
+    }
 }
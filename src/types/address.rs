@@ -1,6 +1,13 @@
 use serde::{Serialize, Deserialize};
 use ring::digest;
 use std::convert::TryInto;
+use std::str::FromStr;
+
+/// 地址文本编码的网络前缀 (bech32 human-readable part)
+pub const ADDRESS_HRP: &str = "bit";
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CHECKSUM_LEN: usize = 6;
 
 // 20-byte address
 #[derive(Eq, PartialEq, Serialize, Deserialize, Clone, Hash, Default, Copy, Ord, PartialOrd)]
@@ -26,22 +33,115 @@ impl AsRef<[u8]> for Address {
     }
 }
 
+/// 带校验和的 bech32 编码，例如 `bit1...`。支持宽度。
+/// 所有地址的前缀相同，`{:.N}` 只显示 `bit1` 之后的最后 N 个字符
 impl std::fmt::Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let start = if let Some(precision) = f.precision() {
-            if precision >= 40 {
-                0
-            } else {
-                20 - precision / 2
+        let mut data = convert_bits(&self.0, 8, 5, true).expect("8-bit to 5-bit conversion never fails");
+        data.extend(bech32_checksum(ADDRESS_HRP, &data));
+        let payload: String = data.iter().map(|value| BECH32_CHARSET[*value as usize] as char).collect();
+        match f.precision() {
+            Some(n) => f.pad(&payload[payload.len().saturating_sub(n)..]),
+            None => f.pad(&format!("{}1{}", ADDRESS_HRP, payload)),
+        }
+    }
+}
+
+/// 接受 bech32 编码 (大小写不能混用) 和旧的 40 位 hex 编码
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let hex_part = s.strip_prefix("0x").unwrap_or(s);
+        if hex_part.len() == 40 && hex_part.chars().all(|c| c.is_ascii_hexdigit()) {
+            let bytes = hex::decode(hex_part).map_err(|_| format!("Invalid hex address {}", s))?;
+            let array: [u8; 20] = bytes.try_into().expect("40 hex chars are 20 bytes");
+            return Ok(Address(array));
+        }
+        if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+            return Err(format!("Mixed-case address {}", s));
+        }
+        let lower = s.to_ascii_lowercase();
+        let (hrp, payload) = lower.rsplit_once('1').ok_or(format!("Invalid address {}", s))?;
+        if hrp != ADDRESS_HRP {
+            return Err(format!("Address {} is not for this network (expected prefix {})", s, ADDRESS_HRP));
+        }
+        let data = payload.bytes()
+            .map(|c| BECH32_CHARSET.iter().position(|x| *x == c).map(|v| v as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(format!("Invalid character in address {}", s))?;
+        if data.len() < BECH32_CHECKSUM_LEN || !bech32_verify(hrp, &data) {
+            return Err(format!("Invalid address checksum {}", s));
+        }
+        let bytes = convert_bits(&data[..data.len() - BECH32_CHECKSUM_LEN], 5, 8, false)
+            .ok_or(format!("Invalid address {}", s))?;
+        let array: [u8; 20] = bytes.try_into().map_err(|_| format!("Address must be 20 bytes: {}", s))?;
+        Ok(Address(array))
+    }
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
             }
-        } else {
-            0
-        };
-        for byte_idx in start..20 {
-            write!(f, "{:>02x}", &self.0[byte_idx])?;
         }
-        Ok(())
     }
+    chk
+}
+
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values
+}
+
+fn bech32_checksum(hrp: &str, data: &[u8]) -> Vec<u8> {
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; BECH32_CHECKSUM_LEN]);
+    let polymod = bech32_polymod(&values) ^ 1;
+    (0..BECH32_CHECKSUM_LEN).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8).collect()
+}
+
+fn bech32_verify(hrp: &str, data: &[u8]) -> bool {
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(data);
+    bech32_polymod(&values) == 1
+}
+
+/// 在 8 位与 5 位分组之间转换
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max = (1u32 << to) - 1;
+    let mut out = Vec::new();
+    for value in data {
+        if (*value as u32) >> from != 0 {
+            return None;
+        }
+        acc = (acc << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return None;
+    }
+    Some(out)
 }
 
 impl std::fmt::Debug for Address {
@@ -59,12 +159,64 @@ impl Address {
         let hash: digest::Digest = digest::digest(&digest::SHA256, bytes); //digest type is a ptr
         let hash_bytes: &[u8] = hash.as_ref();  //turn it into a byte slice
         let address_array: [u8; 20] = hash_bytes[12..].try_into().expect("length must be 20");
-        return Address::from(address_array)
+        Address::from(address_array)
     }
 }
 
 
 
+#[cfg(test)]
+mod bech32_test {
+    use super::{bech32_verify, Address, BECH32_CHARSET};
+
+    fn decode_data(payload: &str) -> Vec<u8> {
+        payload.bytes().map(|c| BECH32_CHARSET.iter().position(|x| *x == c).unwrap() as u8).collect()
+    }
+
+    #[test]
+    fn bech32_test_vectors() {
+        // BIP-173 中的合法编码
+        assert!(bech32_verify("a", &decode_data("2uel5l")));
+        assert!(bech32_verify("abcdef", &decode_data("qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw")));
+        assert!(!bech32_verify("abcdef", &decode_data("qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxq")));
+    }
+
+    #[test]
+    fn text_encoding_round_trip() {
+        let addr: Address = hex!("1851a0eae0060a132cf0f64a0ffaea248de6cba0").into();
+        let text = addr.to_string();
+        assert!(text.starts_with("bit1"));
+        assert_eq!(text.parse::<Address>().unwrap(), addr);
+        assert_eq!(text.to_uppercase().parse::<Address>().unwrap(), addr);
+        // 旧的 hex 编码仍然可用
+        assert_eq!("1851a0eae0060a132cf0f64a0ffaea248de6cba0".parse::<Address>().unwrap(), addr);
+        assert_eq!("0x1851a0eae0060a132cf0f64a0ffaea248de6cba0".parse::<Address>().unwrap(), addr);
+
+        // 任意一个字符写错都能被校验和发现
+        for i in 4..text.len() {
+            let mut typo = text.clone().into_bytes();
+            typo[i] = if typo[i] == b'q' { b'p' } else { b'q' };
+            assert!(String::from_utf8(typo).unwrap().parse::<Address>().is_err());
+        }
+        let mixed = format!("BIT1{}", &text[4..]);
+        assert!(mixed.parse::<Address>().is_err());
+        assert!(text.replacen("bit1", "btc1", 1).parse::<Address>().is_err());
+        assert!("1851a0ea".parse::<Address>().is_err());
+        let god: Address = hex!("67d39da22d106b686c4f301b6f357600d28fc104").into();
+        assert_eq!(god.to_string(), "bit1vlfemg3dzp4ksmz0xqdk7dtkqrfglsgy6ht4uc");
+    }
+
+    #[test]
+    fn display_respects_precision_and_width() {
+        let god: Address = hex!("67d39da22d106b686c4f301b6f357600d28fc104").into();
+        let text = god.to_string();
+        assert_eq!(format!("{:.10}", god), &text[text.len() - 10..]);
+        assert_eq!(format!("{:>12.10}", god), format!("  {}", &text[text.len() - 10..]));
+        assert_eq!(format!("{:.100}", god), text.trim_start_matches("bit1"));
+        assert_eq!(format!("{:>44}", god), format!("  {}", god));
+    }
+}
+
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
 
 #[cfg(test)]
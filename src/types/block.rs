@@ -1,11 +1,8 @@
 use serde::{Serialize, Deserialize};
 use crate::types::hash::{H256, Hashable};
-use std::time::{SystemTime, UNIX_EPOCH};
 use ring::digest;
-use rand::Rng;
 use crate::types::merkle::MerkleTree;
 use crate::types::transaction::{Transaction, SignedTransaction};
-use crate::types::address::Address;
 
 
 
@@ -21,25 +18,33 @@ pub struct Block {
     pub data: Vec<SignedTransaction>,
 }
 
-impl Hashable for Block {
+/// 区块头: 区块中参与哈希的部分，不含交易列表
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockHeader {
+    pub parent: H256,
+    pub nonce: u32,
+    pub difficulty: H256,
+    pub timestamp: u128,
+    pub merkle_root: H256,
+    pub state_root: H256,
+    pub coinbase: Transaction,
+}
+
+impl Hashable for BlockHeader {
     fn hash(&self) -> H256 {
-        let header_data = (
-            &self.parent,
-            &self.nonce,
-            &self.difficulty,
-            &self.timestamp,
-            &self.merkle_root,
-            &self.state_root,
-            &self.coinbase,
-        );
-
-        let encoded: Vec<u8> = bincode::serialize(&header_data).expect("Serialization failed");
+        let encoded: Vec<u8> = bincode::serialize(self).expect("Serialization failed");
         
         let digest: H256 = digest::digest(&digest::SHA256, &encoded).into();
         digest
     }
 }
 
+impl Hashable for Block {
+    fn hash(&self) -> H256 {
+        self.header().hash()
+    }
+}
+
 
 impl Block {
 
@@ -60,13 +65,30 @@ impl Block {
             nonce,
             difficulty,
             timestamp,
-            merkle_root: merkle_root,
-            state_root: state_root,
-            coinbase: coinbase,
+            merkle_root,
+            state_root,
+            coinbase,
             data,
         }
     }
 
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            parent: self.parent,
+            nonce: self.nonce,
+            difficulty: self.difficulty,
+            timestamp: self.timestamp,
+            merkle_root: self.merkle_root,
+            state_root: self.state_root,
+            coinbase: self.coinbase.clone(),
+        }
+    }
+
+    /// 区块 bincode 序列化后的字节数
+    pub fn serialized_size(&self) -> u64 {
+        bincode::serialized_size(self).expect("Serialization failed")
+    }
+
     pub fn get_parent(&self) -> H256 {
         self.parent
     }
@@ -88,33 +110,33 @@ impl Block {
     }
 
     pub fn set_nonce(&mut self, nonce: &u32) {
-        self.nonce = nonce.clone();
+        self.nonce = *nonce;
     }
 
     pub fn set_timestamp(&mut self, timestamp: &u128) {
-        self.timestamp = timestamp.clone();
+        self.timestamp = *timestamp;
     }
 
-    pub fn genesis(state_root: H256) -> Self {
+    /// 创世块: 没有父块和交易，参数来自创世配置
+    pub fn genesis(chain_id: u64, difficulty: H256, timestamp: u128, state_root: H256) -> Self {
         let zero_hash = H256::from([0u8; 32]);
-        let mut difficulty_bytes = [255u8; 32];
-        for i in  0..3 {
-            difficulty_bytes[i] = 0;
-        }
-        let genesis_difficulty = H256::from(difficulty_bytes);
 
         let data = Vec::new();
         let merkle_root = MerkleTree::new(&data).root();
-        let coinbase = Transaction::default();
+        // coinbase 参与区块哈希，不同链 ID 的创世块哈希不同
+        let coinbase = Transaction {
+            chain_id,
+            ..Transaction::default()
+        };
 
         Block {
             parent: zero_hash,
             nonce: 0,
-            difficulty: genesis_difficulty,
-            timestamp: 0,
-            merkle_root: merkle_root,
-            state_root: state_root,
-            coinbase: coinbase,
+            difficulty,
+            timestamp,
+            merkle_root,
+            state_root,
+            coinbase,
             data,
         }
     }
@@ -0,0 +1,246 @@
+use super::{
+    block::Block,
+    hash::{Hashable, H256},
+    mempool::Mempool,
+};
+use crate::blockchain::Blockchain;
+
+use serde::Serialize;
+
+use std::collections::{HashMap, VecDeque};
+
+/// 参与估算的最近 canonical 区块数
+pub const FEE_HISTORY_BLOCKS: usize = 50;
+/// 没有任何样本时给出的最低 gas_price
+pub const MIN_GAS_PRICE: u64 = 1;
+/// 未确认交易的 first-seen 记录最多保留的区块数
+const MAX_TRACKED_AGE: u64 = 1000;
+
+/// 单个区块中被打包交易的 (gas_price, 打包延迟)。延迟未知时为 None (例如重启前的区块)
+#[derive(Debug, Clone)]
+struct BlockSample {
+    hash: H256,
+    height: u64,
+    txs: Vec<(u64, Option<u64>)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeEstimate {
+    pub target_blocks: u64,
+    pub gas_price: u64,
+    /// 仅根据历史区块得到的价格
+    pub history_gas_price: u64,
+    /// 仅根据当前 Mempool 拥堵程度得到的价格
+    pub mempool_gas_price: u64,
+    pub sampled_blocks: usize,
+    pub sampled_transactions: usize,
+    pub pending_transactions: usize,
+}
+
+/// 根据最近 canonical 区块中交易的 gas_price 与打包延迟，以及 Mempool 的拥堵程度估算手续费
+#[derive(Debug, Default)]
+pub struct FeeEstimator {
+    /// 交易哈希 -> 首次见到该交易时的链高度
+    first_seen: HashMap<H256, u64>,
+    samples: VecDeque<BlockSample>,
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 用数据库中最近的 canonical 区块初始化 (重启后这些交易的延迟未知)
+    pub fn from_chain(blockchain: &Blockchain) -> Self {
+        let mut estimator = Self::new();
+        let chain = blockchain.all_blocks_in_longest_chain();
+        let start = chain.len().saturating_sub(FEE_HISTORY_BLOCKS);
+        for hash in &chain[start..] {
+            if let Some(block) = blockchain.get_block(hash) {
+                estimator.observe_block(&block, blockchain.get_height(hash));
+            }
+        }
+        estimator
+    }
+
+    /// 记录交易首次进入本地 Mempool 时的链高度
+    pub fn observe_transaction(&mut self, hash: H256, tip_height: u64) {
+        self.first_seen.entry(hash).or_insert(tip_height);
+    }
+
+    /// 区块提交后调用，只记录成为新 tip 的区块，分叉块不参与估算。
+    /// 发生重组时从分叉点开始重新记录新的 canonical 区块
+    pub fn observe_committed_block(&mut self, blockchain: &Blockchain, block: &Block) {
+        let hash = block.hash();
+        if blockchain.tip() != hash {
+            return;
+        }
+        // 从新 tip 往回走到已记录的区块 (分叉点)，沿途都是新成为 canonical 的区块
+        let mut branch = Vec::new();
+        let mut curr = hash;
+        while branch.len() < FEE_HISTORY_BLOCKS && !self.samples.iter().any(|s| s.hash == curr) {
+            let block = match blockchain.get_block(&curr) {
+                Some(block) => block,
+                None => break,
+            };
+            curr = block.get_parent();
+            branch.push(block);
+        }
+        for block in branch.iter().rev() {
+            self.observe_block(block, blockchain.get_height(&block.hash()));
+        }
+    }
+
+    /// 记录一个成为 tip 的区块
+    pub fn observe_block(&mut self, block: &Block, height: u64) {
+        let txs = block.data.iter()
+            .map(|tx| {
+                let delay = self.first_seen.remove(&tx.hash())
+                    .map(|seen| height.saturating_sub(seen));
+                (tx.transaction.gas_price, delay)
+            })
+            .collect();
+
+        // 发生重组时丢弃被替换掉的高度
+        while self.samples.back().is_some_and(|s| s.height >= height) {
+            self.samples.pop_back();
+        }
+        self.samples.push_back(BlockSample { hash: block.hash(), height, txs });
+        while self.samples.len() > FEE_HISTORY_BLOCKS {
+            self.samples.pop_front();
+        }
+
+        self.first_seen.retain(|_, seen| *seen + MAX_TRACKED_AGE > height);
+    }
+
+    /// 估算在 `target_blocks` 个区块内被打包所需的 gas_price
+    pub fn estimate(&self, target_blocks: u64, mempool: &Mempool) -> FeeEstimate {
+        let target_blocks = target_blocks.clamp(1, FEE_HISTORY_BLOCKS as u64);
+
+        // 历史: 在目标区块数内被打包的交易的 gas_price 中位数
+        let mut prices: Vec<u64> = self.samples.iter()
+            .flat_map(|s| s.txs.iter())
+            .filter(|(_, delay)| delay.is_none_or(|d| d <= target_blocks))
+            .map(|(price, _)| *price)
+            .collect();
+        prices.sort_unstable();
+        let history_gas_price = prices.get(prices.len() / 2).copied().unwrap_or(MIN_GAS_PRICE);
+
+        // Mempool: 目标区块数内大约能打包多少笔交易，需要出价超过排在其后的交易
+        let capacity = self.average_block_transactions() * target_blocks as usize;
+        let mut pending: Vec<u64> = mempool.select_transactions().iter()
+            .map(|tx| tx.transaction.gas_price)
+            .collect();
+        pending.sort_unstable_by(|a, b| b.cmp(a));
+        let mempool_gas_price = match pending.get(capacity) {
+            Some(price) => price.saturating_add(1),
+            None => MIN_GAS_PRICE,
+        };
+
+        FeeEstimate {
+            target_blocks,
+            gas_price: history_gas_price.max(mempool_gas_price).max(MIN_GAS_PRICE),
+            history_gas_price,
+            mempool_gas_price,
+            sampled_blocks: self.samples.len(),
+            sampled_transactions: prices.len(),
+            pending_transactions: pending.len(),
+        }
+    }
+
+    /// 最近区块平均打包的交易数 (至少为 1，避免空链时容量为 0)
+    fn average_block_transactions(&self) -> usize {
+        if self.samples.is_empty() {
+            return 1;
+        }
+        let total: usize = self.samples.iter().map(|s| s.txs.len()).sum();
+        (total / self.samples.len()).max(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FeeEstimator, MIN_GAS_PRICE};
+    use crate::blockchain::Blockchain;
+    use crate::blockchain::genesis::GenesisConfig;
+    use crate::database::Storage;
+    use crate::types::address::Address;
+    use crate::types::block::Block;
+    use crate::types::hash::{Hashable, H256};
+    use crate::types::key_pair;
+    use crate::types::mempool::Mempool;
+    use crate::types::transaction::{sign_with, SignedTransaction, Transaction};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn signed(nonce: u64, gas_price: u64) -> SignedTransaction {
+        sign_with(Transaction::new(1, nonce, gas_price, 10, Address::default(), 1, vec![]), &key_pair::random())
+    }
+
+    fn block_with(txs: Vec<SignedTransaction>) -> Block {
+        Block::new(H256::default(), 0, H256::default(), 0, H256::default(), Transaction::default(), txs)
+    }
+
+    #[test]
+    fn empty_history_uses_minimum() {
+        let estimator = FeeEstimator::new();
+        let estimate = estimator.estimate(3, &Mempool::new());
+        assert_eq!(estimate.gas_price, MIN_GAS_PRICE);
+    }
+
+    #[test]
+    fn slow_cheap_transactions_only_count_for_long_targets() {
+        let mut estimator = FeeEstimator::new();
+        let cheap = signed(0, 1);
+        let pricey = signed(0, 10);
+        estimator.observe_transaction(cheap.hash(), 0);
+        estimator.observe_transaction(pricey.hash(), 4);
+        // 两笔交易都在高度 5 被打包: cheap 等了 5 个块, pricey 只等了 1 个块
+        estimator.observe_block(&block_with(vec![cheap, pricey]), 5);
+
+        let mempool = Mempool::new();
+        assert_eq!(estimator.estimate(1, &mempool).gas_price, 10);
+        assert_eq!(estimator.estimate(5, &mempool).history_gas_price, 10);
+        assert_eq!(estimator.estimate(5, &mempool).sampled_transactions, 2);
+    }
+
+    #[test]
+    fn reorg_resamples_new_canonical_blocks() {
+        let mut chain = Blockchain::from_storage(Arc::new(Storage::new_temporary()), &GenesisConfig::default()).unwrap();
+        let mut estimator = FeeEstimator::from_chain(&chain);
+        let genesis = chain.tip();
+        let child = |parent: H256, nonce: u32, txs: Vec<SignedTransaction>| {
+            Block::new(parent, nonce, H256::default(), 0, H256::default(), Transaction::default(), txs)
+        };
+
+        let a1 = child(genesis, 1, vec![signed(0, 1)]);
+        chain.commit_block(&a1, HashMap::new());
+        estimator.observe_committed_block(&chain, &a1);
+        // b1 提交时只是分叉块，b2 使 b 分支成为最长链，a1 的样本应被 b1 替换
+        let b1 = child(genesis, 2, vec![signed(0, 100)]);
+        let b2 = child(b1.hash(), 3, vec![]);
+        chain.commit_block(&b1, HashMap::new());
+        estimator.observe_committed_block(&chain, &b1);
+        chain.commit_block(&b2, HashMap::new());
+        estimator.observe_committed_block(&chain, &b2);
+
+        let estimate = estimator.estimate(5, &Mempool::new());
+        assert_eq!(estimate.sampled_blocks, 3);
+        assert_eq!(estimate.sampled_transactions, 1);
+        assert_eq!(estimate.history_gas_price, 100);
+    }
+
+    #[test]
+    fn mempool_pressure_raises_estimate() {
+        let mut estimator = FeeEstimator::new();
+        estimator.observe_block(&block_with(vec![signed(0, 1)]), 1);
+
+        let mut mempool = Mempool::new();
+        for price in [2, 3, 7] {
+            mempool.insert(signed(0, price));
+        }
+        // 平均每块 1 笔交易，1 个块内只能打包出价最高的交易
+        assert_eq!(estimator.estimate(1, &mempool).gas_price, 4);
+        assert_eq!(estimator.estimate(3, &mempool).gas_price, 1);
+    }
+}
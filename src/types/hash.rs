@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 use std::convert::TryInto;
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 use rand::Rng;
 
 /// An object that can be meaningfully hashed.
@@ -109,7 +109,7 @@ impl PartialOrd for H256 {
     }
 }
 
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 pub fn generate_random_hash() -> H256 {
     let mut rng = rand::thread_rng();
     let random_bytes: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
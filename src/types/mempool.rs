@@ -1,19 +1,49 @@
 use super::{
+    address::Address,
     hash::{Hashable, H256},
-    transaction::SignedTransaction,
+    transaction::{SignedTransaction, TxLock},
 };
 
+use serde::Serialize;
 
-
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Default, Clone)]
 pub struct Mempool {
     transactions: HashMap<H256, SignedTransaction>,
 }
 
+/// 交易在 Mempool 中相对发送者账户 nonce 的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TxStatus {
+    /// 之前的 nonce 全部就绪，可以被打包
+    Ready,
+    /// 缺少该 nonce 的交易或该 nonce 的交易尚未解锁，暂时无法打包
+    Waiting { nonce: u64 },
+    /// 交易本身尚未解锁
+    Locked { lock: TxLock },
+    /// nonce 已被链上使用，永远不会被打包
+    Stale,
+}
+
+/// 手续费直方图的一个桶，包含 fee 落在 [min_fee, max_fee] 的交易数
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeBucket {
+    pub min_fee: u64,
+    pub max_fee: u64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MempoolStats {
+    pub count: usize,
+    pub bytes: u64,
+    pub fee_histogram: Vec<FeeBucket>,
+}
+
 impl Mempool {
-    
+
     pub fn new() -> Self {
         Self{
             transactions: HashMap::new(),
@@ -21,26 +51,39 @@ impl Mempool {
     }
 
     pub fn insert(&mut self, tx: SignedTransaction) {
-        let hash = tx.hash();
-        if !self.transactions.contains_key(&hash) {
-            self.transactions.insert(hash, tx);
-        }
+        self.transactions.entry(tx.hash()).or_insert(tx);
     }
 
     pub fn select_transactions(&self) -> Vec<SignedTransaction> {
         self.transactions.values().cloned().collect()
     }
 
+    /// 可以被打包进高度为 `height`、时间戳为 `timestamp` 的区块的交易，锁定的交易留在 Mempool 中
+    pub fn select_unlocked(&self, height: u64, timestamp: u128) -> Vec<SignedTransaction> {
+        self.transactions.values()
+            .filter(|tx| tx.transaction.is_unlocked(height, timestamp))
+            .cloned()
+            .collect()
+    }
+
     pub fn remove_transactions(&mut self, hashes: &[H256]) {
         for hash in hashes {
             self.transactions.remove(hash);
         }
     }
 
+    pub fn remove(&mut self, hash: &H256) -> Option<SignedTransaction> {
+        self.transactions.remove(hash)
+    }
+
     pub fn len(&self) -> usize {
         self.transactions.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
     pub fn get_transaction(&self, hash: &H256) -> Option<SignedTransaction> {
         self.transactions.get(hash).cloned()
     }
@@ -49,4 +92,155 @@ impl Mempool {
         self.transactions.contains_key(hash)
     }
 
+    /// 按发送者、接收者和最低手续费过滤交易，结果按 (发送者, nonce) 排序
+    pub fn filter(
+        &self,
+        sender: Option<&Address>,
+        recipient: Option<&Address>,
+        min_fee: u64,
+    ) -> Vec<SignedTransaction> {
+        let mut txs: Vec<SignedTransaction> = self.transactions.values()
+            .filter(|tx| sender.is_none_or(|s| tx.sender_address() == *s))
+            .filter(|tx| recipient.is_none_or(|r| tx.transaction.payments().iter().any(|(to, _)| to == r)))
+            .filter(|tx| tx.transaction.fee() >= min_fee)
+            .cloned()
+            .collect();
+        txs.sort_by_key(|tx| (tx.sender_address(), tx.transaction.nonce));
+        txs
+    }
+
+    /// 判断交易能否被打包。`account_nonce` 为发送者在链上 tip 的 nonce，
+    /// `height` 与 `timestamp` 为下一个区块的高度和时间戳，用于判断锁定的交易
+    pub fn status(&self, hash: &H256, account_nonce: u64, height: u64, timestamp: u128) -> Option<TxStatus> {
+        let tx = self.transactions.get(hash)?;
+        let nonce = tx.transaction.nonce;
+        if nonce < account_nonce {
+            return Some(TxStatus::Stale);
+        }
+        if let Some(lock) = tx.transaction.lock.filter(|_| !tx.transaction.is_unlocked(height, timestamp)) {
+            return Some(TxStatus::Locked { lock });
+        }
+
+        let sender = tx.sender_address();
+        let pending_nonces: HashSet<u64> = self.transactions.values()
+            .filter(|t| t.sender_address() == sender)
+            .filter(|t| t.transaction.is_unlocked(height, timestamp))
+            .map(|t| t.transaction.nonce)
+            .collect();
+
+        match (account_nonce..nonce).find(|n| !pending_nonces.contains(n)) {
+            Some(missing) => Some(TxStatus::Waiting { nonce: missing }),
+            None => Some(TxStatus::Ready),
+        }
+    }
+
+    /// 统计交易数量、序列化后的总字节数，以及按 2 的幂分桶的手续费直方图
+    pub fn stats(&self) -> MempoolStats {
+        let mut bytes = 0;
+        let mut buckets: BTreeMap<u32, usize> = BTreeMap::new();
+        for tx in self.transactions.values() {
+            bytes += bincode::serialized_size(tx).expect("Serialization failed");
+            let fee = tx.transaction.fee();
+            // 0 单独一个桶，其余 fee 落在 [2^(k-1), 2^k - 1]
+            let bucket = u64::BITS - fee.leading_zeros();
+            *buckets.entry(bucket).or_default() += 1;
+        }
+
+        let fee_histogram = buckets.into_iter()
+            .map(|(bucket, count)| {
+                let (min_fee, max_fee) = match bucket {
+                    0 => (0, 0),
+                    64 => (1 << 63, u64::MAX),
+                    k => (1 << (k - 1), (1 << k) - 1),
+                };
+                FeeBucket { min_fee, max_fee, count }
+            })
+            .collect();
+
+        MempoolStats {
+            count: self.transactions.len(),
+            bytes,
+            fee_histogram,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mempool, TxStatus};
+    use crate::types::address::Address;
+    use crate::types::hash::Hashable;
+    use crate::types::key_pair;
+    use crate::types::transaction::{sign_with, SignedTransaction, Transaction, TxLock};
+    use ring::signature::Ed25519KeyPair;
+
+    fn signed(key: &Ed25519KeyPair, nonce: u64, gas_price: u64) -> SignedTransaction {
+        sign_with(Transaction::new(1, nonce, gas_price, 10, Address::default(), 1, vec![]), key)
+    }
+
+    #[test]
+    fn status_reports_nonce_gaps() {
+        let key = key_pair::random();
+        let tx0 = signed(&key, 0, 1);
+        let tx2 = signed(&key, 2, 1);
+        let mut mempool = Mempool::new();
+        mempool.insert(tx0.clone());
+        mempool.insert(tx2.clone());
+
+        assert_eq!(mempool.status(&tx0.hash(), 0, 1, 0), Some(TxStatus::Ready));
+        assert_eq!(mempool.status(&tx2.hash(), 0, 1, 0), Some(TxStatus::Waiting { nonce: 1 }));
+        assert_eq!(mempool.status(&tx0.hash(), 1, 1, 0), Some(TxStatus::Stale));
+        assert_eq!(mempool.status(&tx2.hash(), 2, 1, 0), Some(TxStatus::Ready));
+
+        let tx1 = signed(&key, 1, 1);
+        mempool.insert(tx1);
+        assert_eq!(mempool.status(&tx2.hash(), 0, 1, 0), Some(TxStatus::Ready));
+
+        mempool.remove(&tx0.hash());
+        assert_eq!(mempool.status(&tx0.hash(), 0, 1, 0), None);
+        assert_eq!(mempool.status(&tx2.hash(), 0, 1, 0), Some(TxStatus::Waiting { nonce: 0 }));
+    }
+
+    #[test]
+    fn locked_transactions_are_held() {
+        let key = key_pair::random();
+        let locked = |nonce: u64, lock: TxLock| {
+            sign_with(Transaction::new(1, nonce, 1, 10, Address::default(), 1, vec![]).with_lock(lock), &key)
+        };
+        let tx0 = locked(0, TxLock::Height(5));
+        let tx1 = signed(&key, 1, 1);
+        let timed = locked(2, TxLock::Time(100));
+        let mut mempool = Mempool::new();
+        for tx in [&tx0, &tx1, &timed] {
+            mempool.insert(tx.clone());
+        }
+
+        assert_eq!(mempool.status(&tx0.hash(), 0, 4, 0), Some(TxStatus::Locked { lock: TxLock::Height(5) }));
+        assert_eq!(mempool.status(&tx1.hash(), 0, 4, 0), Some(TxStatus::Waiting { nonce: 0 }));
+        assert_eq!(mempool.status(&tx1.hash(), 0, 5, 0), Some(TxStatus::Ready));
+        assert_eq!(mempool.select_unlocked(4, 0).len(), 1);
+        assert_eq!(mempool.status(&timed.hash(), 0, 5, 99_999), Some(TxStatus::Locked { lock: TxLock::Time(100) }));
+        assert_eq!(mempool.status(&timed.hash(), 0, 5, 100_000), Some(TxStatus::Ready));
+        assert_eq!(mempool.select_unlocked(5, 100_000).len(), 3);
+    }
+
+    #[test]
+    fn stats_histogram() {
+        let key = key_pair::random();
+        let mut mempool = Mempool::new();
+        mempool.insert(signed(&key, 0, 0));
+        mempool.insert(signed(&key, 1, 1));
+        mempool.insert(signed(&key, 2, 2));
+
+        let stats = mempool.stats();
+        assert_eq!(stats.count, 3);
+        assert!(stats.bytes > 0);
+        // fee = gas_price * 10 -> 0, 10, 20
+        let buckets: Vec<(u64, u64, usize)> = stats.fee_histogram.iter()
+            .map(|b| (b.min_fee, b.max_fee, b.count))
+            .collect();
+        assert_eq!(buckets, vec![(0, 0, 1), (8, 15, 1), (16, 31, 1)]);
+        assert_eq!(mempool.filter(None, None, 10).len(), 2);
+    }
 }
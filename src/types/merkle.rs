@@ -77,6 +77,17 @@ impl MerkleTree {
     }
 }
 
+/// Number of hashes in the proof of a tree with `leaf_size` leaves.
+pub fn depth(leaf_size: usize) -> usize {
+    let mut depth = 0;
+    let mut width = leaf_size;
+    while width > 1 {
+        width = width.div_ceil(2);
+        depth += 1;
+    }
+    depth
+}
+
 /// Verify that the datum hash with a vector of proofs will produce the Merkle root. Also need the
 /// index of datum and `leaf_size`, the total number of leaves.
 pub fn verify(root: &H256, datum: &H256, proof: &[H256], index: usize, leaf_size: usize) -> bool {
@@ -87,7 +98,7 @@ pub fn verify(root: &H256, datum: &H256, proof: &[H256], index: usize, leaf_size
     let mut cur_hash = *datum;
     let mut cur_idx = index;
     for proof_hash in proof {
-        if cur_idx % 2 == 0 {
+        if cur_idx.is_multiple_of(2) {
             cur_hash = hash_pair(&cur_hash, proof_hash);
         } else {
             cur_hash = hash_pair(proof_hash, &cur_hash);
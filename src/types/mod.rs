@@ -2,8 +2,13 @@ pub mod address;
 pub mod block;
 pub mod hash;
 pub mod merkle;
+pub mod proof;
 pub mod key_pair;
 pub mod transaction;
+pub mod multisig;
+pub mod receipt;
 pub mod mempool;
+pub mod fee_estimator;
 pub mod state;
-pub mod state_trie;
\ No newline at end of file
+pub mod state_trie;
+pub mod node_cache;
\ No newline at end of file
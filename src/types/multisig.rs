@@ -0,0 +1,152 @@
+use serde::{Serialize, Deserialize};
+use crate::types::address::Address;
+use crate::types::transaction::{verify, Transaction};
+
+/// 一个策略最多包含的公钥数
+pub const MAX_MULTISIG_KEYS: usize = 16;
+/// Ed25519 公钥长度
+const PUBLIC_KEY_LEN: usize = 32;
+/// 计算策略地址时的前缀，与单个公钥的地址区分
+const POLICY_ADDRESS_PREFIX: &[u8] = b"multisig";
+
+/// M-of-N 多签策略: 公钥按字节序排序且不重复，地址由策略本身决定
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct MultisigPolicy {
+    pub threshold: u32,
+    pub public_keys: Vec<Vec<u8>>,
+}
+
+/// 交易的多签证明: 策略以及 (公钥序号, 签名) 列表
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct MultisigProof {
+    pub policy: MultisigPolicy,
+    pub signatures: Vec<(u32, Vec<u8>)>,
+}
+
+impl MultisigPolicy {
+    /// 排序并去重公钥，检查阈值
+    pub fn new(threshold: u32, mut public_keys: Vec<Vec<u8>>) -> Result<Self, String> {
+        public_keys.sort();
+        public_keys.dedup();
+        let policy = Self { threshold, public_keys };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let n = self.public_keys.len();
+        if n == 0 || n > MAX_MULTISIG_KEYS {
+            return Err(format!("Policy must have 1 to {} keys", MAX_MULTISIG_KEYS));
+        }
+        if self.threshold == 0 || self.threshold as usize > n {
+            return Err(format!("Threshold must be between 1 and {}", n));
+        }
+        if self.public_keys.iter().any(|k| k.len() != PUBLIC_KEY_LEN) {
+            return Err("Invalid public key length".to_string());
+        }
+        // 规范形式保证同一组公钥只对应一个地址
+        if self.public_keys.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("Public keys must be sorted and distinct".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn address(&self) -> Address {
+        let mut bytes = POLICY_ADDRESS_PREFIX.to_vec();
+        bytes.extend_from_slice(&self.threshold.to_be_bytes());
+        for key in &self.public_keys {
+            bytes.extend_from_slice(key);
+        }
+        Address::from_public_key_bytes(&bytes)
+    }
+
+    pub fn key_index(&self, public_key: &[u8]) -> Option<u32> {
+        self.public_keys.iter().position(|k| k == public_key).map(|i| i as u32)
+    }
+}
+
+impl MultisigProof {
+    /// 每个签名的公钥序号都必须在策略范围内
+    pub fn check_indexes(&self) -> Result<(), String> {
+        let n = self.policy.public_keys.len();
+        match self.signatures.iter().find(|(index, _)| *index as usize >= n) {
+            Some((index, _)) => Err(format!("Signature index {} out of range for {} keys", index, n)),
+            None => Ok(()),
+        }
+    }
+
+    /// 序号为 `index` 的公钥对 `tx` 的签名是否有效，序号越界时无效
+    pub fn signature_is_valid(&self, tx: &Transaction, index: u32, signature: &[u8]) -> bool {
+        self.policy.public_keys.get(index as usize).is_some_and(|key| verify(tx, key, signature))
+    }
+
+    /// 是否已有序号为 `index` 的公钥的有效签名
+    pub fn has_valid_signature(&self, tx: &Transaction, index: u32) -> bool {
+        self.signatures.iter().any(|(i, signature)| *i == index && self.signature_is_valid(tx, index, signature))
+    }
+
+    /// 加入签名，同一公钥只保留一个签名
+    pub fn add_signature(&mut self, index: u32, signature: Vec<u8>) {
+        self.signatures.retain(|(i, _)| *i != index);
+        self.signatures.push((index, signature));
+        self.signatures.sort();
+    }
+
+    /// 策略合法，且至少 threshold 个不同公钥的签名有效
+    pub fn verify(&self, tx: &Transaction) -> bool {
+        if self.policy.validate().is_err() {
+            return false;
+        }
+        let mut seen = vec![false; self.policy.public_keys.len()];
+        for (index, signature) in &self.signatures {
+            let key = match self.policy.public_keys.get(*index as usize) {
+                Some(key) => key,
+                None => return false,
+            };
+            if seen[*index as usize] || !verify(tx, key, signature) {
+                return false;
+            }
+            seen[*index as usize] = true;
+        }
+        self.signatures.len() >= self.policy.threshold as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MultisigPolicy, MultisigProof};
+    use crate::types::address::Address;
+    use crate::types::key_pair;
+    use crate::types::transaction::{sign, Transaction};
+    use ring::signature::KeyPair;
+
+    #[test]
+    fn two_of_three() {
+        let keys: Vec<_> = (0..3).map(|_| key_pair::random()).collect();
+        let public_keys: Vec<Vec<u8>> = keys.iter().map(|k| k.public_key().as_ref().to_vec()).collect();
+        let policy = MultisigPolicy::new(2, public_keys.clone()).unwrap();
+        // 公钥顺序不影响地址
+        let reversed = MultisigPolicy::new(2, public_keys.iter().rev().cloned().collect()).unwrap();
+        assert_eq!(policy.address(), reversed.address());
+        assert_ne!(policy.address(), MultisigPolicy::new(1, public_keys.clone()).unwrap().address());
+        assert!(MultisigPolicy::new(4, public_keys).is_err());
+
+        let tx = Transaction::new(1, 0, 1, 10, Address::default(), 5, vec![]);
+        let mut proof = MultisigProof { policy: policy.clone(), signatures: vec![] };
+        let index = |k: &ring::signature::Ed25519KeyPair| policy.key_index(k.public_key().as_ref()).unwrap();
+
+        proof.add_signature(index(&keys[0]), sign(&tx, &keys[0]).as_ref().to_vec());
+        proof.add_signature(index(&keys[0]), sign(&tx, &keys[0]).as_ref().to_vec());
+        assert!(!proof.verify(&tx));
+        proof.add_signature(index(&keys[2]), sign(&tx, &keys[2]).as_ref().to_vec());
+        assert!(proof.verify(&tx));
+
+        // 签名对应的交易被修改后失效
+        let other = Transaction::new(1, 0, 1, 10, Address::default(), 6, vec![]);
+        assert!(!proof.verify(&other));
+        // 重复的公钥序号不能凑数
+        let first = proof.signatures[0].clone();
+        proof.signatures = vec![first.clone(), first];
+        assert!(!proof.verify(&tx));
+    }
+}
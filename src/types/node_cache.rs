@@ -0,0 +1,137 @@
+use super::{hash::H256, state_trie::Node};
+
+use serde::Serialize;
+
+use std::collections::{BTreeMap, HashMap};
+
+/// 状态节点启用缓存时默认缓存的个数
+pub const DEFAULT_STATE_CACHE_NODES: usize = 100_000;
+
+/// 已解码状态节点的 LRU 缓存。节点以哈希为键，内容不会变化，所以无需失效
+#[derive(Debug, Default)]
+pub struct NodeCache {
+    capacity: usize,
+    /// 节点及其最近一次访问的序号
+    nodes: HashMap<H256, (Node, u64)>,
+    /// 访问序号 -> 哈希，最小的最久未使用
+    recency: BTreeMap<u64, H256>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl NodeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, ..Self::default() }
+    }
+
+    /// 调整容量，超出的最久未使用节点被丢弃。容量为 0 时关闭缓存
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.nodes.len() > capacity {
+            self.evict();
+        }
+    }
+
+    pub fn get(&mut self, hash: &H256) -> Option<Node> {
+        let tick = self.tick();
+        match self.nodes.get_mut(hash) {
+            Some((node, last_used)) => {
+                self.recency.remove(last_used);
+                self.recency.insert(tick, *hash);
+                *last_used = tick;
+                self.hits += 1;
+                Some(node.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, node: Node) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.tick();
+        let hash = node.hash;
+        if let Some((_, last_used)) = self.nodes.insert(hash, (node, tick)) {
+            self.recency.remove(&last_used);
+        } else if self.nodes.len() > self.capacity {
+            self.evict();
+        }
+        self.recency.insert(tick, hash);
+    }
+
+    /// 节点从数据库删除后同步移出缓存
+    pub fn remove(&mut self, hash: &H256) {
+        if let Some((_, last_used)) = self.nodes.remove(hash) {
+            self.recency.remove(&last_used);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            capacity: self.capacity,
+            entries: self.nodes.len(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn evict(&mut self) {
+        if let Some((_, hash)) = self.recency.pop_first() {
+            self.nodes.remove(&hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NodeCache;
+    use crate::blockchain::Account;
+    use crate::types::address::Address;
+    use crate::types::state_trie::{Node, NodeData};
+
+    fn leaf(byte: u8) -> Node {
+        Node::new(NodeData::Leaf(Address::from([byte; 20]), Account { nonce: 0, balance: 1 }))
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = NodeCache::new(2);
+        let (a, b, c) = (leaf(1), leaf(2), leaf(3));
+        cache.insert(a.clone());
+        cache.insert(b.clone());
+        // 访问 a 后 b 成为最久未使用的节点
+        assert!(cache.get(&a.hash).is_some());
+        cache.insert(c.clone());
+        assert!(cache.get(&b.hash).is_none());
+        assert!(cache.get(&a.hash).is_some());
+        assert!(cache.get(&c.hash).is_some());
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 3, 1));
+
+        cache.set_capacity(1);
+        assert!(cache.get(&a.hash).is_none());
+        assert!(cache.get(&c.hash).is_some());
+        cache.set_capacity(0);
+        cache.insert(a.clone());
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Serialize, Deserialize};
+use crate::types::block::{Block, BlockHeader};
+use crate::types::hash::{H256, Hashable};
+use crate::types::merkle::{self, MerkleTree};
+use crate::types::address::Address;
+use crate::types::state_trie::{get_bit_at, NodeData};
+use crate::blockchain::Account;
+
+/// 交易包含在区块中的 Merkle 证明，只需区块头即可离线验证
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionProof {
+    pub tx_hash: H256,
+    pub header: BlockHeader,
+    /// 交易在区块中的序号
+    pub index: u32,
+    /// 区块中的交易数
+    pub leaf_count: u32,
+    /// 从叶子到根的兄弟节点哈希
+    pub path: Vec<H256>,
+}
+
+impl TransactionProof {
+    /// 区块中第 `index` 笔交易的证明
+    pub fn new(block: &Block, index: usize) -> Option<Self> {
+        let tx = block.data.get(index)?;
+        Some(Self {
+            tx_hash: tx.hash(),
+            header: block.header(),
+            index: index as u32,
+            leaf_count: block.data.len() as u32,
+            path: MerkleTree::new(&block.data).proof(index),
+        })
+    }
+
+    pub fn block_hash(&self) -> H256 {
+        self.header.hash()
+    }
+
+    /// 检查区块头满足其难度，且路径从交易哈希得到区块头中的 Merkle 根。
+    /// 区块是否在主链上需要调用者用可信的区块哈希确认
+    pub fn verify(&self) -> Result<(), String> {
+        if self.block_hash() > self.header.difficulty {
+            return Err("Block header does not satisfy its difficulty".to_string());
+        }
+        // 路径长度固定，防止把内部节点当作叶子
+        let depth = merkle::depth(self.leaf_count as usize);
+        if self.path.len() != depth {
+            return Err(format!("Proof path has {} hashes, expected {}", self.path.len(), depth));
+        }
+        let valid = merkle::verify(
+            &self.header.merkle_root,
+            &self.tx_hash,
+            &self.path,
+            self.index as usize,
+            self.leaf_count as usize,
+        );
+        if !valid {
+            return Err("Merkle path does not match the block's merkle root".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// 账户在状态树中的证明: 从根往下每层的兄弟哈希，以及路径末端的节点。
+/// 末端为该地址的 Leaf 时证明账户存在，为其他地址的 Leaf 或 Empty 时证明账户不存在
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountProof {
+    pub address: Address,
+    pub siblings: Vec<H256>,
+    pub terminal: NodeData,
+}
+
+impl AccountProof {
+    /// 用 `state_root` 验证证明，返回账户 (不存在时为 None)
+    pub fn verify(&self, state_root: &H256) -> Result<Option<Account>, String> {
+        if self.siblings.len() > 160 {
+            return Err("Proof is deeper than the address length".to_string());
+        }
+        let account = match &self.terminal {
+            NodeData::Leaf(address, account) if *address == self.address => Some(*account),
+            NodeData::Leaf(_, _) | NodeData::Empty => None,
+            NodeData::Branch(..) | NodeData::Extension(..) => return Err("Proof must end at a leaf or an empty node".to_string()),
+        };
+
+        let mut hash = self.terminal.hash();
+        for (depth, sibling) in self.siblings.iter().enumerate().rev() {
+            let branch = if get_bit_at(&self.address, depth) == 0 {
+                NodeData::Branch(hash, *sibling)
+            } else {
+                NodeData::Branch(*sibling, hash)
+            };
+            hash = branch.hash();
+        }
+        if hash != *state_root {
+            return Err("Proof does not match the state root".to_string());
+        }
+        Ok(account)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TransactionProof;
+    use crate::blockchain::Account;
+    use crate::database::Storage;
+    use crate::types::address::Address;
+    use crate::types::block::Block;
+    use crate::types::hash::{H256, Hashable};
+    use crate::types::state_trie::{NodeData, StateTrie};
+    use crate::types::transaction::{SignedTransaction, Transaction};
+    use std::sync::Arc;
+
+    #[test]
+    fn account_inclusion_and_exclusion() {
+        let storage = Arc::new(Storage::new_temporary());
+        let empty = StateTrie::new(storage.clone());
+        let absent = Address::from([0x10u8; 20]);
+        assert!(empty.prove(&absent).unwrap().verify(&empty.root_hash).unwrap().is_none());
+
+        // 0x00.. 与 0x01.. 共享前 7 位，0x80.. 在另一侧
+        let accounts: Vec<(Address, Account)> = [0x00u8, 0x01, 0x80]
+            .iter()
+            .map(|b| (Address::from([*b; 20]), Account { nonce: *b as u64, balance: 100 + *b as u64 }))
+            .collect();
+        let (root, nodes) = empty.insert_batch(accounts.iter().cloned().collect()).unwrap();
+        storage.batch_save_state_nodes(&nodes);
+        let trie = StateTrie::new_from_root(root, storage);
+
+        for (address, account) in &accounts {
+            let proof = trie.prove(address).unwrap();
+            let proven = proof.verify(&root).unwrap().unwrap();
+            assert_eq!((proven.nonce, proven.balance), (account.nonce, account.balance));
+        }
+
+        // 路径终止于另一个账户的 Leaf 或空子树
+        let proof = trie.prove(&Address::from([0x02u8; 20])).unwrap();
+        assert!(matches!(proof.terminal, NodeData::Empty | NodeData::Leaf(_, _)));
+        assert!(proof.verify(&root).unwrap().is_none());
+        let proof = trie.prove(&Address::from([0xffu8; 20])).unwrap();
+        assert!(matches!(proof.terminal, NodeData::Leaf(_, _)));
+        assert!(proof.verify(&root).unwrap().is_none());
+
+        // 篡改余额或使用其他状态根都无法通过验证
+        let mut forged = trie.prove(&accounts[2].0).unwrap();
+        forged.terminal = NodeData::Leaf(accounts[2].0, Account { nonce: 0, balance: 1_000_000 });
+        assert!(forged.verify(&root).is_err());
+        assert!(trie.prove(&accounts[0].0).unwrap().verify(&H256::default()).is_err());
+    }
+
+    #[test]
+    fn proves_every_transaction_of_a_block() {
+        let data: Vec<SignedTransaction> = (0..5)
+            .map(|nonce| SignedTransaction {
+                transaction: Transaction::new(1, nonce, 1, 10, Address::default(), 1, vec![]),
+                ..Default::default()
+            })
+            .collect();
+        let block = Block::new(H256::default(), 0, H256::from([255u8; 32]), 0, H256::default(), Transaction::default(), data);
+
+        for index in 0..5 {
+            let proof = TransactionProof::new(&block, index).unwrap();
+            assert_eq!(proof.block_hash(), block.hash());
+            assert_eq!(proof.verify(), Ok(()));
+        }
+        assert!(TransactionProof::new(&block, 5).is_none());
+
+        let mut wrong_index = TransactionProof::new(&block, 1).unwrap();
+        wrong_index.index = 0;
+        assert!(wrong_index.verify().is_err());
+        let mut other_tx = TransactionProof::new(&block, 1).unwrap();
+        other_tx.tx_hash = block.data[2].hash();
+        assert!(other_tx.verify().is_err());
+        let mut short_path = TransactionProof::new(&block, 4).unwrap();
+        short_path.path.pop();
+        assert!(short_path.verify().is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Serialize, Deserialize};
+use crate::types::block::Block;
+use crate::types::hash::{H256, Hashable};
+
+/// 交易被打包后的执行结果
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub tx_hash: H256,
+    pub block_hash: H256,
+    pub index: u32,
+    pub gas_used: u64,
+    pub fee_paid: u64,
+}
+
+impl Receipt {
+    /// 为区块中的每笔交易生成 Receipt
+    pub fn for_block(block: &Block) -> Vec<Receipt> {
+        let block_hash = block.hash();
+        block.data.iter().enumerate()
+            .map(|(index, tx)| Receipt {
+                tx_hash: tx.hash(),
+                block_hash,
+                index: index as u32,
+                gas_used: tx.gas_used(),
+                fee_paid: tx.fee_paid(),
+            })
+            .collect()
+    }
+}
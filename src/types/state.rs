@@ -1,6 +1,4 @@
-use std::collections::HashMap;
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct State {
 }
 
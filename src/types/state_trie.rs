@@ -1,18 +1,109 @@
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use crate::types::hash::{H256, Hashable};
 use crate::types::address::Address;
 use crate::blockchain::Account;
-use crate::database::Storage;
+use crate::database::{StatePin, Storage};
+use crate::types::proof::AccountProof;
 use std::sync::Arc;
-use std::hash::Hash;
 use ring::digest;
 
+/// 状态节点的存储格式版本。1: 单侧为空的 Branch 链压缩为 Extension
+pub const STATE_NODE_FORMAT: u32 = 1;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum NodeData {
     Empty,
     Leaf(Address, Account),
     Branch(H256, H256), 
+    /// 连续 `path.len()` 层只有一侧非空的 Branch，子节点总是 Branch。
+    /// 哈希与展开后的 Branch 链相同，所以压缩不改变状态根和证明
+    Extension(BitPath, H256),
+}
+
+/// 最多 160 位的比特串，高位在前打包
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BitPath {
+    len: u8,
+    bits: [u8; 20],
+}
+
+impl BitPath {
+    /// 地址从第 `start` 位开始的 `len` 位
+    pub fn from_address(address: &Address, start: usize, len: usize) -> Self {
+        let mut path = Self::default();
+        for i in start..start + len {
+            path.push(get_bit_at(address, i));
+        }
+        path
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn bit(&self, index: usize) -> u8 {
+        (self.bits[index / 8] >> (7 - index % 8)) & 1
+    }
+
+    fn push(&mut self, bit: u8) {
+        let i = self.len();
+        self.bits[i / 8] |= bit << (7 - i % 8);
+        self.len += 1;
+    }
+
+    fn slice(&self, from: usize, to: usize) -> Self {
+        let mut path = Self::default();
+        for i in from..to {
+            path.push(self.bit(i));
+        }
+        path
+    }
+
+    fn concat(&self, other: &Self) -> Self {
+        let mut path = *self;
+        for i in 0..other.len() {
+            path.push(other.bit(i));
+        }
+        path
+    }
+
+    /// 与地址从第 `depth` 位开始的公共前缀长度
+    fn common_prefix(&self, address: &Address, depth: usize) -> usize {
+        (0..self.len())
+            .take_while(|i| self.bit(*i) == get_bit_at(address, depth + i))
+            .count()
+    }
+}
+
+/// 在 `child` 之上加一条 `path` 的 Branch 链后的哈希
+fn fold_path(path: &BitPath, child: H256) -> H256 {
+    let empty = NodeData::Empty.hash();
+    (0..path.len()).rev().fold(child, |hash, i| {
+        if path.bit(i) == 0 {
+            NodeData::Branch(hash, empty).hash()
+        } else {
+            NodeData::Branch(empty, hash).hash()
+        }
+    })
+}
+
+/// 账户在两个状态之间的变化，None 表示账户不存在
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountChange {
+    pub address: Address,
+    pub before: Option<Account>,
+    pub after: Option<Account>,
+}
+
+impl AccountChange {
+    fn new(address: Address) -> Self {
+        Self { address, before: None, after: None }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -43,6 +134,7 @@ impl Hashable for NodeData {
                 bytes.extend_from_slice(l.as_ref());
                 bytes.extend_from_slice(r.as_ref());
             }
+            NodeData::Extension(path, child) => return fold_path(path, *child),
         }
         let hash = digest::digest(&digest::SHA256, &bytes);
         let mut hash_bytes = [0u8; 32];
@@ -54,10 +146,16 @@ impl Hashable for NodeData {
 // 定义别名方便使用
 type UpdatePair = (Address, Account);
 
+/// 二叉 Merkle 状态树。树的形状只由账户集合决定: 含两个以上账户的子树是 Branch，
+/// 只含一个账户的子树直接是该账户的 Leaf，不含账户的子树是 Empty。
+/// 因此同一组账户无论以什么顺序、单个还是批量写入，根都相同。
+/// 存储时单侧为空的 Branch 链压缩为一个 Extension 节点
 #[derive(Clone)]
 pub struct StateTrie {
     pub root_hash: H256,
     storage: Arc<Storage>, // 持有 DB 引用用于懒加载读取
+    /// 由 `open` 打开时持有，读取期间状态回收不会删除这个状态
+    _pin: Option<Arc<StatePin>>,
 }
 
 impl StateTrie {
@@ -68,6 +166,7 @@ impl StateTrie {
         Self {
             root_hash: empty_node.hash,
             storage,
+            _pin: None,
         }
     }
 
@@ -76,22 +175,37 @@ impl StateTrie {
         Self {
             root_hash: root,
             storage,
+            _pin: None,
+        }
+    }
+
+    /// 打开已有状态并登记为正在读取，克隆共享同一个登记。
+    /// 状态已被剪枝或正在被回收时返回 Err
+    pub fn open(root: H256, storage: Arc<Storage>) -> Result<Self, String> {
+        let pin = storage.pin_state(root)?;
+        let trie = Self { root_hash: root, storage, _pin: Some(Arc::new(pin)) };
+        if !trie.is_available() {
+            return Err(format!("State {} has been pruned", root));
         }
+        Ok(trie)
+    }
+
+    /// 状态是否仍在数据库中 (剪枝模式下旧状态会被删除)
+    pub fn is_available(&self) -> bool {
+        self.root_hash == NodeData::Empty.hash() || self.storage.get_state_node(&self.root_hash).is_some()
     }
 
-    /// 获取账户余额
-    pub fn get(&self, address: &Address) -> Option<Account> {
+    /// 获取账户，账户不存在时返回 None，节点缺失时返回 Err
+    pub fn get(&self, address: &Address) -> Result<Option<Account>, String> {
         self.get_recursive(self.root_hash, address, 0)
     }
 
-    fn get_recursive(&self, node_hash: H256, key: &Address, depth: usize) -> Option<Account> {
+    fn get_recursive(&self, node_hash: H256, key: &Address, depth: usize) -> Result<Option<Account>, String> {
         // 从 DB 读取节点 (Lazy Load)
-        let node: Node = self.storage.get_state_node(&node_hash)?;
-
-        match node.data {
-            NodeData::Empty => None,
+        match self.load(node_hash)? {
+            NodeData::Empty => Ok(None),
             NodeData::Leaf(leaf_addr, account) => {
-                if leaf_addr == *key { Some(account) } else { None }
+                if leaf_addr == *key { Ok(Some(account)) } else { Ok(None) }
             }
             NodeData::Branch(left, right) => {
                 let bit = get_bit_at(key, depth);
@@ -101,21 +215,157 @@ impl StateTrie {
                     self.get_recursive(right, key, depth + 1)
                 }
             }
+            NodeData::Extension(path, child) => {
+                if path.common_prefix(key, depth) == path.len() {
+                    self.get_recursive(child, key, depth + path.len())
+                } else {
+                    Ok(None)
+                }
+            }
         }
     }
 
-    pub fn insert(&self, address: Address, account: Account) -> (H256, HashMap<H256, Node>) {
-        let mut new_nodes = HashMap::new();
-        let new_root = self.insert_recursive(self.root_hash, address, account, 0, &mut new_nodes);
-        (new_root, new_nodes)
+    /// 沿 `address` 的路径收集兄弟哈希，直到 Leaf 或 Empty。节点缺失时返回 None
+    pub fn prove(&self, address: &Address) -> Option<AccountProof> {
+        let empty_hash = NodeData::Empty.hash();
+        let mut siblings = Vec::new();
+        let mut node_hash = self.root_hash;
+        loop {
+            let data = match self.storage.get_state_node(&node_hash) {
+                Some(node) => node.data,
+                // 空子树的节点不一定被持久化
+                None if node_hash == empty_hash => NodeData::Empty,
+                None => return None,
+            };
+            match data {
+                NodeData::Branch(left, right) => {
+                    if get_bit_at(address, siblings.len()) == 0 {
+                        siblings.push(right);
+                        node_hash = left;
+                    } else {
+                        siblings.push(left);
+                        node_hash = right;
+                    }
+                }
+                // 展开为 Branch 链，兄弟都是空子树
+                NodeData::Extension(path, child) => {
+                    let matched = path.common_prefix(address, siblings.len());
+                    siblings.extend(std::iter::repeat_n(empty_hash, matched));
+                    if matched == path.len() {
+                        node_hash = child;
+                    } else {
+                        // 地址在这里离开路径，进入空的一侧
+                        siblings.push(fold_path(&path.slice(matched + 1, path.len()), child));
+                        return Some(AccountProof { address: *address, siblings, terminal: NodeData::Empty });
+                    }
+                }
+                terminal => return Some(AccountProof { address: *address, siblings, terminal }),
+            }
+        }
+    }
+
+    /// 按地址顺序列出所有账户 (左子树在前)，节点缺失时返回 Err
+    pub fn accounts(&self) -> Result<Vec<(Address, Account)>, String> {
+        let mut accounts = Vec::new();
+        self.collect_accounts(self.load(self.root_hash)?, &mut accounts)?;
+        Ok(accounts)
+    }
+
+    /// 与 `other` 相比发生变化的账户，按地址排序。哈希相同的子树直接跳过，
+    /// 所以代价与变化的账户数而不是账户总数成正比
+    pub fn diff(&self, other: &StateTrie) -> Result<Vec<AccountChange>, String> {
+        let mut changes = Vec::new();
+        self.diff_data(self.load(self.root_hash)?, other.load(other.root_hash)?, other, &mut changes)?;
+        Ok(changes)
+    }
+
+    fn diff_data(&self, before: NodeData, after: NodeData, other: &StateTrie, changes: &mut Vec<AccountChange>) -> Result<(), String> {
+        if before.hash() == after.hash() {
+            return Ok(());
+        }
+        match (self.split(before.clone())?, other.split(after.clone())?) {
+            (Some((before_left, before_right)), Some((after_left, after_right))) => {
+                self.diff_data(before_left, after_left, other, changes)?;
+                self.diff_data(before_right, after_right, other, changes)
+            }
+            // 至少一侧是 Leaf 或 Empty，子树中最多只有一个账户，逐个比较
+            _ => {
+                let (mut old, mut new) = (Vec::new(), Vec::new());
+                self.collect_accounts(before, &mut old)?;
+                other.collect_accounts(after, &mut new)?;
+                let mut merged: BTreeMap<Address, AccountChange> = BTreeMap::new();
+                for (address, account) in old {
+                    merged.entry(address).or_insert_with(|| AccountChange::new(address)).before = Some(account);
+                }
+                for (address, account) in new {
+                    merged.entry(address).or_insert_with(|| AccountChange::new(address)).after = Some(account);
+                }
+                changes.extend(merged.into_values().filter(|c| c.before != c.after));
+                Ok(())
+            }
+        }
+    }
+
+    /// 把 Branch 或 Extension 拆成下一层的左右子树，Extension 的另一侧为空
+    fn split(&self, data: NodeData) -> Result<Option<(NodeData, NodeData)>, String> {
+        match data {
+            NodeData::Branch(left, right) => Ok(Some((self.load(left)?, self.load(right)?))),
+            NodeData::Extension(path, child) => {
+                let rest = if path.len() == 1 {
+                    self.load(child)?
+                } else {
+                    NodeData::Extension(path.slice(1, path.len()), child)
+                };
+                if path.bit(0) == 0 {
+                    Ok(Some((rest, NodeData::Empty)))
+                } else {
+                    Ok(Some((NodeData::Empty, rest)))
+                }
+            }
+            NodeData::Leaf(..) | NodeData::Empty => Ok(None),
+        }
+    }
+
+    fn collect_accounts(&self, data: NodeData, accounts: &mut Vec<(Address, Account)>) -> Result<(), String> {
+        let mut stack = vec![data];
+        while let Some(data) = stack.pop() {
+            match data {
+                NodeData::Empty => {}
+                NodeData::Leaf(address, account) => accounts.push((address, account)),
+                NodeData::Branch(left, right) => stack.extend([self.load(right)?, self.load(left)?]),
+                NodeData::Extension(_, child) => stack.push(self.load(child)?),
+            }
+        }
+        Ok(())
+    }
+
+    /// 读取节点内容，空子树的节点不一定被持久化
+    fn load(&self, hash: H256) -> Result<NodeData, String> {
+        match self.storage.get_state_node(&hash) {
+            Some(node) => Ok(node.data),
+            None if hash == NodeData::Empty.hash() => Ok(NodeData::Empty),
+            None => Err(format!("State node {} is missing", hash)),
+        }
+    }
+
+    /// 写入账户，空账户等同于删除。与 `insert_batch` 走同一条路径，保证根相同
+    pub fn insert(&self, address: Address, account: Account) -> Result<(H256, HashMap<H256, Node>), String> {
+        self.insert_batch(HashMap::from([(address, account)]))
+    }
+
+    /// 删除账户。只剩一个账户的子树收缩为该账户的 Leaf，
+    /// 得到的根与从未插入过该账户的树相同
+    pub fn delete(&self, address: &Address) -> Result<(H256, HashMap<H256, Node>), String> {
+        self.insert_batch(HashMap::from([(*address, Account::default())]))
     }
 
-    pub fn insert_batch(&self, updates: HashMap<Address, Account>) -> (H256, HashMap<H256, Node>) {
+    /// 批量写入账户，其中的空账户会被删除。只返回新根可达的新节点
+    pub fn insert_batch(&self, updates: HashMap<Address, Account>) -> Result<(H256, HashMap<H256, Node>), String> {
         let mut new_nodes = HashMap::new();
         let update_list: Vec<UpdatePair> = updates.into_iter().collect();
 
         if update_list.is_empty() {
-            return (self.root_hash, new_nodes);
+            return Ok((self.root_hash, new_nodes));
         }
 
         let new_root = self.insert_batch_recursive(
@@ -123,9 +373,9 @@ impl StateTrie {
             &update_list, 
             0, 
             &mut new_nodes
-        );
+        )?;
 
-        (new_root, new_nodes)
+        Ok((new_root, retain_reachable(new_root, new_nodes)))
     }
 
     fn insert_batch_recursive(
@@ -134,29 +384,26 @@ impl StateTrie {
         updates: &[UpdatePair], 
         depth: usize,
         new_nodes: &mut HashMap<H256, Node>,
-    ) -> H256 {
+    ) -> Result<H256, String> {
         if updates.is_empty() {
-            return node_hash;
+            return Ok(node_hash);
         }
 
-        let node = if let Some(n) = new_nodes.get(&node_hash) {
-            n.clone()
-        } else {
-            self.storage.get_state_node(&node_hash).unwrap_or_else(|| Node::new(NodeData::Empty))
-        };
+        // 空账户只用于删除，不建 Leaf
+        let live: Vec<UpdatePair> = updates.iter().filter(|(_, acc)| !acc.is_empty()).cloned().collect();
 
-        match node.data {
+        match self.load_data(node_hash, new_nodes)? {
             NodeData::Empty => {
-                self.build_subtree_from_scratch(updates, depth, new_nodes)
+                self.build_subtree_from_scratch(&live, depth, new_nodes)
             }
 
             NodeData::Leaf(curr_addr, curr_acc) => {
                 let overridden = updates.iter().any(|(addr, _)| *addr == curr_addr);
 
                 if overridden {
-                    self.build_subtree_from_scratch(updates, depth, new_nodes)
+                    self.build_subtree_from_scratch(&live, depth, new_nodes)
                 } else {
-                    let mut combined_updates = updates.to_vec();
+                    let mut combined_updates = live;
                     combined_updates.push((curr_addr, curr_acc));
                     self.build_subtree_from_scratch(&combined_updates, depth, new_nodes)
                 }
@@ -170,12 +417,41 @@ impl StateTrie {
                     });
 
                 // 递归构建
-                let new_left = self.insert_batch_recursive(left, &left_updates, depth + 1, new_nodes);
-                let new_right = self.insert_batch_recursive(right, &right_updates, depth + 1, new_nodes);
+                let new_left = self.insert_batch_recursive(left, &left_updates, depth + 1, new_nodes)?;
+                let new_right = self.insert_batch_recursive(right, &right_updates, depth + 1, new_nodes)?;
 
-                let new_node = Node::new(NodeData::Branch(new_left, new_right));
-                new_nodes.insert(new_node.hash, new_node.clone());
-                new_node.hash
+                // 删除后子树可能只剩一个账户
+                self.join(new_left, new_right, new_nodes)
+            }
+
+            NodeData::Extension(path, child) => {
+                let matched = updates.iter()
+                    .map(|(addr, _)| path.common_prefix(addr, depth))
+                    .min()
+                    .unwrap_or(0);
+                if matched == path.len() {
+                    let new_child = self.insert_batch_recursive(child, updates, depth + path.len(), new_nodes)?;
+                    return self.extend(&path, new_child, new_nodes);
+                }
+
+                // 在第 matched 位分叉: 原有子树在 path 的一侧，其余更新在另一侧
+                let rest = path.slice(matched + 1, path.len());
+                let existing = self.extend(&rest, child, new_nodes)?;
+                let split = depth + matched;
+                let (same, other): (Vec<UpdatePair>, Vec<UpdatePair>) = updates.iter().cloned()
+                    .partition(|(addr, _)| get_bit_at(addr, split) == path.bit(matched));
+                let new_same = self.insert_batch_recursive(existing, &same, split + 1, new_nodes)?;
+                let new_other = self.build_subtree_from_scratch(
+                    &other.into_iter().filter(|(_, acc)| !acc.is_empty()).collect::<Vec<_>>(),
+                    split + 1,
+                    new_nodes,
+                )?;
+                let node = if path.bit(matched) == 0 {
+                    self.join(new_same, new_other, new_nodes)?
+                } else {
+                    self.join(new_other, new_same, new_nodes)?
+                };
+                self.extend(&path.slice(0, matched), node, new_nodes)
             }
         }
     }
@@ -185,155 +461,432 @@ impl StateTrie {
         items: &[UpdatePair],
         depth: usize,
         new_nodes: &mut HashMap<H256, Node>
-    ) -> H256 {
+    ) -> Result<H256, String> {
         if items.is_empty() {
             let empty = Node::new(NodeData::Empty);
-            return empty.hash;
+            return Ok(empty.hash);
         }
 
         if items.len() == 1 {
             let (addr, acc) = &items[0];
-            let leaf = Node::new(NodeData::Leaf(addr.clone(), acc.clone()));
+            let leaf = Node::new(NodeData::Leaf(*addr, *acc));
             new_nodes.insert(leaf.hash, leaf.clone());
-            return leaf.hash;
+            return Ok(leaf.hash);
         }
 
+        // 所有账户的公共前缀压缩为一个 Extension，之后的第一位必然分叉
+        let first = &items[0].0;
+        let shared = (depth..160)
+            .take_while(|i| items.iter().all(|(addr, _)| get_bit_at(addr, *i) == get_bit_at(first, *i)))
+            .count();
+        let split = depth + shared;
+
         let (left_items, right_items): (Vec<UpdatePair>, Vec<UpdatePair>) = 
             items.iter().cloned().partition(|(addr, _)| {
-                get_bit_at(addr, depth) == 0
+                get_bit_at(addr, split) == 0
             });
 
-        let left_hash = self.build_subtree_from_scratch(&left_items, depth + 1, new_nodes);
-        let right_hash = self.build_subtree_from_scratch(&right_items, depth + 1, new_nodes);
+        let left_hash = self.build_subtree_from_scratch(&left_items, split + 1, new_nodes)?;
+        let right_hash = self.build_subtree_from_scratch(&right_items, split + 1, new_nodes)?;
 
         let branch = Node::new(NodeData::Branch(left_hash, right_hash));
         new_nodes.insert(branch.hash, branch.clone());
-        branch.hash
+        self.extend(&BitPath::from_address(first, depth, shared), branch.hash, new_nodes)
     }
 
-    fn insert_recursive(
-        &self, 
-        node_hash: H256, 
-        address: Address, 
-        account: Account, 
-        depth: usize,
-        new_nodes: &mut HashMap<H256, Node>
-    ) -> H256 {
-        let node = if let Some(n) = new_nodes.get(&node_hash) {
-            n.clone()
-        } else {
-            self.storage.get_state_node(&node_hash).unwrap_or_else(|| Node::new(NodeData::Empty))
+    /// 以两个子树构造父节点: 两边都空时为 Empty，只有一个 Leaf 时直接上移该 Leaf，
+    /// 另一边为空时并入 Extension
+    fn join(&self, left: H256, right: H256, new_nodes: &mut HashMap<H256, Node>) -> Result<H256, String> {
+        let left_data = self.load_data(left, new_nodes)?;
+        let right_data = self.load_data(right, new_nodes)?;
+        let mut path = BitPath::default();
+        match (&left_data, &right_data) {
+            (NodeData::Empty, NodeData::Empty) => Ok(NodeData::Empty.hash()),
+            (NodeData::Empty, NodeData::Leaf(..)) => Ok(right),
+            (NodeData::Leaf(..), NodeData::Empty) => Ok(left),
+            (NodeData::Empty, _) => {
+                path.push(1);
+                self.extend(&path, right, new_nodes)
+            }
+            (_, NodeData::Empty) => {
+                path.push(0);
+                self.extend(&path, left, new_nodes)
+            }
+            _ => {
+                let branch = Node::new(NodeData::Branch(left, right));
+                new_nodes.insert(branch.hash, branch.clone());
+                Ok(branch.hash)
+            }
+        }
+    }
+
+    /// 在子树之上加一段路径。Leaf 和 Empty 不受深度影响，原样返回
+    fn extend(&self, path: &BitPath, node_hash: H256, new_nodes: &mut HashMap<H256, Node>) -> Result<H256, String> {
+        if path.is_empty() {
+            return Ok(node_hash);
+        }
+        let data = match self.load_data(node_hash, new_nodes)? {
+            NodeData::Empty | NodeData::Leaf(..) => return Ok(node_hash),
+            NodeData::Extension(rest, child) => NodeData::Extension(path.concat(&rest), child),
+            NodeData::Branch(..) => NodeData::Extension(*path, node_hash),
         };
+        let node = Node { hash: fold_path(path, node_hash), data };
+        new_nodes.insert(node.hash, node.clone());
+        Ok(node.hash)
+    }
 
-        match node.data {
-            NodeData::Empty => {
-                let new_node = Node::new(NodeData::Leaf(address, account));
-                new_nodes.insert(new_node.hash, new_node.clone());
-                new_node.hash
-            },
-            
-            NodeData::Leaf(curr_addr, curr_acc) => {
-                if curr_addr == address {
-                    let new_node = Node::new(NodeData::Leaf(address, account));
-                    new_nodes.insert(new_node.hash, new_node.clone());
-                    new_node.hash
-                } else {
-                    let empty = Node::new(NodeData::Empty);
-                    new_nodes.insert(empty.hash, empty.clone());
-                    let branch_node = Node::new(NodeData::Branch(empty.hash, empty.hash));
-                    let h1 = self.insert_recursive_on_data(branch_node, curr_addr, curr_acc, depth, new_nodes);
-                    self.insert_recursive(h1, address, account, depth, new_nodes)
-                }
-            },
-            
-            NodeData::Branch(left, right) => {
-                let bit = get_bit_at(&address, depth);
-                let new_data = if bit == 0 {
-                    let new_left = self.insert_recursive(left, address, account, depth + 1, new_nodes);
-                    NodeData::Branch(new_left, right)
-                } else {
-                    let new_right = self.insert_recursive(right, address, account, depth + 1, new_nodes);
-                    NodeData::Branch(left, new_right)
-                };
-                
-                let new_node = Node::new(new_data);
-                new_nodes.insert(new_node.hash, new_node.clone());
-                new_node.hash
+    /// 优先读取本次更新新建的节点，其余与 `load` 相同
+    fn load_data(&self, node_hash: H256, new_nodes: &HashMap<H256, Node>) -> Result<NodeData, String> {
+        match new_nodes.get(&node_hash) {
+            Some(n) => Ok(n.data.clone()),
+            None => self.load(node_hash),
+        }
+    }
+} 
+
+/// 更新过程中分叉 Extension 会产生中间节点，只保留新根引用到的
+fn retain_reachable(root: H256, mut created: HashMap<H256, Node>) -> HashMap<H256, Node> {
+    let mut kept = HashMap::new();
+    let mut stack = vec![root];
+    while let Some(hash) = stack.pop() {
+        if let Some(node) = created.remove(&hash) {
+            match &node.data {
+                NodeData::Branch(left, right) => stack.extend([*left, *right]),
+                NodeData::Extension(_, child) => stack.push(*child),
+                _ => {}
             }
+            kept.insert(hash, node);
         }
     }
-    
+    kept
+}
 
-    fn insert_recursive_on_data(
-        &self, 
-        node: Node, 
-        address: Address, 
-        account: Account, 
-        depth: usize, 
-        new_nodes: &mut HashMap<H256, Node>
-    ) -> H256 {
-        let new_data = match node.data {
-            NodeData::Empty => NodeData::Leaf(address, account),
-            NodeData::Leaf(curr_addr, curr_acc) => {
-                if curr_addr == address {
-                    NodeData::Leaf(address, account)
-                } else {
-                    let curr_bit = get_bit_at(&curr_addr, depth);
-                    let new_bit = get_bit_at(&address, depth);
-
-                    if curr_bit != new_bit {
-                        let curr_node_new = Node::new(NodeData::Leaf(curr_addr, curr_acc));
-                        let new_node_new = Node::new(NodeData::Leaf(address, account));
-                        
-                        new_nodes.insert(curr_node_new.hash, curr_node_new.clone());
-                        new_nodes.insert(new_node_new.hash, new_node_new.clone());
-                        
-                        if new_bit == 0 {
-                            NodeData::Branch(new_node_new.hash, curr_node_new.hash)
-                        } else {
-                            NodeData::Branch(curr_node_new.hash, new_node_new.hash)
-                        }
-                    } else {
-                        let empty = Node::new(NodeData::Empty);
-                        new_nodes.insert(empty.hash, empty.clone());
-                        
-                        let child_hash = self.insert_recursive_on_data(
-                            node.clone(), 
-                            address, 
-                            account, 
-                            depth + 1, 
-                            new_nodes
-                        );
-                        
-                        if new_bit == 0 {
-                            NodeData::Branch(child_hash, empty.hash)
-                        } else {
-                            NodeData::Branch(empty.hash, child_hash)
-                        }
-                    }
-                }
-            },
-            NodeData::Branch(left, right) => {
-                let bit = get_bit_at(&address, depth);
-                if bit == 0 {
-                    let new_left = self.insert_recursive(left, address, account, depth + 1, new_nodes);
-                    NodeData::Branch(new_left, right)
-                } else {
-                    let new_right = self.insert_recursive(right, address, account, depth + 1, new_nodes);
-                    NodeData::Branch(left, new_right)
+/// 把旧格式中单侧为空的 Branch 链改写为 Extension。键 (哈希) 不变，
+/// 已有的状态根和区块头仍然有效。返回改写的节点数
+pub fn compress_state_nodes(storage: &Storage) -> usize {
+    let empty = NodeData::Empty.hash();
+    // 单侧为空的 Branch: 返回非空一侧的位和子节点
+    let single_child = |data: &NodeData| match data {
+        NodeData::Branch(left, right) if *right == empty && *left != empty => Some((0, *left)),
+        NodeData::Branch(left, right) if *left == empty && *right != empty => Some((1, *right)),
+        _ => None,
+    };
+
+    let mut rewritten = 0;
+    let mut batch = HashMap::new();
+    for item in storage.state_nodes.iter() {
+        let (_, bytes) = item.expect("Failed to read state node");
+        let node: Node = bincode::deserialize(&bytes).expect("Corrupted state node");
+        let (bit, mut curr) = match single_child(&node.data) {
+            Some(step) => step,
+            None => continue,
+        };
+
+        let mut path = BitPath::default();
+        path.push(bit);
+        let target = loop {
+            match storage.get_state_node(&curr).map(|n| n.data) {
+                Some(NodeData::Extension(rest, child)) => {
+                    path = path.concat(&rest);
+                    break Some(child);
                 }
+                Some(data @ NodeData::Branch(..)) => match single_child(&data) {
+                    Some((bit, child)) => {
+                        path.push(bit);
+                        curr = child;
+                    }
+                    None => break Some(curr),
+                },
+                // 链的末端不是 Branch 时不是规范形式，保持原样
+                _ => break None,
             }
         };
 
-        let new_node = Node::new(new_data);
-        new_nodes.insert(new_node.hash, new_node.clone());
-        new_node.hash
+        if let Some(child) = target {
+            batch.insert(node.hash, Node { hash: node.hash, data: NodeData::Extension(path, child) });
+            rewritten += 1;
+        }
+        if batch.len() >= 10_000 {
+            storage.batch_save_state_nodes(&batch);
+            batch.clear();
+        }
     }
-} 
+    storage.batch_save_state_nodes(&batch);
+    rewritten
+}
 
-fn get_bit_at(data: &Address, index: usize) -> u8 {
+pub(crate) fn get_bit_at(data: &Address, index: usize) -> u8 {
     if index >= 160 { return 0; }
     let byte_index = index / 8;
     let bit_index = 7 - (index % 8);
     (data.as_ref()[byte_index] >> bit_index) & 1
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::{compress_state_nodes, get_bit_at, AccountChange, Node, NodeData, StateTrie};
+    use crate::blockchain::Account;
+    use crate::types::hash::{H256, Hashable};
+    use std::time::Instant;
+    use crate::database::Storage;
+    use crate::types::address::Address;
+    use proptest::prelude::*;
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::sync::Arc;
+
+    /// 每个字节只取 0 或 1，地址之间有很长的公共前缀
+    fn address() -> impl Strategy<Value = Address> {
+        prop::array::uniform20(0u8..2).prop_map(Address::from)
+    }
+
+    /// None 表示删除，余额为 0 的账户也等同于删除
+    fn operation() -> impl Strategy<Value = (Address, Option<Account>)> {
+        let account = (0u64..2, 0u64..3).prop_map(|(nonce, balance)| Account { nonce, balance });
+        (address(), prop::option::weighted(0.6, account))
+    }
+
+    /// 保存更新产生的节点并打开新的根
+    fn apply(trie: &StateTrie, update: Result<(H256, HashMap<H256, Node>), String>) -> StateTrie {
+        let (root, nodes) = update.unwrap();
+        trie.storage.batch_save_state_nodes(&nodes);
+        StateTrie::new_from_root(root, trie.storage.clone())
+    }
+
+    /// 旧格式: 不压缩的二叉树，单侧为空的 Branch 逐层保存
+    fn build_uncompressed(items: &[(Address, Account)], depth: usize, nodes: &mut HashMap<H256, Node>) -> H256 {
+        let node = match items {
+            [] => return NodeData::Empty.hash(),
+            [(address, account)] => Node::new(NodeData::Leaf(*address, *account)),
+            _ => {
+                let (left, right): (Vec<_>, Vec<_>) = items.iter().partition(|(a, _)| get_bit_at(a, depth) == 0);
+                let left = build_uncompressed(&left, depth + 1, nodes);
+                let right = build_uncompressed(&right, depth + 1, nodes);
+                Node::new(NodeData::Branch(left, right))
+            }
+        };
+        nodes.insert(node.hash, node.clone());
+        node.hash
+    }
+
+    /// 查找 `address` 时读取的节点数，以及不压缩时需要读取的节点数
+    fn lookup_cost(trie: &StateTrie, address: &Address) -> (usize, usize) {
+        let (mut reads, mut levels) = (0, 0);
+        let mut hash = trie.root_hash;
+        while let Some(node) = trie.storage.get_state_node(&hash) {
+            reads += 1;
+            levels += 1;
+            match node.data {
+                NodeData::Branch(left, right) => {
+                    hash = if get_bit_at(address, levels - 1) == 0 { left } else { right };
+                }
+                NodeData::Extension(path, child) => {
+                    levels += path.len() - 1;
+                    hash = child;
+                }
+                _ => break,
+            }
+        }
+        (reads, levels)
+    }
+
+    /// 有长公共前缀的地址: 只有最后两个字节不同
+    fn clustered(count: usize) -> Vec<(Address, Account)> {
+        (0..count)
+            .map(|i| {
+                let mut bytes = [0x5au8; 20];
+                bytes[18..].copy_from_slice(&(i as u16).to_be_bytes());
+                (Address::from(bytes), Account { nonce: 1, balance: i as u64 + 1 })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn migration_keeps_roots_and_shortens_paths() {
+        let mut accounts = clustered(20);
+        accounts.extend((0..20u8).map(|i| (Address::from([i.wrapping_mul(37); 20]), Account { nonce: 0, balance: 5 })));
+
+        let storage = Arc::new(Storage::new_temporary());
+        let mut nodes = HashMap::new();
+        let root = build_uncompressed(&accounts, 0, &mut nodes);
+        storage.batch_save_state_nodes(&nodes);
+        let old = StateTrie::new_from_root(root, storage.clone());
+        let before: Vec<usize> = accounts.iter().map(|(a, _)| lookup_cost(&old, a).0).collect();
+
+        // 压缩只改变存储，不改变哈希
+        let fresh = StateTrie::new(Arc::new(Storage::new_temporary()));
+        assert_eq!(fresh.insert_batch(accounts.iter().cloned().collect()).unwrap().0, root);
+
+        assert!(compress_state_nodes(&storage) > 0);
+        let migrated = StateTrie::new_from_root(root, storage);
+        for ((address, account), before) in accounts.iter().zip(before) {
+            assert_eq!(migrated.get(address).unwrap().map(|a| a.balance), Some(account.balance));
+            assert!(migrated.prove(address).unwrap().verify(&root).unwrap().is_some());
+            let (reads, levels) = lookup_cost(&migrated, address);
+            assert_eq!(levels, before);
+            assert!(reads <= before);
+        }
+        let (address, _) = accounts[0];
+        assert!(lookup_cost(&migrated, &address).0 < 20);
+
+        // 迁移后的树上继续更新，与新建的树一致
+        let update: HashMap<_, _> = [(accounts[3].0, Account::default()), (Address::from([0x5au8; 20]), Account { nonce: 0, balance: 9 })].into();
+        let compressed = apply(&fresh, fresh.insert_batch(accounts.iter().cloned().collect()));
+        assert_eq!(migrated.insert_batch(update.clone()).unwrap().0, compressed.insert_batch(update).unwrap().0);
+    }
+
+    /// 基准: cargo test --release bench_state_trie -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_state_trie() {
+        for (name, accounts) in [
+            ("random", (0..10_000).map(|i| (Address::from(rand::random::<[u8; 20]>()), Account { nonce: 0, balance: i + 1 })).collect()),
+            ("clustered", clustered(10_000)),
+        ] {
+            let accounts: Vec<(Address, Account)> = accounts;
+            let storage = Arc::new(Storage::new_temporary());
+            let empty = StateTrie::new(storage.clone());
+
+            let start = Instant::now();
+            let (root, nodes) = empty.insert_batch(accounts.iter().cloned().collect()).unwrap();
+            let build_time = start.elapsed();
+            storage.batch_save_state_nodes(&nodes);
+            let mut uncompressed = HashMap::new();
+            build_uncompressed(&accounts, 0, &mut uncompressed);
+            let trie = StateTrie::new_from_root(root, storage);
+
+            let start = Instant::now();
+            for (address, _) in &accounts {
+                assert!(trie.get(address).unwrap().is_some());
+            }
+            let get_time = start.elapsed();
+
+            let (reads, levels) = accounts.iter()
+                .map(|(a, _)| lookup_cost(&trie, a))
+                .fold((0, 0), |(r, l), (reads, levels)| (r + reads, l + levels));
+            let written: usize = accounts.iter().take(1_000)
+                .map(|(a, _)| trie.insert(*a, Account { nonce: 1, balance: 1 }).unwrap().1.len())
+                .sum();
+            let n = accounts.len() as f64;
+            println!("{}: {} accounts", name, accounts.len());
+            println!("  stored nodes        {} (uncompressed {})", nodes.len(), uncompressed.len());
+            println!("  reads per lookup    {:.1} (uncompressed {:.1})", reads as f64 / n, levels as f64 / n);
+            println!("  nodes per update    {:.1}", written as f64 / 1_000.0);
+            println!("  batch insert        {:?}", build_time);
+            println!("  lookup              {:?} per account", get_time / accounts.len() as u32);
+        }
+    }
+
+    #[test]
+    fn update_fails_on_missing_node() {
+        let empty = StateTrie::new(Arc::new(Storage::new_temporary()));
+        let accounts = clustered(3);
+        let trie = apply(&empty, empty.insert_batch(accounts.iter().cloned().collect()));
+        let leaf = Node::new(NodeData::Leaf(accounts[0].0, accounts[0].1)).hash;
+        trie.storage.remove_unpinned_state_nodes(&[leaf]);
+
+        // 缺失的子树不能当作空树，否则会得到错误的状态根
+        assert!(trie.delete(&accounts[1].0).is_err());
+        assert!(trie.insert(accounts[2].0, Account { nonce: 2, balance: 1 }).is_ok());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn deletion_matches_rebuilt_trie(ops in prop::collection::vec(operation(), 1..40)) {
+            let storage = Arc::new(Storage::new_temporary());
+            let mut trie = StateTrie::new(storage.clone());
+            let mut expected: HashMap<Address, Account> = HashMap::new();
+
+            for (address, account) in ops {
+                trie = match account {
+                    Some(account) => apply(&trie, trie.insert_batch(HashMap::from([(address, account)]))),
+                    None => apply(&trie, trie.delete(&address)),
+                };
+                match account {
+                    Some(account) if !account.is_empty() => { expected.insert(address, account); }
+                    _ => { expected.remove(&address); }
+                }
+            }
+
+            let rebuilt = StateTrie::new(Arc::new(Storage::new_temporary()));
+            let (root, _) = rebuilt.insert_batch(expected.clone()).unwrap();
+            prop_assert_eq!(trie.root_hash, root);
+            for (address, account) in &expected {
+                prop_assert_eq!(trie.get(address).unwrap().map(|a| (a.nonce, a.balance)), Some((account.nonce, account.balance)));
+            }
+        }
+
+        #[test]
+        fn deleting_every_account_empties_the_trie(addresses in prop::collection::hash_set(address(), 1..20)) {
+            let storage = Arc::new(Storage::new_temporary());
+            let empty = StateTrie::new(storage.clone());
+            let accounts = addresses.iter().map(|a| (*a, Account { nonce: 1, balance: 1 })).collect();
+            let mut trie = apply(&empty, empty.insert_batch(accounts));
+
+            for address in &addresses {
+                prop_assert!(trie.get(address).unwrap().is_some());
+                trie = apply(&trie, trie.delete(address));
+                prop_assert!(trie.get(address).unwrap().is_none());
+            }
+            prop_assert_eq!(trie.root_hash, empty.root_hash);
+        }
+
+        #[test]
+        fn root_is_independent_of_order_and_method(
+            (entries, shuffled) in prop::collection::hash_map(address(), (0u64..2, 1u64..3), 1..40)
+                .prop_map(|m| m.into_iter().map(|(a, (nonce, balance))| (a, Account { nonce, balance })).collect::<Vec<_>>())
+                .prop_flat_map(|v| (Just(v.clone()), Just(v).prop_shuffle())),
+            stale in prop::collection::vec((0usize..40, 1u64..3), 0..10),
+            chunk in 1usize..8,
+        ) {
+            let storage = Arc::new(Storage::new_temporary());
+            let empty = StateTrie::new(storage);
+            let (expected, _) = empty.insert_batch(entries.iter().cloned().collect()).unwrap();
+
+            // 逐个写入，顺序打乱
+            let mut trie = empty.clone();
+            for (address, account) in &shuffled {
+                trie = apply(&trie, trie.insert(*address, *account));
+            }
+            prop_assert_eq!(trie.root_hash, expected);
+
+            // 分批写入，先写入一些过期的值再覆盖
+            let mut trie = empty.clone();
+            let stale: HashMap<Address, Account> = stale.iter()
+                .map(|(i, balance)| (entries[i % entries.len()].0, Account { nonce: 7, balance: *balance }))
+                .collect();
+            trie = apply(&trie, trie.insert_batch(stale));
+            for part in shuffled.chunks(chunk) {
+                trie = apply(&trie, trie.insert_batch(part.iter().cloned().collect()));
+            }
+            prop_assert_eq!(trie.root_hash, expected);
+
+            // 重复写入相同的值不改变根
+            let (again, _) = trie.insert(entries[0].0, entries[0].1).unwrap();
+            prop_assert_eq!(again, expected);
+        }
+
+        #[test]
+        fn diff_matches_account_lists(
+            initial in prop::collection::vec(operation(), 0..30),
+            ops in prop::collection::vec(operation(), 0..20),
+        ) {
+            let storage = Arc::new(Storage::new_temporary());
+            let empty = StateTrie::new(storage);
+            let updates = |ops: Vec<(Address, Option<Account>)>| -> HashMap<Address, Account> {
+                ops.into_iter().map(|(address, account)| (address, account.unwrap_or_default())).collect()
+            };
+            let before = apply(&empty, empty.insert_batch(updates(initial)));
+            let after = apply(&before, before.insert_batch(updates(ops)));
+
+            let old: BTreeMap<Address, Account> = before.accounts().unwrap().into_iter().collect();
+            let new: BTreeMap<Address, Account> = after.accounts().unwrap().into_iter().collect();
+            let expected: Vec<AccountChange> = old.keys().chain(new.keys()).collect::<BTreeSet<_>>().into_iter()
+                .map(|a| AccountChange { address: *a, before: old.get(a).copied(), after: new.get(a).copied() })
+                .filter(|c| c.before != c.after)
+                .collect();
+            prop_assert_eq!(before.diff(&after).unwrap(), expected);
+            prop_assert!(after.diff(&after).unwrap().is_empty());
+        }
+    }
+}
@@ -1,19 +1,61 @@
 use serde::{Serialize,Deserialize};
-use ring::signature::{Ed25519KeyPair, Signature, UnparsedPublicKey, KeyPair};
-use rand::Rng;
+use ring::signature::{Ed25519KeyPair, Signature, UnparsedPublicKey};
 use bincode;
 use crate::types::address::Address;
 use crate::types::hash::{H256, Hashable};
+use crate::types::multisig::MultisigProof;
 use ring::digest;
 
+/// 每笔交易的基础 gas 消耗
+pub const TX_BASE_GAS: u64 = 10;
+/// `data` 每个字节额外消耗的 gas
+pub const TX_DATA_BYTE_GAS: u64 = 1;
+/// 批量交易每个输出额外消耗的 gas
+pub const TX_OUTPUT_GAS: u64 = 2;
+/// 多签交易每个签名额外消耗的 gas，签名越多验证越慢
+pub const TX_SIGNATURE_GAS: u64 = 5;
+/// 批量交易最多包含的输出数
+pub const MAX_BATCH_OUTPUTS: usize = 1_000;
+
+/// 批量交易的一个输出
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct TxOutput {
+    pub to: Address,
+    pub value: u64,
+}
+
+/// 交易可以被打包的最早区块高度或时间
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxLock {
+    /// 区块高度不小于该值
+    Height(u64),
+    /// 区块时间戳不早于该 Unix 时间 (秒)
+    Time(u64),
+}
+
+impl std::fmt::Display for TxLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TxLock::Height(height) => write!(f, "height {}", height),
+            TxLock::Time(secs) => write!(f, "unix time {}", secs),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Transaction {
+    /// 签名内容包含链 ID，交易只在对应的网络上有效
+    pub chain_id: u64,
     pub nonce: u64,
     pub gas_price: u64,
     pub gas_limit: u64,
     pub to: Address,
     pub value: u64,
     pub data: Vec<u8>,
+    /// 非空时为批量交易，此时 to 与 value 必须为默认值
+    pub outputs: Vec<TxOutput>,
+    /// 为 Some 时交易在解锁前不能被打包
+    pub lock: Option<TxLock>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -21,33 +63,148 @@ pub struct SignedTransaction {
     pub transaction: Transaction,
     pub signature: Vec<u8>,
     pub public_key: Vec<u8>,
+    /// 多签账户的交易使用该字段，此时 signature 与 public_key 为空
+    #[serde(default)]
+    pub multisig: Option<MultisigProof>,
 }
 
 
 impl Transaction {
-    pub fn new(nonce: u64, gas_price: u64, gas_limit: u64, to: Address, value: u64, data: Vec<u8>) -> Self {
+    pub fn new(chain_id: u64, nonce: u64, gas_price: u64, gas_limit: u64, to: Address, value: u64, data: Vec<u8>) -> Self {
         Transaction {
+            chain_id,
             nonce,
             gas_price,
             gas_limit,
             to,
             value,
             data,
+            outputs: Vec::new(),
+            lock: None,
         }
     }
 
-    
+    pub fn with_lock(mut self, lock: TxLock) -> Self {
+        self.lock = Some(lock);
+        self
+    }
+
+    /// 交易能否被打包进高度为 `height`、时间戳为 `timestamp` (毫秒) 的区块
+    pub fn is_unlocked(&self, height: u64, timestamp: u128) -> bool {
+        match self.lock {
+            None => true,
+            Some(TxLock::Height(h)) => height >= h,
+            Some(TxLock::Time(secs)) => timestamp >= secs as u128 * 1000,
+        }
+    }
+
+    /// 一次向多个接收者转账，所有输出要么全部执行，要么全部不执行
+    pub fn new_batch(chain_id: u64, nonce: u64, gas_price: u64, gas_limit: u64, outputs: Vec<TxOutput>) -> Self {
+        Transaction {
+            chain_id,
+            nonce,
+            gas_price,
+            gas_limit,
+            outputs,
+            ..Transaction::default()
+        }
+    }
+
+    pub fn is_batch(&self) -> bool {
+        !self.outputs.is_empty()
+    }
+
+    /// 交易的所有 (接收者, 金额)，普通交易只有一个
+    pub fn payments(&self) -> Vec<(Address, u64)> {
+        if self.is_batch() {
+            self.outputs.iter().map(|o| (o.to, o.value)).collect()
+        } else {
+            vec![(self.to, self.value)]
+        }
+    }
+
+    /// 转出的总金额，溢出时返回 None
+    pub fn total_value(&self) -> Option<u64> {
+        self.outputs.iter().try_fold(self.value, |total, o| total.checked_add(o.value))
+    }
+
+    /// 交易愿意支付的手续费上限 (gas_price * gas_limit)，仅用于展示和排序
+    pub fn fee(&self) -> u64 {
+        self.gas_price.saturating_mul(self.gas_limit)
+    }
+
+    /// 发送者余额必须覆盖的最大花费: 总金额 + gas_price * gas_limit，溢出时返回 None
+    pub fn max_cost(&self) -> Option<u64> {
+        self.gas_price.checked_mul(self.gas_limit)?.checked_add(self.total_value()?)
+    }
+
+    /// 交易内容消耗的 gas: 基础消耗加上 data 的字节消耗和每个批量输出的消耗。
+    /// 多签交易还需加上签名的消耗，见 `SignedTransaction::gas_used`
+    pub fn gas_used(&self) -> u64 {
+        TX_BASE_GAS
+            .saturating_add(TX_DATA_BYTE_GAS.saturating_mul(self.data.len() as u64))
+            .saturating_add(TX_OUTPUT_GAS.saturating_mul(self.outputs.len() as u64))
+    }
 }
 
 impl SignedTransaction {
 
     pub fn sender_address(&self) -> Address {
-        Address::from_public_key_bytes(&self.public_key)
+        match &self.multisig {
+            Some(proof) => proof.policy.address(),
+            None => Address::from_public_key_bytes(&self.public_key),
+        }
     }
     
     /// 验证交易合法性
     pub fn verify(&self) -> bool {
-        verify(&self.transaction, &self.public_key, &self.signature)
+        match &self.multisig {
+            Some(proof) => self.signature.is_empty() && self.public_key.is_empty() && proof.verify(&self.transaction),
+            None => verify(&self.transaction, &self.public_key, &self.signature),
+        }
+    }
+
+    /// 执行交易实际消耗的 gas: 交易内容的消耗加上多签交易每个签名的消耗
+    pub fn gas_used(&self) -> u64 {
+        let signatures = self.multisig.as_ref().map_or(0, |proof| proof.signatures.len() as u64);
+        self.transaction.gas_used().saturating_add(TX_SIGNATURE_GAS.saturating_mul(signatures))
+    }
+
+    /// 实际收取的手续费，溢出时返回 None
+    pub fn checked_fee_paid(&self) -> Option<u64> {
+        self.transaction.gas_price.checked_mul(self.gas_used())
+    }
+
+    /// 实际收取的手续费，gas_limit 中未使用的部分不收取。仅用于展示
+    pub fn fee_paid(&self) -> u64 {
+        self.transaction.gas_price.saturating_mul(self.gas_used())
+    }
+
+    /// 不依赖链上状态的检查 (链 ID、签名、gas_limit)，用于 Mempool 准入
+    pub fn validate(&self, chain_id: u64) -> Result<(), String> {
+        if self.transaction.chain_id != chain_id {
+            return Err(format!("Chain ID mismatch: expected {}, got {}", chain_id, self.transaction.chain_id));
+        }
+        if !self.verify() {
+            return Err("Invalid signature".to_string());
+        }
+        let t = &self.transaction;
+        if t.is_batch() {
+            if t.to != Address::default() || t.value != 0 {
+                return Err("Batch transaction must not set to or value".to_string());
+            }
+            if t.outputs.len() > MAX_BATCH_OUTPUTS {
+                return Err(format!("Too many outputs: {} > {}", t.outputs.len(), MAX_BATCH_OUTPUTS));
+            }
+        }
+        let gas_used = self.gas_used();
+        if self.transaction.gas_limit < gas_used {
+            return Err(format!("Gas limit {} below required {}", self.transaction.gas_limit, gas_used));
+        }
+        if self.transaction.max_cost().is_none() {
+            return Err("Transaction cost overflows".to_string());
+        }
+        Ok(())
     }
 
 }
@@ -76,6 +233,18 @@ pub fn sign(t: &Transaction, key: &Ed25519KeyPair) -> Signature {
     key.sign(&bytes_to_sign)
 }
 
+/// 测试用: 用 `key` 签名交易并附上公钥
+#[cfg(test)]
+pub fn sign_with(t: Transaction, key: &Ed25519KeyPair) -> SignedTransaction {
+    use ring::signature::KeyPair;
+    SignedTransaction {
+        signature: sign(&t, key).as_ref().to_vec(),
+        public_key: key.public_key().as_ref().to_vec(),
+        transaction: t,
+        multisig: None,
+    }
+}
+
 /// Verify digital signature of a transaction, using public key instead of secret key
 pub fn verify(t: &Transaction, public_key: &[u8], signature: &[u8]) -> bool {
     let bytes_to_verify = bincode::serialize(t).expect("error in verify");
@@ -86,3 +255,40 @@ pub fn verify(t: &Transaction, public_key: &[u8], signature: &[u8]) -> bool {
 
 
 
+
+#[cfg(test)]
+mod test {
+    use super::{sign_with, SignedTransaction, Transaction, TX_BASE_GAS, TX_DATA_BYTE_GAS};
+    use crate::types::address::Address;
+    use crate::types::key_pair;
+
+    fn signed(gas_limit: u64, data: Vec<u8>) -> SignedTransaction {
+        sign_with(Transaction::new(1, 0, 3, gas_limit, Address::default(), 1, data), &key_pair::random())
+    }
+
+    #[test]
+    fn only_used_gas_is_charged() {
+        let tx = signed(100, vec![0u8; 5]);
+        let gas_used = TX_BASE_GAS + 5 * TX_DATA_BYTE_GAS;
+        assert_eq!(tx.gas_used(), gas_used);
+        assert_eq!(tx.fee_paid(), 3 * gas_used);
+        assert_eq!(tx.transaction.fee(), 300);
+        assert!(tx.validate(1).is_ok());
+    }
+
+    #[test]
+    fn gas_limit_below_intrinsic_gas_is_rejected() {
+        assert!(signed(TX_BASE_GAS, vec![]).validate(1).is_ok());
+        assert!(signed(TX_BASE_GAS, vec![1]).validate(1).is_err());
+    }
+
+    #[test]
+    fn chain_id_is_signed_and_checked() {
+        let mut tx = signed(TX_BASE_GAS, vec![]);
+        assert!(tx.validate(2).is_err());
+        // 修改链 ID 会使签名失效
+        tx.transaction.chain_id = 2;
+        assert!(!tx.verify());
+        assert!(tx.validate(2).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use crate::types::address::Address;
+use crate::types::transaction::{TxOutput, MAX_BATCH_OUTPUTS};
+
+/// 解析批量转账的 CSV: 每行 `接收者,金额`，接收者由 `resolve` 解析为地址。
+/// 空行和 `#` 开头的行被忽略，可以有一行 `to,amount` 表头
+pub fn parse_csv<F>(content: &str, resolve: F) -> Result<Vec<TxOutput>, String>
+where
+    F: Fn(&str) -> Result<Address, String>,
+{
+    let mut outputs = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_no = idx + 1;
+        let (to, amount) = line.split_once(',')
+            .ok_or(format!("Line {}: expected <to>,<amount>", line_no))?;
+        let (to, amount) = (to.trim(), amount.trim());
+        if outputs.is_empty() && amount.eq_ignore_ascii_case("amount") {
+            continue;
+        }
+        let value: u64 = amount.parse().map_err(|_| format!("Line {}: invalid amount {}", line_no, amount))?;
+        let to = resolve(to).map_err(|e| format!("Line {}: {}", line_no, e))?;
+        outputs.push(TxOutput { to, value });
+    }
+    if outputs.is_empty() {
+        return Err("No outputs in file".to_string());
+    }
+    if outputs.len() > MAX_BATCH_OUTPUTS {
+        return Err(format!("Too many outputs: {} > {}", outputs.len(), MAX_BATCH_OUTPUTS));
+    }
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_csv;
+    use crate::types::address::Address;
+
+    #[test]
+    fn parse_payroll_file() {
+        let alice = Address::from([1u8; 20]);
+        let bob = Address::from([2u8; 20]);
+        let content = format!("to,amount\n# payroll\n{}, 100\n\nbob,25\n", alice);
+        let resolve = |s: &str| if s == "bob" { Ok(bob) } else { s.parse() };
+
+        let outputs = parse_csv(&content, resolve).unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!((outputs[0].to, outputs[0].value), (alice, 100));
+        assert_eq!((outputs[1].to, outputs[1].value), (bob, 25));
+
+        assert!(parse_csv("bob,ten", resolve).unwrap_err().starts_with("Line 1"));
+        assert!(parse_csv("bob,1\nnobody,2", resolve).unwrap_err().starts_with("Line 2"));
+        assert!(parse_csv("# empty\n", resolve).is_err());
+    }
+}
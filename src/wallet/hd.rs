@@ -0,0 +1,141 @@
+use bip39::Mnemonic;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use crate::types::address::Address;
+
+/// SLIP-10 Ed25519 只支持 hardened 派生，索引会加上该偏移
+pub const HARDENED_OFFSET: u32 = 1 << 31;
+/// 地址派生路径 m/44'/COIN_TYPE'/0'/0'/index'
+pub const COIN_TYPE: u32 = 1;
+/// 地址扫描时连续多少个未使用地址后停止
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+/// 新助记词的熵长度 (24 个单词)
+const MNEMONIC_ENTROPY_LEN: usize = 32;
+
+const MASTER_HMAC_KEY: &[u8] = b"ed25519 seed";
+
+/// SLIP-10 扩展私钥: 32 字节私钥 seed 与 32 字节 chain code
+#[derive(Clone)]
+pub struct ExtendedKey {
+    key: [u8; 32],
+    chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    /// 由主 seed (例如助记词生成的 64 字节 seed) 得到根密钥 m
+    pub fn master(seed: &[u8]) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA512, MASTER_HMAC_KEY);
+        Self::from_hmac(hmac::sign(&key, seed))
+    }
+
+    /// hardened 派生子密钥，`index` 不需要预先加上 `HARDENED_OFFSET`
+    pub fn derive_child(&self, index: u32) -> Self {
+        let mut data = Vec::with_capacity(37);
+        data.push(0u8);
+        data.extend_from_slice(&self.key);
+        data.extend_from_slice(&(index | HARDENED_OFFSET).to_be_bytes());
+        let key = hmac::Key::new(hmac::HMAC_SHA512, &self.chain_code);
+        Self::from_hmac(hmac::sign(&key, &data))
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Self {
+        path.iter().fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    /// 第 `index` 个地址的密钥
+    pub fn derive_address_key(&self, index: u32) -> Self {
+        self.derive_path(&address_path(index))
+    }
+
+    pub fn key_pair(&self) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&self.key).expect("32-byte seed is always valid")
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from_public_key_bytes(self.key_pair().public_key().as_ref())
+    }
+
+    fn from_hmac(tag: hmac::Tag) -> Self {
+        let bytes = tag.as_ref();
+        let mut key = [0u8; 32];
+        let mut chain_code = [0u8; 32];
+        key.copy_from_slice(&bytes[..32]);
+        chain_code.copy_from_slice(&bytes[32..]);
+        Self { key, chain_code }
+    }
+}
+
+/// m/44'/COIN_TYPE'/0'/0'/index'
+pub fn address_path(index: u32) -> [u32; 5] {
+    [44, COIN_TYPE, 0, 0, index]
+}
+
+/// 生成新的随机助记词
+pub fn generate_mnemonic() -> Result<Mnemonic, String> {
+    let mut entropy = [0u8; MNEMONIC_ENTROPY_LEN];
+    SystemRandom::new().fill(&mut entropy).map_err(|_| "Failed to generate entropy".to_string())?;
+    Mnemonic::from_entropy(&entropy).map_err(|e| e.to_string())
+}
+
+pub fn parse_mnemonic(phrase: &str) -> Result<Mnemonic, String> {
+    Mnemonic::parse(phrase).map_err(|e| format!("Invalid mnemonic: {}", e))
+}
+
+/// 助记词对应的根密钥 (不使用 BIP-39 口令，keystore 已经用口令加密)
+pub fn master_from_mnemonic(mnemonic: &Mnemonic) -> ExtendedKey {
+    ExtendedKey::master(&mnemonic.to_seed(""))
+}
+
+/// 依次检查派生地址，连续 `gap_limit` 个地址未被使用时停止。
+/// 返回所有已使用地址的 (index, address)
+pub fn scan<F>(master: &ExtendedKey, gap_limit: u32, mut is_used: F) -> Result<Vec<(u32, Address)>, String>
+where
+    F: FnMut(&Address) -> Result<bool, String>,
+{
+    let mut used = Vec::new();
+    let mut gap = 0;
+    let mut index = 0;
+    while gap < gap_limit {
+        let address = master.derive_address_key(index).address();
+        if is_used(&address)? {
+            used.push((index, address));
+            gap = 0;
+        } else {
+            gap += 1;
+        }
+        index += 1;
+    }
+    Ok(used)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{scan, ExtendedKey};
+
+    // SLIP-10 Ed25519 测试向量 1
+    #[test]
+    fn slip10_test_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedKey::master(&seed);
+        assert_eq!(hex::encode(master.key), "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7");
+        assert_eq!(hex::encode(master.chain_code), "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb");
+
+        let child = master.derive_path(&[0]);
+        assert_eq!(hex::encode(child.key), "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3");
+        assert_eq!(hex::encode(child.chain_code), "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69");
+    }
+
+    #[test]
+    fn scan_stops_after_gap() {
+        let master = ExtendedKey::master(&[1u8; 64]);
+        let used_indexes = [0, 2, 5];
+        let used_addresses: Vec<_> = used_indexes.iter().map(|i| master.derive_address_key(*i).address()).collect();
+
+        let found = scan(&master, 3, |a| Ok(used_addresses.contains(a))).unwrap();
+        assert_eq!(found.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0, 2, 5]);
+        // 2 和 5 之间有 2 个未使用地址，gap 为 2 时扫描不到 5
+        let found = scan(&master, 2, |a| Ok(used_addresses.contains(a))).unwrap();
+        assert_eq!(found.len(), 2);
+    }
+}
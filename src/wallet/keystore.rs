@@ -0,0 +1,390 @@
+use std::fs;
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use log::warn;
+use crate::types::address::Address;
+use crate::types::multisig::MultisigPolicy;
+use crate::wallet::hd::{self, ExtendedKey};
+
+/// 当前 keystore 文件格式版本
+pub const KEYSTORE_VERSION: u32 = 1;
+/// 新建 keystore 时使用的 PBKDF2 迭代次数
+pub const DEFAULT_PBKDF2_ITERATIONS: u32 = 100_000;
+
+const KDF_NAME: &str = "pbkdf2-hmac-sha256";
+const CIPHER_NAME: &str = "aes-256-gcm";
+const SALT_LEN: usize = 16;
+const SEED_LEN: usize = 32;
+const POLICY_DIR: &str = "policies";
+
+/// 加密保存的密钥类型
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyKind {
+    /// 单个 Ed25519 seed
+    #[default]
+    Ed25519,
+    /// 助记词的熵，可以派生多个地址
+    Mnemonic,
+}
+
+/// `Keystore::list` 的一项，不包含私密信息
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KeystoreEntry {
+    pub name: String,
+    pub kind: KeyKind,
+    pub address: String,
+}
+
+/// 解密后的密钥
+pub enum UnlockedKey {
+    /// ring 的密钥对不暴露私钥，这里保存 seed 以便重复构造
+    Single(Vec<u8>),
+    Hd(ExtendedKey),
+}
+
+impl UnlockedKey {
+    /// 单密钥账户只有 index 0
+    pub fn key_pair(&self, index: u32) -> Result<Ed25519KeyPair, String> {
+        match self {
+            UnlockedKey::Single(_) if index != 0 => Err("Not an HD account, only index 0 exists".to_string()),
+            UnlockedKey::Single(seed) => Ed25519KeyPair::from_seed_unchecked(seed)
+                .map_err(|_| "Invalid seed".to_string()),
+            UnlockedKey::Hd(_) if index >= hd::HARDENED_OFFSET => Err(format!("Index {} out of range", index)),
+            UnlockedKey::Hd(master) => Ok(master.derive_address_key(index).key_pair()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KdfParams {
+    name: String,
+    iterations: u32,
+    salt: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CipherParams {
+    name: String,
+    nonce: String,
+}
+
+/// 磁盘上的 keystore 文件，seed 用口令派生的密钥加密
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    /// 旧文件没有该字段，默认为单个 seed
+    #[serde(default)]
+    kind: KeyKind,
+    /// 助记词账户为 index 0 的地址
+    address: String,
+    kdf: KdfParams,
+    cipher: CipherParams,
+    /// 加密后的 seed 与认证标签，地址作为附加认证数据
+    ciphertext: String,
+}
+
+/// 多签策略文件，只包含公钥，不需要加密
+#[derive(Serialize, Deserialize)]
+struct PolicyFile {
+    version: u32,
+    address: String,
+    threshold: u32,
+    public_keys: Vec<String>,
+}
+
+/// 保存加密 seed 的目录，每个账户一个 `<name>.json` 文件，
+/// 多签策略保存在 `policies/<name>.json`
+pub struct Keystore {
+    dir: PathBuf,
+    iterations: u32,
+}
+
+impl Keystore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            iterations: DEFAULT_PBKDF2_ITERATIONS,
+        }
+    }
+
+    /// 生成新的随机 seed 并保存，返回其地址
+    pub fn create(&self, name: &str, passphrase: &str) -> Result<Address, String> {
+        let mut seed = [0u8; SEED_LEN];
+        SystemRandom::new().fill(&mut seed).map_err(|_| "Failed to generate seed".to_string())?;
+        self.import(name, &seed, passphrase)
+    }
+
+    /// 加密并保存已有的 seed，返回其地址。不会覆盖同名账户
+    pub fn import(&self, name: &str, seed: &[u8], passphrase: &str) -> Result<Address, String> {
+        self.store(name, KeyKind::Ed25519, seed, passphrase)
+    }
+
+    /// 加密并保存助记词，返回 index 0 的地址
+    pub fn import_mnemonic(&self, name: &str, mnemonic: &bip39::Mnemonic, passphrase: &str) -> Result<Address, String> {
+        self.store(name, KeyKind::Mnemonic, &mnemonic.to_entropy(), passphrase)
+    }
+
+    fn store(&self, name: &str, kind: KeyKind, secret: &[u8], passphrase: &str) -> Result<Address, String> {
+        let path = self.path(name)?;
+        if path.exists() {
+            return Err(format!("Account {} already exists", name));
+        }
+        let address = match kind {
+            KeyKind::Ed25519 => address_of(secret)?,
+            KeyKind::Mnemonic => master_of(secret)?.derive_address_key(0).address(),
+        };
+
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut salt).map_err(|_| "Failed to generate salt".to_string())?;
+        rng.fill(&mut nonce).map_err(|_| "Failed to generate nonce".to_string())?;
+
+        let key = derive_key(passphrase, &salt, self.iterations)?;
+        let mut ciphertext = secret.to_vec();
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(address.as_ref()), &mut ciphertext)
+            .map_err(|_| "Encryption failed".to_string())?;
+
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            kind,
+            address: address.to_string(),
+            kdf: KdfParams {
+                name: KDF_NAME.to_string(),
+                iterations: self.iterations,
+                salt: hex::encode(salt),
+            },
+            cipher: CipherParams {
+                name: CIPHER_NAME.to_string(),
+                nonce: hex::encode(nonce),
+            },
+            ciphertext: hex::encode(ciphertext),
+        };
+
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let content = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        write_private(&path, &content)?;
+        Ok(address)
+    }
+
+    /// 用口令解密账户的 seed (助记词账户为助记词的熵)
+    pub fn export(&self, name: &str, passphrase: &str) -> Result<(KeyKind, Vec<u8>), String> {
+        let file = self.read(name)?;
+        if file.version != KEYSTORE_VERSION {
+            return Err(format!("Unsupported keystore version {}", file.version));
+        }
+        if file.kdf.name != KDF_NAME || file.cipher.name != CIPHER_NAME {
+            return Err("Unsupported keystore algorithms".to_string());
+        }
+
+        let salt = hex::decode(&file.kdf.salt).map_err(|_| "Invalid salt".to_string())?;
+        let nonce = hex::decode(&file.cipher.nonce).map_err(|_| "Invalid nonce".to_string())?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce).map_err(|_| "Invalid nonce".to_string())?;
+        // 旧文件中的地址为 hex，新文件为 bech32，两者都能解析
+        let address: Address = file.address.parse()?;
+        let mut ciphertext = hex::decode(&file.ciphertext).map_err(|_| "Invalid ciphertext".to_string())?;
+
+        let key = derive_key(passphrase, &salt, file.kdf.iterations)?;
+        let secret = key.open_in_place(nonce, Aad::from(address.as_ref()), &mut ciphertext)
+            .map_err(|_| "Wrong passphrase or corrupted keystore".to_string())?;
+        Ok((file.kind, secret.to_vec()))
+    }
+
+    /// 解密账户得到单个密钥对或 HD 根密钥
+    pub fn unlock(&self, name: &str, passphrase: &str) -> Result<UnlockedKey, String> {
+        let (kind, secret) = self.export(name, passphrase)?;
+        match kind {
+            KeyKind::Ed25519 => {
+                address_of(&secret)?;
+                Ok(UnlockedKey::Single(secret))
+            }
+            KeyKind::Mnemonic => master_of(&secret).map(UnlockedKey::Hd),
+        }
+    }
+
+    /// 按名称排序的账户列表，无需口令。无法解析的文件被跳过
+    pub fn list(&self) -> Result<Vec<KeystoreEntry>, String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut accounts = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            match self.read(&name) {
+                Ok(file) => accounts.push(KeystoreEntry { name, kind: file.kind, address: file.address }),
+                Err(e) => warn!("Skipping keystore file {}: {}", path.display(), e),
+            }
+        }
+        accounts.sort();
+        Ok(accounts)
+    }
+
+    /// 保存多签策略，不会覆盖同名策略
+    pub fn save_policy(&self, name: &str, policy: &MultisigPolicy) -> Result<(), String> {
+        let path = self.policy_path(name)?;
+        if path.exists() {
+            return Err(format!("Policy {} already exists", name));
+        }
+        let file = PolicyFile {
+            version: KEYSTORE_VERSION,
+            address: policy.address().to_string(),
+            threshold: policy.threshold,
+            public_keys: policy.public_keys.iter().map(hex::encode).collect(),
+        };
+        fs::create_dir_all(self.dir.join(POLICY_DIR)).map_err(|e| e.to_string())?;
+        let content = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        write_private(&path, &content)
+    }
+
+    pub fn load_policy(&self, name: &str) -> Result<MultisigPolicy, String> {
+        let path = self.policy_path(name)?;
+        let content = fs::read_to_string(&path).map_err(|_| format!("Policy {} not found", name))?;
+        let file: PolicyFile = serde_json::from_str(&content).map_err(|e| format!("Invalid policy {}: {}", name, e))?;
+        let public_keys = file.public_keys.iter()
+            .map(|k| hex::decode(k).map_err(|_| format!("Invalid public key in policy {}", name)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let policy = MultisigPolicy { threshold: file.threshold, public_keys };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// 按名称排序的 (策略名, 策略)
+    pub fn list_policies(&self) -> Result<Vec<(String, MultisigPolicy)>, String> {
+        let entries = match fs::read_dir(self.dir.join(POLICY_DIR)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut policies = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                policies.push((name.to_string(), self.load_policy(name)?));
+            }
+        }
+        policies.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(policies)
+    }
+
+    fn policy_path(&self, name: &str) -> Result<PathBuf, String> {
+        let file_name = self.path(name)?;
+        let file_name = file_name.file_name().expect("validated name");
+        Ok(self.dir.join(POLICY_DIR).join(file_name))
+    }
+
+    fn read(&self, name: &str) -> Result<KeystoreFile, String> {
+        let path = self.path(name)?;
+        let content = fs::read_to_string(&path).map_err(|_| format!("Account {} not found", name))?;
+        serde_json::from_str(&content).map_err(|e| format!("Invalid keystore {}: {}", name, e))
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, String> {
+        let valid = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!("Invalid account name {:?}", name));
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+}
+
+/// 新建文件并写入，只有所有者可读写。文件已存在时失败，不会覆盖
+fn write_private(path: &Path, content: &str) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+    file.write_all(content.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<LessSafeKey, String> {
+    let iterations = NonZeroU32::new(iterations).ok_or("Invalid KDF iterations")?;
+    let mut key_bytes = [0u8; 32];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key_bytes);
+    let key = UnboundKey::new(&AES_256_GCM, &key_bytes).map_err(|_| "Invalid key".to_string())?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn master_of(entropy: &[u8]) -> Result<ExtendedKey, String> {
+    let mnemonic = bip39::Mnemonic::from_entropy(entropy).map_err(|e| e.to_string())?;
+    Ok(hd::master_from_mnemonic(&mnemonic))
+}
+
+fn address_of(seed: &[u8]) -> Result<Address, String> {
+    if seed.len() != SEED_LEN {
+        return Err(format!("Seed must be {} bytes", SEED_LEN));
+    }
+    let key_pair = Ed25519KeyPair::from_seed_unchecked(seed).map_err(|_| "Invalid seed".to_string())?;
+    Ok(Address::from_public_key_bytes(key_pair.public_key().as_ref()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyKind, Keystore};
+    use crate::wallet::hd;
+    use rand::Rng;
+
+    #[test]
+    fn import_export_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("keystore-test-{}", rand::thread_rng().gen::<u64>()));
+        let mut keystore = Keystore::new(&dir);
+        keystore.iterations = 1_000;
+
+        let seed = [7u8; 32];
+        let address = keystore.import("alice", &seed, "secret").unwrap();
+        assert!(keystore.import("alice", &seed, "secret").is_err());
+        assert!(keystore.import("../bob", &seed, "secret").is_err());
+        keystore.create("bob", "other").unwrap();
+
+        assert_eq!(keystore.export("alice", "secret").unwrap(), (KeyKind::Ed25519, seed.to_vec()));
+        assert!(keystore.export("alice", "wrong").is_err());
+        assert!(keystore.unlock("alice", "secret").unwrap().key_pair(1).is_err());
+
+        let mnemonic = hd::generate_mnemonic().unwrap();
+        let hd_address = keystore.import_mnemonic("carol", &mnemonic, "secret").unwrap();
+        let unlocked = keystore.unlock("carol", "secret").unwrap();
+        assert!(unlocked.key_pair(1).is_ok());
+        assert!(unlocked.key_pair(hd::HARDENED_OFFSET).is_err());
+        assert_eq!(hd::master_from_mnemonic(&mnemonic).derive_address_key(0).address(), hd_address);
+
+        let accounts = keystore.list().unwrap();
+        let names: Vec<&str> = accounts.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["alice", "bob", "carol"]);
+        assert_eq!(accounts[0].address, address.to_string());
+        assert_eq!(accounts[2].kind, KeyKind::Mnemonic);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.join("alice.json")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        // 损坏的文件不影响列出其他账户
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        assert_eq!(keystore.list().unwrap().len(), 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1 +1,7 @@
-pub mod wallet;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod wallet;
+pub mod keystore;
+pub mod hd;
+pub mod session;
+pub mod offline;
+pub mod batch;
\ No newline at end of file
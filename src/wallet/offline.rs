@@ -0,0 +1,305 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::types::address::Address;
+use crate::types::hash::Hashable;
+use crate::types::multisig::{MultisigPolicy, MultisigProof};
+use crate::types::transaction::{SignedTransaction, Transaction};
+use crate::wallet::wallet::Wallet;
+
+/// 离线签名文件格式版本
+pub const OFFLINE_FORMAT_VERSION: u32 = 1;
+
+/// 在线机器构造、离线机器签名的未签名交易
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnsignedRequest {
+    pub version: u32,
+    /// 预期的签名账户，签名时会检查
+    pub from: Address,
+    pub transaction: Transaction,
+}
+
+/// 已签名交易，由在线机器提交
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedRequest {
+    pub version: u32,
+    pub signed: SignedTransaction,
+}
+
+impl UnsignedRequest {
+    pub fn new(from: Address, transaction: Transaction) -> Self {
+        Self {
+            version: OFFLINE_FORMAT_VERSION,
+            from,
+            transaction,
+        }
+    }
+
+    /// 用 `wallet` 签名，钱包必须属于 `from` 账户
+    pub fn sign(&self, wallet: &Wallet) -> Result<SignedRequest, String> {
+        let signer = wallet.get_my_address();
+        if signer != self.from {
+            return Err(format!("Transaction must be signed by {}, not {}", self.from, signer));
+        }
+        Ok(SignedRequest {
+            version: OFFLINE_FORMAT_VERSION,
+            signed: wallet.sign_transaction(self.transaction.clone()),
+        })
+    }
+
+    pub fn review(&self) -> String {
+        review(&self.from, &self.transaction)
+    }
+}
+
+/// 正在收集签名的多签交易，每个签名人签名后传给下一个人或合并
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultisigRequest {
+    pub version: u32,
+    pub transaction: Transaction,
+    pub proof: MultisigProof,
+}
+
+impl MultisigRequest {
+    /// 由 `tx build` 生成的未签名交易开始收集签名，`from` 必须是策略地址
+    pub fn from_unsigned(request: &UnsignedRequest, policy: MultisigPolicy) -> Result<Self, String> {
+        if policy.address() != request.from {
+            return Err(format!("Policy address {} does not match sender {}", policy.address(), request.from));
+        }
+        Ok(Self {
+            version: OFFLINE_FORMAT_VERSION,
+            transaction: request.transaction.clone(),
+            proof: MultisigProof { policy, signatures: Vec::new() },
+        })
+    }
+
+    /// 用 `wallet` 加入一个签名，钱包的公钥必须在策略中
+    pub fn sign(&mut self, wallet: &Wallet) -> Result<(), String> {
+        let index = self.proof.policy.key_index(&wallet.get_public_key_bytes())
+            .ok_or(format!("{} is not a signer of this policy", wallet.get_my_address()))?;
+        let signed = wallet.sign_transaction(self.transaction.clone());
+        self.proof.add_signature(index, signed.signature);
+        Ok(())
+    }
+
+    /// 合并其他签名人对同一交易的签名。对方的签名必须全部有效，已有的有效签名不会被替换
+    pub fn merge(&mut self, other: &MultisigRequest) -> Result<(), String> {
+        let same_tx = self.transaction.hash() == other.transaction.hash();
+        if !same_tx || self.proof.policy != other.proof.policy {
+            return Err("Files contain different transactions".to_string());
+        }
+        other.proof.check_indexes()?;
+        for (index, signature) in &other.proof.signatures {
+            if !self.proof.signature_is_valid(&self.transaction, *index, signature) {
+                let signer = Address::from_public_key_bytes(&self.proof.policy.public_keys[*index as usize]);
+                return Err(format!("Invalid signature from {} in merged file", signer));
+            }
+        }
+        for (index, signature) in &other.proof.signatures {
+            if !self.proof.has_valid_signature(&self.transaction, *index) {
+                self.proof.add_signature(*index, signature.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// 签名数达到阈值后生成可提交的交易。每个签名都要付 gas，多余的签名被去掉
+    pub fn finalize(&self) -> Result<SignedRequest, String> {
+        let mut signed = SignedTransaction {
+            transaction: self.transaction.clone(),
+            signature: Vec::new(),
+            public_key: Vec::new(),
+            multisig: Some(self.proof.clone()),
+        };
+        if !signed.verify() {
+            return Err(format!(
+                "Need {} valid signatures, have {}",
+                self.proof.policy.threshold,
+                self.proof.signatures.len()
+            ));
+        }
+        if let Some(proof) = signed.multisig.as_mut() {
+            proof.signatures.truncate(proof.policy.threshold as usize);
+        }
+        Ok(SignedRequest { version: OFFLINE_FORMAT_VERSION, signed })
+    }
+
+    pub fn review(&self) -> String {
+        let policy = &self.proof.policy;
+        let mut lines = vec![
+            review(&policy.address(), &self.transaction),
+            format!("  Policy:      {}-of-{}", policy.threshold, policy.public_keys.len()),
+            format!("  Signatures:  {}/{}", self.proof.signatures.len(), policy.threshold),
+        ];
+        for (index, _) in &self.proof.signatures {
+            match policy.public_keys.get(*index as usize) {
+                Some(key) => lines.push(format!("    signed by {}", Address::from_public_key_bytes(key))),
+                None => lines.push(format!("    invalid signer index {}", index)),
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn load_multisig<P: AsRef<Path>>(path: P) -> Result<MultisigRequest, String> {
+    let request: MultisigRequest = load(path)?;
+    check_version(request.version)?;
+    request.proof.policy.validate()?;
+    request.proof.check_indexes()?;
+    Ok(request)
+}
+
+pub fn save<T: Serialize, P: AsRef<Path>>(path: P, request: &T) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(request).map_err(|e| e.to_string())?;
+    fs::write(path, content).map_err(|e| e.to_string())
+}
+
+pub fn load_unsigned<P: AsRef<Path>>(path: P) -> Result<UnsignedRequest, String> {
+    let request: UnsignedRequest = load(path)?;
+    check_version(request.version)?;
+    Ok(request)
+}
+
+pub fn load_signed<P: AsRef<Path>>(path: P) -> Result<SignedRequest, String> {
+    let request: SignedRequest = load(path)?;
+    check_version(request.version)?;
+    Ok(request)
+}
+
+fn load<T: serde::de::DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<T, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&content).map_err(|e| format!("Invalid transaction file: {}", e))
+}
+
+fn check_version(version: u32) -> Result<(), String> {
+    if version != OFFLINE_FORMAT_VERSION {
+        return Err(format!("Unsupported transaction file version {}", version));
+    }
+    Ok(())
+}
+
+/// 签名或提交前给操作员核对的交易内容
+pub fn review(from: &Address, tx: &Transaction) -> String {
+    let max_cost = match tx.max_cost() {
+        Some(cost) => cost.to_string(),
+        None => "overflow".to_string(),
+    };
+    let total_value = match tx.total_value() {
+        Some(value) => value.to_string(),
+        None => "overflow".to_string(),
+    };
+    let mut lines = vec![
+        format!("  Chain ID:    {}", tx.chain_id),
+        format!("  From:        {}", from),
+    ];
+    if tx.is_batch() {
+        lines.push(format!("  Outputs:     {}", tx.outputs.len()));
+        for output in &tx.outputs {
+            lines.push(format!("    {} {}", output.to, output.value));
+        }
+    } else {
+        lines.push(format!("  To:          {}", tx.to));
+    }
+    lines.extend(vec![
+        format!("  Value:       {}", total_value),
+        format!("  Nonce:       {}", tx.nonce),
+        format!("  Gas price:   {}", tx.gas_price),
+        format!("  Gas limit:   {}", tx.gas_limit),
+        format!("  Max fee:     {}", tx.fee()),
+        format!("  Max cost:    {}", max_cost),
+    ]);
+    if let Some(lock) = tx.lock {
+        lines.push(format!("  Locked until {}", lock));
+    }
+    if !tx.data.is_empty() {
+        lines.push(format!("  Data:        {} bytes", tx.data.len()));
+    }
+    lines.join("\n")
+}
+
+impl SignedRequest {
+    pub fn review(&self) -> String {
+        format!(
+            "{}\n  Hash:        {}\n  Signature:   {}",
+            review(&self.signed.sender_address(), &self.signed.transaction),
+            self.signed.hash(),
+            if self.signed.verify() { "valid" } else { "INVALID" },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load_signed, load_unsigned, save, MultisigRequest, UnsignedRequest};
+    use crate::types::address::Address;
+    use crate::types::key_pair;
+    use crate::types::multisig::MultisigPolicy;
+    use crate::types::transaction::{Transaction, TX_BASE_GAS, TX_SIGNATURE_GAS};
+    use crate::wallet::wallet::Wallet;
+    use rand::Rng;
+
+    #[test]
+    fn sign_through_files() {
+        let dir = std::env::temp_dir().join(format!("offline-test-{}", rand::thread_rng().gen::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let wallet = Wallet::new(key_pair::random());
+        let from = wallet.get_my_address();
+        let tx = Transaction::new(1, 3, 2, TX_BASE_GAS, Address::from([5u8; 20]), 100, vec![]);
+
+        save(dir.join("unsigned.json"), &UnsignedRequest::new(from, tx)).unwrap();
+        let request = load_unsigned(dir.join("unsigned.json")).unwrap();
+        assert!(request.sign(&Wallet::new(key_pair::random())).is_err());
+        save(dir.join("signed.json"), &request.sign(&wallet).unwrap()).unwrap();
+
+        let signed = load_signed(dir.join("signed.json")).unwrap().signed;
+        assert!(signed.validate(1).is_ok());
+        assert_eq!(signed.sender_address(), from);
+        assert_eq!(signed.transaction.nonce, 3);
+        assert!(load_signed(dir.join("unsigned.json")).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn approvers_sign_separately_and_merge() {
+        let approvers: Vec<Wallet> = (0..3).map(|_| Wallet::new(key_pair::random())).collect();
+        let policy = MultisigPolicy::new(2, approvers.iter().map(|w| w.get_public_key_bytes()).collect()).unwrap();
+        let tx = Transaction::new(1, 0, 1, TX_BASE_GAS + 2 * TX_SIGNATURE_GAS, Address::from([5u8; 20]), 100, vec![]);
+        let unsigned = UnsignedRequest::new(policy.address(), tx);
+
+        let wrong_policy = MultisigPolicy::new(1, vec![approvers[0].get_public_key_bytes()]).unwrap();
+        assert!(MultisigRequest::from_unsigned(&unsigned, wrong_policy).is_err());
+        let mut first = MultisigRequest::from_unsigned(&unsigned, policy.clone()).unwrap();
+        let mut second = first.clone();
+        first.sign(&approvers[0]).unwrap();
+        second.sign(&approvers[2]).unwrap();
+        assert!(first.sign(&Wallet::new(key_pair::random())).is_err());
+        assert!(first.finalize().is_err());
+
+        first.merge(&second).unwrap();
+        let signed = first.finalize().unwrap().signed;
+        assert!(signed.validate(1).is_ok());
+        assert_eq!(signed.sender_address(), policy.address());
+
+        // 签名数超过阈值时只保留阈值个，gas_limit 仍然够用
+        first.sign(&approvers[1]).unwrap();
+        let signed = first.finalize().unwrap().signed;
+        assert_eq!(signed.multisig.as_ref().unwrap().signatures.len(), 2);
+        assert!(signed.validate(1).is_ok());
+
+        // 公钥序号越界的签名被拒绝，而不是在 review 时 panic
+        let mut forged = second.clone();
+        forged.proof.signatures.push((7, vec![0u8; 64]));
+        assert!(first.merge(&forged).is_err());
+        assert!(forged.review().contains("invalid signer index 7"));
+
+        // 无效的签名不能替换已有的有效签名
+        let before = first.proof.signatures.clone();
+        let mut corrupted = second.clone();
+        corrupted.proof.signatures = vec![(before[0].0, vec![0u8; 64])];
+        assert!(first.merge(&corrupted).is_err());
+        assert_eq!(first.proof.signatures, before);
+        first.merge(&second).unwrap();
+        assert_eq!(first.proof.signatures, before);
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use crate::types::address::Address;
+use crate::wallet::wallet::Wallet;
+
+const NO_ACCOUNT: &str = "No account is unlocked, use unlock <name> first";
+
+/// 客户端一次会话中解锁的多个账户，按名称索引，其中一个为当前账户。
+/// 会话可以不解锁任何账户，此时只能使用不需要私钥的命令 (例如 `tx build`、`tx submit`)
+#[derive(Default)]
+pub struct Session {
+    accounts: BTreeMap<String, Wallet>,
+    current: Option<String>,
+}
+
+impl Session {
+    pub fn new(name: String, wallet: Wallet) -> Self {
+        let mut accounts = BTreeMap::new();
+        accounts.insert(name.clone(), wallet);
+        Self { accounts, current: Some(name) }
+    }
+
+    /// 加入新解锁的账户，同名账户已存在时返回错误。第一个解锁的账户成为当前账户
+    pub fn add(&mut self, name: String, wallet: Wallet) -> Result<Address, String> {
+        if self.accounts.contains_key(&name) {
+            return Err(format!("Account {} is already unlocked", name));
+        }
+        let address = wallet.get_my_address();
+        self.current.get_or_insert_with(|| name.clone());
+        self.accounts.insert(name, wallet);
+        Ok(address)
+    }
+
+    pub fn use_account(&mut self, name: &str) -> Result<Address, String> {
+        let wallet = self.accounts.get(name).ok_or(format!("Account {} is not unlocked", name))?;
+        let address = wallet.get_my_address();
+        self.current = Some(name.to_string());
+        Ok(address)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    pub fn current_name(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    pub fn current(&self) -> Result<&Wallet, String> {
+        let name = self.current.as_ref().ok_or(NO_ACCOUNT)?;
+        Ok(&self.accounts[name])
+    }
+
+    /// `name` 为 None 时返回当前账户
+    pub fn get_mut(&mut self, name: Option<&str>) -> Result<&mut Wallet, String> {
+        let name = name.or(self.current.as_deref()).ok_or(NO_ACCOUNT)?;
+        self.accounts.get_mut(name).ok_or(format!("Account {} is not unlocked", name))
+    }
+
+    /// 会话中地址为 `address` 的账户
+    pub fn find(&mut self, address: &Address) -> Option<(&str, &mut Wallet)> {
+        self.accounts.iter_mut()
+            .find(|(_, wallet)| wallet.get_my_address() == *address)
+            .map(|(name, wallet)| (name.as_str(), wallet))
+    }
+
+    pub fn wallets(&self) -> impl Iterator<Item = (&String, &Wallet)> {
+        self.accounts.iter()
+    }
+
+    /// 按名称排序的 (名称, 地址)
+    pub fn addresses(&self) -> Vec<(String, Address)> {
+        self.accounts.iter()
+            .map(|(name, wallet)| (name.clone(), wallet.get_my_address()))
+            .collect()
+    }
+
+    /// 转账目标可以是会话中的账户名，否则按地址解析
+    pub fn resolve_address(&self, target: &str) -> Result<Address, String> {
+        if let Some(wallet) = self.accounts.get(target) {
+            return Ok(wallet.get_my_address());
+        }
+        target.parse().map_err(|e| format!("Unknown account {} ({})", target, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Session;
+    use crate::types::key_pair;
+    use crate::wallet::wallet::Wallet;
+
+    #[test]
+    fn switch_and_resolve_accounts() {
+        let treasury = Wallet::new(key_pair::random());
+        let treasury_address = treasury.get_my_address();
+        let mut session = Session::new("treasury".to_string(), treasury);
+        let ops_address = session.add("ops".to_string(), Wallet::new(key_pair::random())).unwrap();
+        assert!(session.add("ops".to_string(), Wallet::new(key_pair::random())).is_err());
+
+        assert_eq!(session.current().unwrap().get_my_address(), treasury_address);
+        assert_eq!(session.use_account("ops").unwrap(), ops_address);
+        assert_eq!(session.current_name(), Some("ops"));
+        assert!(session.use_account("missing").is_err());
+        assert_eq!(session.current_name(), Some("ops"));
+
+        assert_eq!(session.resolve_address("treasury").unwrap(), treasury_address);
+        assert_eq!(session.resolve_address(&ops_address.to_string()).unwrap(), ops_address);
+        assert!(session.resolve_address("nobody").is_err());
+        let names: Vec<String> = session.addresses().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["ops", "treasury"]);
+    }
+
+    #[test]
+    fn empty_session_needs_unlock() {
+        let mut session = Session::default();
+        assert!(session.current().is_err());
+        assert!(session.get_mut(None).is_err());
+        // 第一个解锁的账户成为当前账户
+        session.add("ops".to_string(), Wallet::new(key_pair::random())).unwrap();
+        assert_eq!(session.current_name(), Some("ops"));
+        assert!(session.get_mut(None).is_ok());
+    }
+}
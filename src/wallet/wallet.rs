@@ -1,14 +1,20 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use ring::signature::KeyPair;
-use crate::types::transaction::{Transaction, SignedTransaction, sign};
+use crate::types::transaction::{Transaction, SignedTransaction, TxOutput, sign};
 use crate::types::address::Address;
-use crate::types::hash::Hashable;
 
 // 别名
 pub type RKeyPair = ring::signature::Ed25519KeyPair;
 
+/// 本地记录的待打包 nonce 不在节点 Mempool 中时最多保留的时间 (交易可能尚未转发到该节点)
+const PENDING_NONCE_TTL: Duration = Duration::from_secs(60);
+
 pub struct Wallet {
     key_pair: Arc<RKeyPair>,
+    /// 本地已提交但尚未确认的交易 nonce 及提交时间
+    pending_nonces: BTreeMap<u64, Instant>,
 }
 
 impl Wallet {
@@ -16,6 +22,7 @@ impl Wallet {
     pub fn new(key_pair: RKeyPair) -> Self {
         Self {
             key_pair: Arc::new(key_pair),
+            pending_nonces: BTreeMap::new(),
         }
     }
 
@@ -27,32 +34,123 @@ impl Wallet {
         Address::from_public_key_bytes(self.key_pair.public_key().as_ref())
     }
 
+    /// 下一笔交易应使用的 nonce
+    /// `account_nonce` 为链上状态中的 nonce (即下一笔待打包交易的 nonce)，
+    /// `mempool_nonces` 为节点 Mempool 中本账户的待打包交易
+    pub fn next_nonce(&mut self, account_nonce: u64, mempool_nonces: &[u64]) -> u64 {
+        self.next_nonce_at(account_nonce, mempool_nonces, Instant::now())
+    }
+
+    fn next_nonce_at(&mut self, account_nonce: u64, mempool_nonces: &[u64], now: Instant) -> u64 {
+        // 已上链的 nonce 不再需要跟踪，已被节点丢弃的交易超时后不再占用 nonce
+        self.pending_nonces = self.pending_nonces.split_off(&account_nonce);
+        self.pending_nonces.retain(|nonce, submitted| {
+            mempool_nonces.contains(nonce) || now.saturating_duration_since(*submitted) < PENDING_NONCE_TTL
+        });
+        let pending: BTreeSet<u64> = self.pending_nonces.keys()
+            .chain(mempool_nonces.iter().filter(|n| **n >= account_nonce))
+            .copied()
+            .collect();
+        first_free_nonce(account_nonce, &pending)
+    }
+
+    /// 交易提交成功后记录其 nonce，连续转账时无需等待确认
+    pub fn mark_pending(&mut self, nonce: u64) {
+        self.pending_nonces.insert(nonce, Instant::now());
+    }
+
     /// 本地构建并签名交易
-    /// 注意：nonce 和 balance 必须由外部（Client）通过查询 API 提供
+    /// 注意：nonce 必须由外部（Client）通过 `next_nonce` 计算
     pub fn create_signed_transaction(
         &self,
+        chain_id: u64, // 目标网络的链 ID，防止交易被重放到其他网络
         receiver: Address,
         amount: u64,
         fee_price: u64,
         fee_limit: u64,
-        nonce: u64, // 必须从网络获取当前的 nonce
+        nonce: u64, // 与执行时账户的 nonce 相等才能被打包
     ) -> SignedTransaction {
         
         let t = Transaction::new(
-            nonce + 1, // 交易 nonce 通常是当前状态 nonce + 1
+            chain_id,
+            nonce,
             fee_price,
             fee_limit,
             receiver,
             amount, 
             vec![]
         );
+        self.sign_transaction(t)
+    }
 
+    /// 构建并签名批量交易，gas_limit 恰好为所需的 gas
+    pub fn create_signed_batch(
+        &self,
+        chain_id: u64,
+        outputs: Vec<TxOutput>,
+        fee_price: u64,
+        nonce: u64,
+    ) -> SignedTransaction {
+        let mut t = Transaction::new_batch(chain_id, nonce, fee_price, 0, outputs);
+        t.gas_limit = t.gas_used();
+        self.sign_transaction(t)
+    }
+
+    /// 签名已构造好的交易 (例如离线签名文件中的交易)
+    pub fn sign_transaction(&self, t: Transaction) -> SignedTransaction {
         let signature = sign(&t, &self.key_pair);
         
         SignedTransaction {
             transaction: t,
             signature: signature.as_ref().to_vec(),
             public_key: self.key_pair.public_key().as_ref().to_vec(),
+            multisig: None,
         }
     }
 }
+
+/// 跳过连续的待打包 nonce，出现空缺时从空缺处继续
+pub fn first_free_nonce(account_nonce: u64, pending: &BTreeSet<u64>) -> u64 {
+    let mut nonce = account_nonce;
+    while pending.contains(&nonce) {
+        nonce += 1;
+    }
+    nonce
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Wallet, PENDING_NONCE_TTL};
+    use crate::types::key_pair;
+    use std::time::Instant;
+
+    #[test]
+    fn next_nonce_skips_pending() {
+        let mut wallet = Wallet::new(key_pair::random());
+        assert_eq!(wallet.next_nonce(0, &[]), 0);
+
+        wallet.mark_pending(0);
+        wallet.mark_pending(1);
+        assert_eq!(wallet.next_nonce(0, &[]), 2);
+        // 节点 Mempool 中还有其他客户端提交的交易
+        assert_eq!(wallet.next_nonce(0, &[2, 3]), 4);
+        // nonce 0..2 已上链，nonce 3 仍在 Mempool 中
+        assert_eq!(wallet.next_nonce(3, &[3]), 4);
+        // 交易被节点丢弃后，从空缺处重新使用
+        assert_eq!(wallet.next_nonce(4, &[]), 4);
+        wallet.mark_pending(5);
+        assert_eq!(wallet.next_nonce(4, &[]), 4);
+    }
+
+    #[test]
+    fn dropped_pending_nonces_expire() {
+        let mut wallet = Wallet::new(key_pair::random());
+        wallet.mark_pending(0);
+        wallet.mark_pending(1);
+        let later = Instant::now() + PENDING_NONCE_TTL;
+        // 节点仍持有 nonce 1 的交易，nonce 0 的交易已被丢弃
+        assert_eq!(wallet.next_nonce_at(0, &[1], later), 0);
+        assert_eq!(wallet.next_nonce_at(0, &[], later), 0);
+        assert!(wallet.pending_nonces.is_empty());
+    }
+}